@@ -1,39 +1,190 @@
-use crate::message_collection::MessageCollection;
-use anyhow::{Context, Result};
-use imap::IMap;
+use crate::body_cache::BodyCache;
+use crate::body_search::BodySearch;
+use crate::clipboard::Clipboard;
+use crate::compose::Compose;
+use crate::config::{self, Config};
+use crate::contacts::ContactBook;
+use crate::graphics::Protocol;
+use crate::keymap::{Action, Keymap};
+use crate::list_search::ListSearch;
+use crate::message_collection::{MessageCollection, Undo};
+use crate::outbox::{Flushed, Outbox, Queued};
+use crate::theme::Theme;
+use crate::unsubscribe::{self, Mailto};
+use anyhow::{bail, Context, Result};
+use imap::{
+    message::{AuthResults, Flag, Message, Priority, Unsubscribe},
+    IMap, SearchQuery, SpecialUse,
+};
 use ratatui::{
     backend::CrosstermBackend,
-    crossterm::event::{self, KeyCode, KeyEventKind, KeyModifiers},
-    layout::{Constraint, Direction, Layout},
+    crossterm::{
+        cursor::MoveTo,
+        event::{
+            self, DisableMouseCapture, EnableMouseCapture, KeyCode, KeyEvent, KeyEventKind,
+            KeyModifiers, MouseButton, MouseEvent, MouseEventKind,
+        },
+        execute,
+    },
+    layout::{Constraint, Direction, Layout, Position, Rect},
     style::{Style, Stylize},
-    text::Text,
-    widgets::{List, Paragraph},
+    text::{Line, Span, Text},
+    widgets::{Block, Borders, List, ListState, Paragraph, Wrap},
     Terminal,
 };
-use std::io::Stdout;
+use smtp::{Refused, SMTP};
+use std::collections::HashSet;
+use std::io::{Stdout, Write};
+use std::ops::ControlFlow;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// how long to wait for input before running the time based work
+const TICK_RATE: Duration = Duration::from_millis(250);
+const REFRESH_INTERVAL: Duration = Duration::from_secs(60);
+// how much `<` and `>` resize the list pane by, in percent of the width
+const LIST_WIDTH_STEP: u16 = 5;
+const MAX_LIST_WIDTH: u16 = 90;
+// messages at least this big show their size in the list, in bytes
+const SHOWN_SIZE: usize = 1024 * 1024;
+// lines the body scrolls per wheel notch
+const MOUSE_SCROLL: u16 = 3;
+// how many actions `u` can undo
+const MAX_UNDO: usize = 10;
+// height of the strip images are drawn in, and how many fit side by side
+const IMAGE_ROWS: u16 = 12;
+const MAX_IMAGES: usize = 3;
+// how long the SMTP connection can sit unused before it's checked with a NOOP
+const SMTP_IDLE: Duration = Duration::from_secs(60);
+// the longest the outbox waits between tries while sending keeps failing
+const MAX_OUTBOX_BACKOFF: Duration = Duration::from_secs(30 * 60);
+
+/// The pane keys like `j` and `k` act on.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Pane {
+    List,
+    Body,
+}
+
+impl Pane {
+    /// The pane to the right, wrapping around.
+    fn next(self) -> Self {
+        return match self {
+            Pane::List => Pane::Body,
+            Pane::Body => Pane::List,
+        };
+    }
+
+    /// The pane to the left, wrapping around.
+    fn prev(self) -> Self {
+        return match self {
+            Pane::List => Pane::Body,
+            Pane::Body => Pane::List,
+        };
+    }
+}
 
 pub struct App {
     terminal: Terminal<CrosstermBackend<Stdout>>,
     messages: MessageCollection,
+    theme: Theme,
+    keymap: Keymap,
+    confirm_quit: bool,
+    /// Waiting on the answer to the quit prompt.
+    quitting: bool,
+    /// Waiting on the answer to the permanent delete prompt.
+    confirm_purge: bool,
+    /// Waiting on the answer to the mark all read prompt.
+    confirm_mark_all: bool,
+    /// The list to leave and who it tells, waiting on the unsubscribe prompt.
+    confirm_unsubscribe: Option<(Unsubscribe, String)>,
+    /// Opening a bigger message asks first, in bytes.
+    confirm_download_over: Option<usize>,
+    /// The size of the message waiting on the download prompt.
+    confirm_download: Option<usize>,
+    /// The most recent last.
+    undo: Vec<Undo>,
     hovered_message: usize,
-    selected_message: Option<usize>,
+    /// Scrolls the list so the hovered message stays in view.
+    list_state: ListState,
+    /// Ids of the messages marked with space, bulk actions apply to these
+    /// instead of the hovered one.
+    selected: HashSet<usize>,
+    /// The headers and body of the open message.
     selected_body: Option<Box<str>>,
-    left: bool,
+    /// Index on the page of the open message.
+    open_message: Option<usize>,
+    /// Whether the raw source is shown instead of the decoded text.
+    showing_source: bool,
+    /// Show every header of the open message, not just From and Subject.
+    headers_expanded: bool,
+    body_scroll: u16,
+    search: BodySearch,
+    list_search: ListSearch,
+    /// Only list unread messages.
+    unread_only: bool,
+    /// Whether mouse events are captured.
+    mouse: bool,
+    clipboard: Clipboard,
+    /// Whether the open message's images are fetched.
+    inline_images: bool,
+    /// Whether new mail shows a desktop notification.
+    notify: bool,
+    /// How images are drawn, none if the terminal can't.
+    graphics: Option<Protocol>,
+    /// The drawable images of the open message.
+    images: Vec<Box<[u8]>>,
+    /// Where `images` were last drawn.
+    drawn_images: Option<Rect>,
+    compose: Option<Compose>,
+    /// The hovered row while the address book is shown.
+    contacts_view: Option<usize>,
+    outbox: Outbox,
+    /// Connected when the outbox is first flushed, dropped if the
+    /// connection fails rather than the server refusing a message.
+    smtp: Option<SMTP>,
+    /// The `From:` of composed messages.
+    from: String,
+    /// Added under `-- ` to every new message, from the config.
+    signature: Option<Box<str>>,
+    /// Whether replies go above the quote.
+    top_post: bool,
+    /// Whose `Authentication-Results:` are believed.
+    authserv_ids: Vec<String>,
+    smtp_last_used: Instant,
+    /// When `refresh` next tries the outbox, pushed back while it fails.
+    outbox_retry_at: Instant,
+    /// How much the next failure pushes `outbox_retry_at` back, doubling.
+    outbox_backoff: Duration,
+    /// Count typed before a motion, e.g. the `42` of `42G`.
+    pending_count: Option<usize>,
+    focus: Pane,
+    /// Width of the list pane in percent, 0 hides it.
+    list_width: u16,
+    /// One off message for the status bar, cleared on the next key press.
+    status: Option<Box<str>>,
+    new_messages: usize,
+    last_refresh: Instant,
 }
 
 impl Drop for App {
     fn drop(&mut self) {
+        if self.mouse {
+            let _ = execute!(std::io::stdout(), DisableMouseCapture);
+        }
         ratatui::restore();
+        let _ = self.save_contacts();
     }
 }
 
 impl App {
     pub fn new() -> Result<Self> {
-        let mut imap = IMap::connect("imap.gmail.com", 993)?;
+        let config = Config::load()?;
+        let theme = Theme::from_config(&config.theme)?;
+        let keymap = Keymap::from_config(&config.keys)?;
 
+        let mut imap = connect_imap()?;
         let username = &std::env::var("EMAIL_USERNAME")?;
-        let password = &std::env::var("EMAIL_PASSWORD")?;
-        imap.login(username, password)?;
 
         let inbox = imap
             .list_inbox()?
@@ -44,129 +195,1574 @@ impl App {
         imap.select_inbox(inbox)?;
 
         let terminal = ratatui::init();
-        let height = terminal.size()?.height;
+        if config.mouse {
+            execute!(std::io::stdout(), EnableMouseCapture)?;
+        }
+        // leave a line for the status bar
+        let height = terminal.size()?.height.saturating_sub(1);
 
-        let messages = MessageCollection::new(imap, height.into());
+        let gmail = imap.has_capability("X-GM-EXT-1")?;
+        let mut messages = MessageCollection::new(imap, height.into());
+        messages.gmail = gmail;
+        messages.contacts = ContactBook::load(&contacts_path()?)?;
+        messages.bodies = BodyCache::new(config.body_cache);
+        let outbox = Outbox::open(&Outbox::default_dir().context("No data directory")?)?;
 
-        return Ok(Self {
+        let mut app = Self {
             terminal,
             messages,
+            theme,
+            keymap,
+            confirm_quit: config.confirm_quit,
+            quitting: false,
+            confirm_purge: false,
+            confirm_mark_all: false,
+            confirm_unsubscribe: None,
+            confirm_download_over: match config.confirm_download_mb {
+                0 => None,
+                mb => Some(mb * 1024 * 1024),
+            },
+            confirm_download: None,
+            undo: vec![],
             hovered_message: 0,
-            selected_message: None,
+            list_state: ListState::default(),
+            selected: HashSet::new(),
             selected_body: None,
-            left: true,
-        });
+            open_message: None,
+            showing_source: false,
+            headers_expanded: false,
+            body_scroll: 0,
+            search: BodySearch::default(),
+            list_search: ListSearch::default(),
+            unread_only: false,
+            mouse: config.mouse,
+            clipboard: Clipboard::new(),
+            inline_images: config.inline_images,
+            notify: config.notify,
+            graphics: config.inline_images.then(Protocol::detect).flatten(),
+            images: vec![],
+            drawn_images: None,
+            compose: None,
+            contacts_view: None,
+            outbox,
+            smtp: None,
+            from: smtp::mailbox(config.display_name.as_deref(), username),
+            signature: config.signature.map(Box::from),
+            top_post: config.top_post,
+            authserv_ids: config.authserv_ids,
+            smtp_last_used: Instant::now(),
+            outbox_retry_at: Instant::now(),
+            outbox_backoff: REFRESH_INTERVAL,
+            pending_count: None,
+            focus: Pane::List,
+            list_width: 30,
+            status: None,
+            new_messages: 0,
+            last_refresh: Instant::now(),
+        };
+        if config.unread_only {
+            app.toggle_unread_only()?;
+        }
+        return Ok(app);
     }
 
     pub fn render(&mut self) -> Result<bool> {
         let mut exit = false;
+        let alerts = self.messages.take_alerts();
+        if !alerts.is_empty() {
+            self.status = Some(format!("Server alert: {}", alerts.join("; ")).into());
+        }
+        if self.messages.take_renumbered() {
+            // flag changes are undone and selections kept by id, which now
+            // may be another message
+            self.undo.retain(|x| matches!(x, Undo::Trash { .. }));
+            self.selected.clear();
+        }
+
+        let size = self.terminal.size()?;
+        let image_area = self.image_area(Rect::new(0, 0, size.width, size.height));
+        if self.drawn_images.is_some() && self.drawn_images != image_area {
+            self.clear_images()?;
+        }
 
         let draw_success = self.terminal.draw(|frame| {
-            let layout = Layout::default()
-                .direction(Direction::Horizontal)
-                .constraints(vec![Constraint::Percentage(30), Constraint::Fill(1)])
-                .split(frame.area());
+            let [list_area, body_area, status_bar] = panes(frame.area(), self.list_width);
+            let body_area = match image_area {
+                Some(strip) => {
+                    frame.render_widget(
+                        Block::default()
+                            .borders(Borders::TOP)
+                            .border_style(self.theme.border()),
+                        strip,
+                    );
+                    Rect {
+                        height: body_area.height - strip.height,
+                        ..body_area
+                    }
+                }
+                None => body_area,
+            };
 
             let page_size = self.messages.page_size;
-            let current_page_idx = self.messages.current_page;
+            let offset = page_size * self.messages.current_page;
+            let outgoing = self.messages.outgoing();
             let current_page = self.messages.get_current_page().unwrap_or(&[]);
-
-            let list = List::new(current_page.iter().enumerate().map(|(i, x)| {
-                let style = if i == self.hovered_message {
-                    Style::default().on_blue()
+            let list = message_list(
+                current_page,
+                self.hovered_message,
+                &self.selected,
+                offset,
+                outgoing,
+                &self.theme,
+            );
+            self.list_state.select(Some(self.hovered_message));
+            frame.render_stateful_widget(list, list_area, &mut self.list_state);
+            frame.render_widget(
+                match (&self.compose, self.contacts_view, &self.selected_body) {
+                    (Some(compose), _, _) => Paragraph::new(compose.render(&self.theme)),
+                    (None, Some(hovered), _) => Paragraph::new(render_contacts(
+                        &self.messages.contacts,
+                        &self.theme,
+                        hovered,
+                        page_size,
+                    )),
+                    (None, None, Some(body)) => {
+                        // quoted-printable soft breaks are already joined by the decoder
+                        Paragraph::new(self.search.highlight(body))
+                            .wrap(Wrap { trim: false })
+                            .scroll((self.body_scroll, 0))
+                    }
+                    (None, None, None) => Paragraph::new("Select an Email to view it here"),
+                },
+                body_area,
+            );
+            if self.confirm_purge {
+                let prompt = match self.selected.len() {
+                    0 => "Permanently delete this message? It can't be undone. y/n".to_owned(),
+                    n => format!("Permanently delete {} messages? It can't be undone. y/n", n),
+                };
+                frame.render_widget(
+                    Paragraph::new(prompt).style(self.theme.status_bar()),
+                    status_bar,
+                );
+            } else if self.confirm_mark_all {
+                frame.render_widget(
+                    Paragraph::new("Mark every message in this folder read? y/n")
+                        .style(self.theme.status_bar()),
+                    status_bar,
+                );
+            } else if let Some((_, target)) = &self.confirm_unsubscribe {
+                let prompt = format!("Unsubscribe via {}? y/n", target);
+                frame.render_widget(
+                    Paragraph::new(prompt).style(self.theme.status_bar()),
+                    status_bar,
+                );
+            } else if let Some(size) = self.confirm_download {
+                let prompt = format!("This message is {}, download it? y/n", human_size(size));
+                frame.render_widget(
+                    Paragraph::new(prompt).style(self.theme.status_bar()),
+                    status_bar,
+                );
+            } else if self.quitting {
+                let prompt = if self.compose.is_some() {
+                    "Save the draft before quitting? y/n, Esc cancels"
                 } else {
-                    Style::default()
+                    "Quit? y/n"
                 };
-
-                return Text::styled(
-                    format!("{}. {} ", i + (page_size * current_page_idx), x.subject),
-                    style,
+                frame.render_widget(
+                    Paragraph::new(prompt).style(self.theme.status_bar()),
+                    status_bar,
+                );
+            } else if self.search.typing || !self.search.query.is_empty() {
+                frame.render_widget(
+                    Paragraph::new(self.search.status()).style(self.theme.status_bar()),
+                    status_bar,
+                );
+            } else if self.list_search.typing || self.list_search.results.is_some() {
+                frame.render_widget(
+                    Paragraph::new(self.list_search.status()).style(self.theme.status_bar()),
+                    status_bar,
+                );
+            } else if let Some(status) = &self.status {
+                frame.render_widget(
+                    Paragraph::new(&**status).style(self.theme.status_bar()),
+                    status_bar,
+                );
+            } else if self.new_messages > 0 || self.outbox.pending() > 0 || self.unread_only {
+                let mut counts = vec![];
+                if self.unread_only {
+                    counts.push("unread only, U shows all".to_owned());
+                }
+                if self.new_messages > 0 {
+                    counts.push(format!("{} new messages", self.new_messages));
+                }
+                if self.outbox.pending() > 0 {
+                    counts.push(format!("{} in outbox", self.outbox.pending()));
+                }
+                frame.render_widget(
+                    Paragraph::new(counts.join(", ")).style(self.theme.status_bar()),
+                    status_bar,
                 );
-            }));
+            }
+            exit = false;
+        });
 
-            let selected_meta = self.selected_message.map(|i| &current_page[i]);
+        draw_success?;
+        if self.drawn_images != image_area {
+            if let Some(strip) = image_area {
+                self.draw_images(strip)?;
+            }
+            self.drawn_images = image_area;
+        }
+        if exit {
+            return Ok(true);
+        }
+        return self.handle_events();
+    }
 
-            frame.render_widget(list, layout[0]);
-            frame.render_widget(
-                match (selected_meta, &self.selected_body) {
-                    (Some(selected_meta), Some(body)) => {
-                        Paragraph::new(format!("{}\n{}", selected_meta, body))
-                    }
-                    _ => Paragraph::new("Select an Email to view it here"),
+    /// Where the open message's images go, the bottom of the body pane, if
+    /// there are any to draw and the pane has room.
+    fn image_area(&self, area: Rect) -> Option<Rect> {
+        let showing_body = self.compose.is_none()
+            && self.contacts_view.is_none()
+            && self.selected_body.is_some()
+            && !self.showing_source;
+        if self.graphics.is_none() || self.images.is_empty() || !showing_body {
+            return None;
+        }
+        let [_, body, _] = panes(area, self.list_width);
+        if body.height < IMAGE_ROWS * 2 {
+            return None;
+        }
+        return Some(Rect {
+            y: body.bottom() - IMAGE_ROWS,
+            height: IMAGE_ROWS,
+            ..body
+        });
+    }
+
+    /// Draws `images` side by side under the top border of `strip`. They
+    /// stay until cleared since the cells under them are blank to ratatui.
+    fn draw_images(&mut self, strip: Rect) -> Result<()> {
+        let protocol = self.graphics.context("Terminal can't draw images")?;
+        let width = strip.width / self.images.len() as u16;
+        let mut stdout = std::io::stdout();
+        for (i, image) in self.images.iter().enumerate() {
+            let x = strip.x + width * i as u16;
+            execute!(stdout, MoveTo(x, strip.y + 1))?;
+            stdout.write_all(protocol.draw(image, width, strip.height - 1).as_bytes())?;
+        }
+        stdout.flush()?;
+        return Ok(());
+    }
+
+    fn clear_images(&mut self) -> Result<()> {
+        if let Some(protocol) = self.graphics {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(protocol.clear().as_bytes())?;
+            stdout.flush()?;
+            if protocol.clear().is_empty() {
+                // the next draw repaints every cell, over the images
+                self.terminal.clear()?;
+            }
+        }
+        self.drawn_images = None;
+        return Ok(());
+    }
+
+    /// Fetches the images of the message at `index`, listing them at the end
+    /// of `body`. Only the ones the terminal can draw are downloaded.
+    fn load_images(&mut self, index: usize, body: &mut String) -> Result<()> {
+        for (section, meta) in self.messages.images(index)? {
+            let name = if meta.name.is_empty() {
+                &*section
+            } else {
+                &*meta.name
+            };
+            body.push_str(&format!("\n[image: {}]", name));
+            let drawable = self.graphics.is_some_and(|x| x.supports(&meta.file_type));
+            if drawable && self.images.len() < MAX_IMAGES {
+                let image = self.messages.fetch_image(index, &section, &meta)?;
+                self.images.push(image);
+            }
+        }
+        return Ok(());
+    }
+
+    /// Waits up to a tick for input so time based work runs even when idle.
+    fn handle_events(&mut self) -> Result<bool> {
+        let exit = if event::poll(TICK_RATE)? {
+            self.handle_event(event::read()?)?
+        } else {
+            false
+        };
+        self.on_tick();
+        return Ok(exit);
+    }
+
+    /// Runs once per loop whether or not there was input.
+    fn on_tick(&mut self) {
+        // a failed refresh is retried on the next interval
+        let _ = self.refresh();
+    }
+
+    fn save_draft(&mut self) -> Result<()> {
+        if let Some(compose) = &mut self.compose {
+            let message = compose.to_rfc822(&self.from);
+            compose.draft = self.messages.save_draft(&message, compose.draft)?;
+        }
+        return Ok(());
+    }
+
+    /// Queues the message being written and tries to send it right away.
+    fn send_compose(&mut self) -> Result<Flushed> {
+        let compose = self.compose.as_ref().context("Nothing to send")?;
+        let recipients = compose.recipients();
+        if recipients.is_empty() {
+            bail!("No recipients");
+        }
+        self.outbox.enqueue(Queued {
+            recipients,
+            message: compose.to_rfc822(&self.from),
+        })?;
+        self.compose = None;
+        return self.flush_outbox();
+    }
+
+    /// Sends everything in the outbox, connecting first if needed.
+    fn flush_outbox(&mut self) -> Result<Flushed> {
+        if self.outbox.pending() == 0 {
+            return Ok(Flushed::default());
+        }
+        // the server may have dropped a connection that sat idle
+        if self.smtp_last_used.elapsed() > SMTP_IDLE {
+            if let Some(smtp) = &mut self.smtp {
+                if smtp.noop().is_err() {
+                    self.smtp = None;
+                }
+            }
+        }
+        let smtp = match &mut self.smtp {
+            Some(smtp) => smtp,
+            None => {
+                let mut smtp = SMTP::connect("smtp.gmail.com", 465)?;
+                if let Some(trace) = config::wire_trace()? {
+                    smtp.set_trace(trace);
+                }
+                smtp.login(
+                    std::env::var("EMAIL_USERNAME")?.into(),
+                    &std::env::var("EMAIL_PASSWORD")?,
+                )?;
+                self.smtp.insert(smtp)
+            }
+        };
+        let flushed = self.outbox.flush(smtp);
+        self.smtp_last_used = Instant::now();
+        if let Err(e) = &flushed {
+            // a refusal leaves the session usable, anything else may not
+            if e.downcast_ref::<Refused>().is_none() {
+                self.smtp = None;
+            }
+        }
+        return flushed;
+    }
+
+    fn save_contacts(&self) -> Result<()> {
+        return self.messages.contacts.save(&contacts_path()?);
+    }
+
+    /// Keys in the address book, j/k move, Enter writes to the contact, d
+    /// removes it and Esc or a closes the book.
+    fn handle_contacts_key(&mut self, code: KeyCode, hovered: usize) -> Result<()> {
+        let contacts = self.messages.contacts.list();
+        let email = contacts.get(hovered).map(|x| x.email.trim().to_owned());
+        let last = contacts.len().saturating_sub(1);
+        match code {
+            KeyCode::Char('j') => self.contacts_view = Some((hovered + 1).min(last)),
+            KeyCode::Char('k') => self.contacts_view = Some(hovered.saturating_sub(1)),
+            KeyCode::Enter => {
+                if let Some(email) = email {
+                    self.contacts_view = None;
+                    self.start_compose(Compose::to(&email));
+                }
+            }
+            KeyCode::Char('d') => {
+                if let Some(email) = email {
+                    self.messages.contacts.remove(&email);
+                    self.contacts_view = Some(hovered.min(last.saturating_sub(1)));
+                    self.save_contacts()?;
+                }
+            }
+            KeyCode::Esc | KeyCode::Char('a') => self.contacts_view = None,
+            _ => {}
+        }
+        return Ok(());
+    }
+
+    /// Trashes the hovered message, or deletes it for good if `purge`.
+    /// Returns what happened for the status bar. When there's nowhere to
+    /// trash it to, asks whether to delete it for good instead.
+    fn delete(&mut self, purge: bool) -> Result<String> {
+        if !self.selected.is_empty() {
+            return self.delete_selected(purge);
+        }
+        let status = if purge {
+            self.messages.purge(self.hovered_message)?;
+            "Deleted permanently"
+        } else {
+            match self.messages.trash(self.hovered_message)? {
+                (true, Some(undo)) => {
+                    self.push_undo(undo);
+                    "Moved to Trash, u undoes"
+                }
+                (true, None) => "Moved to Trash",
+                (false, _) => {
+                    self.confirm_purge = true;
+                    return Ok("No Trash folder".to_owned());
+                }
+            }
+        };
+        if self.open_message == Some(self.hovered_message) {
+            self.selected_body = None;
+            self.open_message = None;
+        }
+        let last = self.messages.get_current_page()?.len().saturating_sub(1);
+        self.hovered_message = self.hovered_message.min(last);
+        return Ok(status.to_owned());
+    }
+
+    /// Deletes every selected message with one command, without undo. Like
+    /// `delete` it asks first when there's no Trash.
+    fn delete_selected(&mut self, purge: bool) -> Result<String> {
+        let ids = self.selected.iter().copied().collect::<Vec<_>>();
+        let status = if purge {
+            self.messages.purge_ids(&ids)?;
+            format!("Deleted {} messages permanently", ids.len())
+        } else if self.messages.trash_ids(&ids)? {
+            format!("Moved {} messages to Trash", ids.len())
+        } else {
+            // the selection stays for the prompt
+            self.confirm_purge = true;
+            return Ok("No Trash folder".to_owned());
+        };
+        self.selected.clear();
+        // the open message may be gone and the ids have shifted
+        self.selected_body = None;
+        self.open_message = None;
+        let last = self.messages.get_current_page()?.len().saturating_sub(1);
+        self.hovered_message = self.hovered_message.min(last);
+        return Ok(status);
+    }
+
+    /// Marks the selected messages read, or the hovered one.
+    fn mark_read(&mut self) -> Result<String> {
+        let ids = match self.selected.is_empty() {
+            true => {
+                let page = self.messages.get_current_page()?;
+                let message = page
+                    .get(self.hovered_message)
+                    .context("No message hovered")?;
+                vec![message.id]
+            }
+            false => self.selected.drain().collect(),
+        };
+        if let Some(undo) = self.messages.mark_read_ids(&ids)? {
+            self.push_undo(undo);
+        }
+        return Ok(format!("Marked {} read", ids.len()));
+    }
+
+    /// Adds the hovered message to the selection, or takes it out.
+    fn toggle_selected(&mut self) -> Result<()> {
+        let page = self.messages.get_current_page()?;
+        let id = page
+            .get(self.hovered_message)
+            .context("No message hovered")?
+            .id;
+        if !self.selected.remove(&id) {
+            self.selected.insert(id);
+        }
+        return Ok(());
+    }
+
+    fn push_undo(&mut self, undo: Undo) {
+        if self.undo.len() == MAX_UNDO {
+            self.undo.remove(0);
+        }
+        self.undo.push(undo);
+    }
+
+    fn undo(&mut self) -> Result<String> {
+        let undo = self.undo.pop().context("Nothing to undo")?;
+        let restores = match &undo {
+            Undo::Trash { folder, .. } => Some(*folder),
+            Undo::Flag { .. } => None,
+        };
+        let shown = self.messages.folder;
+        let status = self.messages.undo(undo)?;
+        if let Some(folder) = restores {
+            // the ids changed, start over from the top
+            self.hovered_message = 0;
+            self.selected.clear();
+            self.selected_body = None;
+            self.open_message = None;
+            // the filters only carry over if it's still the folder they're on
+            if folder != shown {
+                self.unread_only = false;
+                self.list_search = ListSearch::default();
+            }
+            self.refilter()?;
+        }
+        return Ok(status);
+    }
+
+    /// Copies the open message as shown, or the hovered sender's address.
+    fn copy(&mut self, address: bool) -> Result<String> {
+        let (text, what) = if address {
+            let page = self.messages.get_current_page()?;
+            let message = page
+                .get(self.hovered_message)
+                .context("No message hovered")?;
+            let from = message.from.first().context("Message has no sender")?;
+            (from.email.to_string(), "address")
+        } else {
+            let body = self.selected_body.as_deref().context("No message open")?;
+            (body.to_owned(), "message")
+        };
+        self.clipboard.copy(&text)?;
+        return Ok(format!("Copied {} to clipboard", what));
+    }
+
+    /// Asks before leaving the hovered message's mailing list, unless all
+    /// there is to do is copy the link.
+    fn ask_unsubscribe(&mut self) -> Result<Option<String>> {
+        let page = self.messages.get_current_page()?;
+        let message = page
+            .get(self.hovered_message)
+            .context("No message hovered")?;
+        let unsubscribe = message
+            .unsubscribe
+            .clone()
+            .context("Message has no unsubscribe link")?;
+        return match unsubscribe::target(&unsubscribe)? {
+            Some(target) => {
+                self.confirm_unsubscribe = Some((unsubscribe, target));
+                Ok(None)
+            }
+            None => self.unsubscribe(unsubscribe).map(Some),
+        };
+    }
+
+    /// Leaves a mailing list: one-click if it allows, otherwise by mail,
+    /// otherwise the link is copied to open by hand.
+    fn unsubscribe(&mut self, unsubscribe: Unsubscribe) -> Result<String> {
+        if let (true, Some(url)) = (unsubscribe.one_click, &unsubscribe.https) {
+            unsubscribe::one_click(url)?;
+            return Ok("Unsubscribed".to_owned());
+        }
+        if let Some(uri) = &unsubscribe.mailto {
+            let mailto: Mailto = uri.parse()?;
+            self.outbox.enqueue(Queued {
+                recipients: vec![mailto.to.clone()],
+                message: smtp::format_message(
+                    &self.from,
+                    &[&mailto.to],
+                    None,
+                    &mailto.subject,
+                    &mailto.body,
+                    false,
+                ),
+            })?;
+            if let Some(problems) = self.flush_outbox()?.problems() {
+                bail!(problems);
+            }
+            return Ok(format!("Unsubscribe request sent to {}", mailto.to));
+        }
+        let url = unsubscribe
+            .https
+            .context("Message has no unsubscribe link")?;
+        self.clipboard.copy(&url)?;
+        return Ok("Unsubscribe link copied to clipboard".to_owned());
+    }
+
+    /// Shows the Drafts folder, or goes back to the INBOX.
+    fn toggle_drafts(&mut self) -> Result<()> {
+        let folder = match self.messages.folder {
+            Some(SpecialUse::Drafts) => None,
+            _ => Some(SpecialUse::Drafts),
+        };
+        self.messages.open_folder(folder)?;
+        self.selected.clear();
+        self.list_search = ListSearch::default();
+        self.selected_body = None;
+        self.open_message = None;
+        return self.refilter();
+    }
+
+    fn open_draft(&mut self) -> Result<()> {
+        let body = self.messages.get_body(self.hovered_message)?;
+        let draft = self.messages.draft_uid(self.hovered_message)?;
+        let message = &self.messages.get_current_page()?[self.hovered_message];
+        let mut compose = Compose::from_message(message, &body);
+        compose.draft = draft;
+        self.compose = Some(compose);
+        return Ok(());
+    }
+
+    /// Starts a reply to the hovered message, quoting its body.
+    fn reply(&mut self) -> Result<()> {
+        if self
+            .messages
+            .get_current_page()?
+            .get(self.hovered_message)
+            .is_none()
+        {
+            return Ok(());
+        }
+        let body = self.messages.get_body(self.hovered_message)?;
+        let message = &self.messages.get_current_page()?[self.hovered_message];
+        let reply = Compose::reply(message, &body, self.top_post);
+        self.start_compose(reply);
+        return Ok(());
+    }
+
+    /// Opens `compose` for writing, signed if there's a signature.
+    fn start_compose(&mut self, mut compose: Compose) {
+        if let Some(signature) = &self.signature {
+            compose.sign(signature);
+        }
+        self.compose = Some(compose);
+    }
+
+    /// Answers the quit prompt, returns whether to exit.
+    fn handle_quit_key(&mut self, code: KeyCode) -> bool {
+        self.quitting = false;
+        if self.compose.is_none() {
+            return matches!(code, KeyCode::Char('y' | 'Y'));
+        }
+        return match code {
+            KeyCode::Char('y' | 'Y') => match self.save_draft() {
+                Ok(()) => true,
+                Err(e) => {
+                    self.status = Some(format!("Failed to save draft: {}", e).into());
+                    false
+                }
+            },
+            KeyCode::Char('n' | 'N') => true,
+            _ => false,
+        };
+    }
+
+    /// Keys while composing, Ctrl-s saves a draft, Ctrl-x sends and Esc
+    /// closes it.
+    fn handle_compose_key(&mut self, key: KeyEvent) {
+        let ctrl = key.modifiers.intersects(KeyModifiers::CONTROL);
+        if ctrl && key.code == KeyCode::Char('s') {
+            self.status = Some(match self.save_draft() {
+                Ok(()) => "Draft saved".into(),
+                Err(e) => format!("Failed to save draft: {}", e).into(),
+            });
+        } else if ctrl && key.code == KeyCode::Char('x') {
+            self.status = Some(match self.send_compose() {
+                Ok(flushed) => flushed.problems().unwrap_or("Sent".to_owned()).into(),
+                Err(e) if self.compose.is_none() => {
+                    format!("Queued in the outbox, send failed: {}", e).into()
+                }
+                Err(e) => format!("Failed to send: {}", e).into(),
+            });
+        } else if key.code == KeyCode::Esc {
+            self.compose = None;
+        } else if let Some(compose) = &mut self.compose {
+            compose.handle_key(key);
+            compose.update_suggestions(&self.messages.contacts);
+        }
+    }
+
+    /// Clicking a message hovers it and focuses the list, clicking the body
+    /// focuses it and the wheel scrolls it.
+    fn handle_mouse(&mut self, mouse: MouseEvent) -> Result<()> {
+        let size = self.terminal.size()?;
+        let [list_area, body_area, _] =
+            panes(Rect::new(0, 0, size.width, size.height), self.list_width);
+        let position = Position::new(mouse.column, mouse.row);
+        match mouse.kind {
+            // the list can't be picked from while composing or in contacts
+            MouseEventKind::Down(MouseButton::Left)
+                if list_area.contains(position)
+                    && self.compose.is_none()
+                    && self.contacts_view.is_none() =>
+            {
+                self.focus = Pane::List;
+                // the list scrolls to keep the hovered message in view
+                let row = self.list_state.offset() + usize::from(mouse.row - list_area.y);
+                if row < self.messages.get_current_page()?.len() {
+                    self.hovered_message = row;
+                }
+            }
+            MouseEventKind::Down(MouseButton::Left) if body_area.contains(position) => {
+                self.focus = Pane::Body;
+            }
+            MouseEventKind::ScrollDown if body_area.contains(position) => {
+                self.body_scroll = self.body_scroll.saturating_add(MOUSE_SCROLL);
+            }
+            MouseEventKind::ScrollUp if body_area.contains(position) => {
+                self.body_scroll = self.body_scroll.saturating_sub(MOUSE_SCROLL);
+            }
+            _ => {}
+        }
+        return Ok(());
+    }
+
+    /// Switches the open message between its decoded text and raw source.
+    fn toggle_source(&mut self) -> Result<()> {
+        let index = self.open_message.unwrap_or(self.hovered_message);
+        if self.showing_source {
+            return self.put_body(index);
+        }
+        self.selected_body = Some(self.messages.get_source(index)?);
+        self.open_message = Some(index);
+        self.showing_source = true;
+        self.body_scroll = 0;
+        self.search = BodySearch::default();
+        return Ok(());
+    }
+
+    /// Switches between the brief and full headers, redrawing the open
+    /// message with them.
+    fn toggle_headers(&mut self) -> Result<()> {
+        self.headers_expanded = !self.headers_expanded;
+        if let (Some(index), false) = (self.open_message, self.showing_source) {
+            if self.compose.is_none() && self.selected_body.is_some() {
+                let scroll = self.body_scroll;
+                self.put_body(index)?;
+                self.body_scroll = scroll;
+            }
+        }
+        return Ok(());
+    }
+
+    /// Opens the message at `index`, drawing it as it downloads so a large
+    /// one shows up before it's complete.
+    fn put_body(&mut self, index: usize) -> Result<()> {
+        let page = self.messages.get_current_page()?.to_vec();
+        let message = page.get(index).context("No message selected")?;
+        let mut body = match self.headers_expanded {
+            true => format!("{}", message),
+            false => message.brief(),
+        };
+        let badge = (message.auth.as_ref()).and_then(|x| auth_badge(x, &self.authserv_ids));
+        if let Some(badge) = badge {
+            body.push_str(&badge);
+            body.push('\n');
+        }
+        body.push('\n');
+        let offset = self.messages.page_size * self.messages.current_page;
+        let (terminal, theme, selected) = (&mut self.terminal, &self.theme, &self.selected);
+        let (list_width, hovered) = (self.list_width, self.hovered_message);
+        let list_state = &mut self.list_state;
+        let outgoing = self.messages.outgoing();
+        let flow = self
+            .messages
+            .get_body_streaming(index, |text, read, total| {
+                body.push_str(text);
+                if read == total {
+                    return ControlFlow::Continue(());
+                }
+                let list = message_list(&page, hovered, selected, offset, outgoing, theme);
+                let loading = format!("Loading message, {}%, Esc cancels", read * 100 / total);
+                draw_busy(
+                    terminal, list_width, list, list_state, &body, &loading, theme,
+                );
+                return match cancel_pressed() {
+                    true => ControlFlow::Break(()),
+                    false => ControlFlow::Continue(()),
+                };
+            })?;
+        if flow.is_break() {
+            // the old connection is stuck partway through the body
+            self.status = Some(
+                match connect_imap().and_then(|x| self.messages.reconnect(x)) {
+                    Ok(()) => "Cancelled".into(),
+                    Err(e) => format!("Cancelled, failed to reconnect, will retry: {}", e).into(),
                 },
-                layout[1],
             );
-            exit = false;
-        });
+            return Ok(());
+        }
+        self.images.clear();
+        if self.inline_images {
+            // the text is still worth showing without them
+            let _ = self.load_images(index, &mut body);
+        }
+        self.selected_body = Some(body.into());
+        self.open_message = Some(index);
+        self.showing_source = false;
+        self.body_scroll = 0;
+        self.search = BodySearch::default();
+        return Ok(());
+    }
+
+    /// Hovers the next message down, onto the next page from the bottom.
+    fn hover_next(&mut self) {
+        let next = self.messages.current_page * self.messages.page_size + self.hovered_message + 1;
+        // nothing past the last message
+        if next < self.messages.total() {
+            if self.hovered_message < self.messages.page_size - 1 {
+                self.hovered_message += 1;
+            } else if self.messages.next_page() {
+                self.hovered_message = 0;
+            }
+        }
+    }
+
+    /// Hovers the next message up, onto the previous page from the top.
+    fn hover_prev(&mut self) {
+        if self.hovered_message > 0 {
+            self.hovered_message -= 1;
+        } else if self.messages.current_page > 0 {
+            self.messages.prev_page();
+            self.hovered_message = self.messages.page_size - 1;
+        }
+    }
+
+    /// Hovers the message at `index` counting from the newest.
+    fn jump_to(&mut self, index: usize) {
+        self.hovered_message = self.messages.jump_to(index);
+    }
+
+    /// Scrolls so the current search match is in view, with a few lines of
+    /// context above it.
+    fn scroll_to_match(&mut self) {
+        if let Some(line) = self.search.current_line() {
+            self.body_scroll = line.saturating_sub(3) as u16;
+        }
+    }
+
+    fn handle_search_key(&mut self, code: KeyCode) {
+        match code {
+            KeyCode::Char(c) => self.search.query.push(c),
+            KeyCode::Backspace => {
+                self.search.query.pop();
+            }
+            KeyCode::Enter => {
+                self.search
+                    .find(self.selected_body.as_deref().unwrap_or(""));
+                self.scroll_to_match();
+            }
+            KeyCode::Esc => self.search = BodySearch::default(),
+            _ => {}
+        }
+    }
+
+    /// Keys while typing a list search, control combinations aren't typed.
+    fn handle_list_search_key(&mut self, key: KeyEvent) -> Result<()> {
+        let ctrl = key.modifiers.intersects(KeyModifiers::CONTROL);
+        match key.code {
+            KeyCode::Char(c) if !ctrl => self.list_search.query.push(c),
+            KeyCode::Backspace => {
+                self.list_search.query.pop();
+            }
+            KeyCode::Enter => {
+                self.list_search.typing = false;
+                self.list_search.results = Some(0);
+                self.refilter()?;
+            }
+            KeyCode::Esc => self.clear_list_search()?,
+            _ => {}
+        }
+        return Ok(());
+    }
+
+    /// Goes back to the whole mailbox, or its unread messages.
+    fn clear_list_search(&mut self) -> Result<()> {
+        let applied = self.list_search.results.is_some();
+        self.list_search = ListSearch::default();
+        if applied {
+            self.refilter()?;
+        }
+        return Ok(());
+    }
 
-        return Ok(draw_success.map(|_| false)? || exit || self.handle_key_press()?);
+    /// Marks the whole open folder read with an undo, returns how many were
+    /// unread.
+    fn mark_all_read(&mut self) -> Result<usize> {
+        let page = self.messages.get_current_page()?.to_vec();
+        let offset = self.messages.page_size * self.messages.current_page;
+        let (terminal, theme, selected) = (&mut self.terminal, &self.theme, &self.selected);
+        let (list_width, hovered) = (self.list_width, self.hovered_message);
+        let list_state = &mut self.list_state;
+        let outgoing = self.messages.outgoing();
+        let body = self.selected_body.as_deref().unwrap_or("");
+        let (count, undo) = self.messages.mark_all_read(|done, total| {
+            let list = message_list(&page, hovered, selected, offset, outgoing, theme);
+            let status = format!("Marking read, {} of {}", done, total);
+            draw_busy(terminal, list_width, list, list_state, body, &status, theme);
+        })?;
+        if let Some(undo) = undo {
+            self.push_undo(undo);
+        }
+        // nothing is unread any more
+        if self.unread_only {
+            self.refilter()?;
+        }
+        return Ok(count);
+    }
+
+    /// Switches between all messages and only the unread ones.
+    fn toggle_unread_only(&mut self) -> Result<()> {
+        self.unread_only = !self.unread_only;
+        return self.refilter();
+    }
+
+    /// Applies the list search and the unread only view together, or shows
+    /// the whole mailbox when neither is on.
+    fn refilter(&mut self) -> Result<()> {
+        let mut terms = vec![];
+        if self.unread_only {
+            terms.push(SearchQuery::Unseen);
+        }
+        if self.list_search.results.is_some() {
+            terms.push(self.list_search.to_query()?);
+        }
+        self.hovered_message = 0;
+        let query = match terms.len() {
+            0 => {
+                self.messages.clear_search();
+                return Ok(());
+            }
+            1 => terms.remove(0),
+            _ => SearchQuery::And(terms.into()),
+        };
+        let count = self.messages.apply_search(&query)?;
+        if self.list_search.results.is_some() {
+            self.list_search.results = Some(count);
+        }
+        return Ok(());
     }
 
-    fn put_body(&mut self) -> Result<()> {
-        self.selected_body = self.messages.get_body(self.hovered_message).ok();
-        self.selected_message = Some(self.hovered_message);
+    fn refresh(&mut self) -> Result<()> {
+        if self.last_refresh.elapsed() < REFRESH_INTERVAL {
+            return Ok(());
+        }
+        self.last_refresh = Instant::now();
+        // the outbox is retried on the same interval, less often while it
+        // keeps failing so the server isn't logged in to every time
+        if Instant::now() >= self.outbox_retry_at {
+            match self.flush_outbox() {
+                Ok(flushed) => {
+                    self.outbox_backoff = REFRESH_INTERVAL;
+                    if let Some(problems) = flushed.problems() {
+                        self.status = Some(problems.into());
+                    }
+                }
+                Err(_) => {
+                    self.outbox_retry_at = Instant::now() + self.outbox_backoff;
+                    self.outbox_backoff = (self.outbox_backoff * 2).min(MAX_OUTBOX_BACKOFF);
+                }
+            }
+        }
+        // as is a connection lost to a cancelled download
+        if self.messages.needs_reconnect() {
+            self.messages.reconnect(connect_imap()?)?;
+        }
+        let count = self.messages.check_new()?;
+        self.new_messages += count;
+        if count > 0 {
+            self.follow_prepended(count);
+        }
+        if self.notify && count > 0 {
+            if let Some(newest) = self.messages.newest() {
+                // the count in the status bar still says there's mail
+                let _ = notify_new_mail(count, newest);
+            }
+        }
         return Ok(());
     }
 
-    fn handle_key_press(&mut self) -> Result<bool> {
-        let a = event::read().unwrap();
-        return Ok(match a {
+    /// Keeps the cursor and the open message on the same mail after `count`
+    /// new messages went in front of them. Selections are by id, which new
+    /// mail doesn't change.
+    fn follow_prepended(&mut self, count: usize) {
+        let page_size = self.messages.page_size;
+        let start = self.messages.current_page * page_size;
+        let hovered = start + self.hovered_message + count;
+        self.messages.current_page = hovered / page_size;
+        self.hovered_message = hovered % page_size;
+        let new_start = self.messages.current_page * page_size;
+        self.open_message = self
+            .open_message
+            .and_then(|x| (start + x + count).checked_sub(new_start))
+            .filter(|&x| x < page_size);
+    }
+
+    fn handle_event(&mut self, event: event::Event) -> Result<bool> {
+        return Ok(match event {
             event::Event::Key(key) => {
-                if key.kind == KeyEventKind::Press
-                    && ((key.modifiers.intersects(KeyModifiers::CONTROL)
-                        && key.code == KeyCode::Char('c'))
-                        || key.code == KeyCode::Char('q'))
-                {
-                    return Ok(true);
+                if key.kind == KeyEventKind::Press {
+                    self.new_messages = 0;
+                    self.status = None;
                 }
 
-                if key.kind == KeyEventKind::Press && key.code == KeyCode::Enter {
-                    let _ = self.put_body();
+                if key.kind == KeyEventKind::Press && self.quitting {
+                    return Ok(self.handle_quit_key(key.code));
                 }
 
-                if key.kind == KeyEventKind::Press
-                    && key.modifiers.intersects(KeyModifiers::CONTROL)
-                    && key.code == KeyCode::Char('h')
-                {
-                    self.left = true;
+                if key.kind == KeyEventKind::Press && self.confirm_purge {
+                    self.confirm_purge = false;
+                    if key.code == KeyCode::Char('y') {
+                        self.status = Some(match self.delete(true) {
+                            Ok(status) => status.into(),
+                            Err(e) => format!("Failed to delete: {}", e).into(),
+                        });
+                    }
+                    return Ok(false);
+                }
+
+                if key.kind == KeyEventKind::Press && self.confirm_mark_all {
+                    self.confirm_mark_all = false;
+                    if key.code == KeyCode::Char('y') {
+                        self.status = Some(match self.mark_all_read() {
+                            Ok(count) => format!("Marked {} messages read", count).into(),
+                            Err(e) => format!("Failed to mark read: {}", e).into(),
+                        });
+                    }
+                    return Ok(false);
+                }
+
+                if key.kind == KeyEventKind::Press && self.confirm_unsubscribe.is_some() {
+                    if let (Some((unsubscribe, _)), KeyCode::Char('y')) =
+                        (self.confirm_unsubscribe.take(), key.code)
+                    {
+                        self.status = Some(match self.unsubscribe(unsubscribe) {
+                            Ok(status) => status.into(),
+                            Err(e) => format!("Failed to unsubscribe: {}", e).into(),
+                        });
+                    }
+                    return Ok(false);
+                }
+
+                if key.kind == KeyEventKind::Press && self.confirm_download.is_some() {
+                    self.confirm_download = None;
+                    if key.code == KeyCode::Char('y') {
+                        let _ = self.put_body(self.hovered_message);
+                    }
+                    return Ok(false);
                 }
 
-                if key.kind == KeyEventKind::Press
-                    && key.modifiers.intersects(KeyModifiers::CONTROL)
-                    && key.code == KeyCode::Char('l')
+                if key.kind == KeyEventKind::Press && self.search.typing {
+                    self.handle_search_key(key.code);
+                    return Ok(false);
+                }
+
+                let ctrl_c = key.modifiers.intersects(KeyModifiers::CONTROL)
+                    && key.code == KeyCode::Char('c');
+                if key.kind == KeyEventKind::Press && self.list_search.typing && !ctrl_c {
+                    if let Err(e) = self.handle_list_search_key(key) {
+                        self.list_search.typing = false;
+                        self.status = Some(format!("Search failed: {}", e).into());
+                    }
+                    return Ok(false);
+                }
+
+                if key.kind == KeyEventKind::Press && self.compose.is_some() && !ctrl_c {
+                    self.handle_compose_key(key);
+                    return Ok(false);
+                }
+
+                if let (KeyEventKind::Press, Some(hovered), false) =
+                    (key.kind, self.contacts_view, ctrl_c)
                 {
-                    self.left = false;
+                    if let Err(e) = self.handle_contacts_key(key.code, hovered) {
+                        self.status = Some(format!("Failed to save contacts: {}", e).into());
+                    }
+                    return Ok(false);
+                }
+
+                let action = match key.kind {
+                    KeyEventKind::Press => self.keymap.action(key),
+                    _ => None,
+                };
+
+                if (key.kind == KeyEventKind::Press && ctrl_c) || action == Some(Action::Quit) {
+                    // always ask while composing so the draft isn't lost
+                    if !self.confirm_quit && self.compose.is_none() {
+                        return Ok(true);
+                    }
+                    self.quitting = true;
+                    return Ok(false);
                 }
 
-                if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('j') {
-                    if self.left {
-                        if self.hovered_message < self.messages.page_size - 1 {
-                            self.hovered_message += 1;
-                        } else {
-                            self.messages.next_page();
-                            self.hovered_message = 0;
+                if action == Some(Action::Open) {
+                    if self.messages.folder == Some(SpecialUse::Drafts) {
+                        let _ = self.open_draft();
+                    } else {
+                        let size = self
+                            .messages
+                            .get_current_page()
+                            .ok()
+                            .and_then(|page| page.get(self.hovered_message)?.size);
+                        match (size, self.confirm_download_over) {
+                            (Some(size), Some(limit)) if size > limit => {
+                                self.confirm_download = Some(size);
+                            }
+                            _ => {
+                                let _ = self.put_body(self.hovered_message);
+                            }
                         }
                     }
                 }
-                if key.kind == KeyEventKind::Press && key.code == KeyCode::Char('k') {
-                    if self.left {
-                        if self.hovered_message > 0 {
-                            self.hovered_message -= 1;
-                        } else if self.messages.current_page > 0 {
-                            self.messages.prev_page();
-                            self.hovered_message = self.messages.page_size - 1;
+
+                if action == Some(Action::ToggleSource) {
+                    if let Err(e) = self.toggle_source() {
+                        self.status = Some(format!("Failed to fetch source: {}", e).into());
+                    }
+                }
+
+                if action == Some(Action::ToggleHeaders) {
+                    if let Err(e) = self.toggle_headers() {
+                        self.status = Some(format!("Failed to reload message: {}", e).into());
+                    }
+                }
+
+                if action == Some(Action::Quota) {
+                    self.status = Some(match self.messages.storage_quota() {
+                        Ok((usage, limit)) => format!("{}/{} MB used", usage, limit).into(),
+                        Err(e) => format!("Failed to get quota: {}", e).into(),
+                    });
+                }
+
+                if action == Some(Action::Compose) {
+                    self.start_compose(Compose::default());
+                }
+
+                if action == Some(Action::Reply) {
+                    if let Err(e) = self.reply() {
+                        self.status = Some(format!("Failed to reply: {}", e).into());
+                    }
+                }
+
+                if action == Some(Action::AddressBook) {
+                    self.contacts_view = Some(0);
+                }
+
+                if action == Some(Action::Delete) {
+                    self.status = Some(match self.delete(false) {
+                        Ok(status) => status.into(),
+                        Err(e) => format!("Failed to delete: {}", e).into(),
+                    });
+                }
+
+                if action == Some(Action::Undo) {
+                    self.status = Some(match self.undo() {
+                        Ok(status) => format!("Undone: {}", status).into(),
+                        Err(e) => format!("Failed to undo: {}", e).into(),
+                    });
+                }
+
+                if let Some(copy @ (Action::CopyBody | Action::CopySender)) = action {
+                    self.status = Some(match self.copy(copy == Action::CopySender) {
+                        Ok(status) => status.into(),
+                        Err(e) => format!("Failed to copy: {}", e).into(),
+                    });
+                }
+
+                if action == Some(Action::Unsubscribe) {
+                    match self.ask_unsubscribe() {
+                        Ok(Some(status)) => self.status = Some(status.into()),
+                        Ok(None) => {}
+                        Err(e) => {
+                            self.status = Some(format!("Failed to unsubscribe: {}", e).into())
                         }
                     }
                 }
+
+                if action == Some(Action::Purge) {
+                    self.confirm_purge = true;
+                }
+
+                if action == Some(Action::Select) {
+                    if let Err(e) = self.toggle_selected() {
+                        self.status = Some(format!("Failed to select: {}", e).into());
+                    }
+                }
+
+                if action == Some(Action::MarkRead) {
+                    self.status = Some(match self.mark_read() {
+                        Ok(status) => status.into(),
+                        Err(e) => format!("Failed to mark read: {}", e).into(),
+                    });
+                }
+
+                if action == Some(Action::MarkAllRead) {
+                    self.confirm_mark_all = true;
+                }
+
+                if action == Some(Action::UnreadOnly) {
+                    if let Err(e) = self.toggle_unread_only() {
+                        self.status = Some(format!("Failed to filter: {}", e).into());
+                    }
+                }
+
+                if action == Some(Action::Drafts) {
+                    if let Err(e) = self.toggle_drafts() {
+                        self.status = Some(format!("Failed to open Drafts: {}", e).into());
+                    }
+                }
+
+                let count = self.pending_count.take();
+                if let (KeyEventKind::Press, KeyCode::Char(c @ '0'..='9')) = (key.kind, key.code) {
+                    let digit = c as usize - '0' as usize;
+                    self.pending_count = Some(count.unwrap_or(0).saturating_mul(10) + digit);
+                    return Ok(false);
+                }
+
+                if action == Some(Action::First) {
+                    self.jump_to(0);
+                }
+
+                if action == Some(Action::Last) {
+                    let last = self.messages.loaded().saturating_sub(1);
+                    self.jump_to(count.unwrap_or(last));
+                }
+
+                if action == Some(Action::NextPage) && self.messages.next_page() {
+                    // the last page can be short
+                    let last = self.messages.total() - 1;
+                    let first = self.messages.current_page * self.messages.page_size;
+                    self.hovered_message = self.hovered_message.min(last - first);
+                }
+
+                if action == Some(Action::PrevPage) {
+                    self.messages.prev_page();
+                }
+
+                if action == Some(Action::Search) {
+                    if self.focus == Pane::List {
+                        self.list_search.start();
+                    } else if self.selected_body.is_some() {
+                        self.search.start();
+                    }
+                }
+
+                if action == Some(Action::ClearSearch) {
+                    if let Err(e) = self.clear_list_search() {
+                        self.status = Some(format!("Failed to filter: {}", e).into());
+                    }
+                }
+
+                if action == Some(Action::NextMatch) {
+                    self.search.next();
+                    self.scroll_to_match();
+                }
+
+                if action == Some(Action::PrevMatch) {
+                    self.search.prev();
+                    self.scroll_to_match();
+                }
+
+                if action == Some(Action::Narrower) {
+                    self.list_width = self.list_width.saturating_sub(LIST_WIDTH_STEP);
+                }
+
+                if action == Some(Action::Wider) {
+                    self.list_width = (self.list_width + LIST_WIDTH_STEP).min(MAX_LIST_WIDTH);
+                }
+
+                if action == Some(Action::Flag) {
+                    match self
+                        .messages
+                        .toggle_flag(self.hovered_message, Flag::Flagged)
+                    {
+                        Ok(undo) => self.push_undo(undo),
+                        Err(e) => {
+                            self.status = Some(format!("Failed to flag message: {}", e).into())
+                        }
+                    }
+                }
+
+                if action == Some(Action::TogglePreviews) {
+                    self.messages.previews = !self.messages.previews;
+                }
+
+                if action == Some(Action::FocusLeft) {
+                    self.focus = Pane::List;
+                }
+
+                if action == Some(Action::FocusRight) {
+                    self.focus = Pane::Body;
+                }
+
+                if action == Some(Action::FocusNext) {
+                    self.focus = self.focus.next();
+                }
+
+                if action == Some(Action::FocusPrev) {
+                    self.focus = self.focus.prev();
+                }
+
+                if action == Some(Action::MoveDown) {
+                    match self.focus {
+                        Pane::List => self.hover_next(),
+                        Pane::Body => self.body_scroll = self.body_scroll.saturating_add(1),
+                    }
+                }
+
+                if action == Some(Action::MoveUp) {
+                    match self.focus {
+                        Pane::List => self.hover_prev(),
+                        Pane::Body => self.body_scroll = self.body_scroll.saturating_sub(1),
+                    }
+                }
+                false
+            }
+            event::Event::Mouse(mouse) => {
+                self.handle_mouse(mouse)?;
                 false
             }
             event::Event::Resize(_, h) => {
                 self.hovered_message = self
                     .messages
-                    .update_page_size(h as usize, self.hovered_message);
+                    .update_page_size(h.saturating_sub(1) as usize, self.hovered_message);
                 false
             }
             _ => false,
         });
     }
 }
+
+/// The first recipient's name, or address if it has none, and how many
+/// more there are, e.g. `Jane Doe +2`.
+fn recipients(message: &Message) -> Option<String> {
+    let to = message.to.iter().flat_map(|x| x.iter());
+    let mut all = to.chain(message.cc.iter().flat_map(|x| x.iter()));
+    let first = all.next()?;
+    let name = first.name.as_deref().unwrap_or(&first.email).trim();
+    return match all.count() {
+        0 => Some(name.to_owned()),
+        more => Some(format!("{} +{}", name, more)),
+    };
+}
+
+/// Splits the screen into the message list, the body pane and the status bar.
+fn panes(area: Rect, list_width: u16) -> [Rect; 3] {
+    let [main, status_bar] = Layout::default()
+        .direction(Direction::Vertical)
+        .constraints(vec![Constraint::Fill(1), Constraint::Length(1)])
+        .areas(area);
+    let [list, body] = Layout::default()
+        .direction(Direction::Horizontal)
+        .constraints(vec![
+            Constraint::Percentage(list_width),
+            Constraint::Fill(1),
+        ])
+        .areas(main);
+    return [list, body, status_bar];
+}
+
+/// Draws the screen while something slow runs, with `status` saying how far
+/// along it is. A failed draw only loses this frame, the next one redraws.
+fn draw_busy(
+    terminal: &mut Terminal<CrosstermBackend<Stdout>>,
+    list_width: u16,
+    list: List,
+    list_state: &mut ListState,
+    body: &str,
+    status: &str,
+    theme: &Theme,
+) {
+    let _ = terminal.draw(|frame| {
+        let [list_area, body_area, status_bar] = panes(frame.area(), list_width);
+        frame.render_stateful_widget(list, list_area, list_state);
+        // only the top is visible, no need to lay out the rest
+        let visible = body.lines().take(body_area.height as usize);
+        frame.render_widget(
+            Paragraph::new(Text::from_iter(visible)).wrap(Wrap { trim: false }),
+            body_area,
+        );
+        frame.render_widget(Paragraph::new(status).style(theme.status_bar()), status_bar);
+    });
+}
+
+/// The page of messages, `offset` is the number of the first one. With
+/// `outgoing` each one shows who it was sent to.
+fn message_list<'a>(
+    page: &'a [Message],
+    hovered: usize,
+    selected: &HashSet<usize>,
+    offset: usize,
+    outgoing: bool,
+    theme: &Theme,
+) -> List<'a> {
+    let list = List::new(page.iter().enumerate().map(|(i, x)| {
+        let style = match (i == hovered, x.flags.seen) {
+            (true, true) => theme.selection(),
+            (true, false) => theme.selection().bold(),
+            (false, true) => Style::default(),
+            (false, false) => theme.unread(),
+        };
+
+        let gutter = if x.flags.flagged {
+            '*'
+        } else if x.flags.answered {
+            '↩'
+        } else {
+            ' '
+        };
+        let mut line = Line::styled(format!("{} {}. ", gutter, i + offset), style);
+        if selected.contains(&x.id) {
+            line.push_span(Span::styled("✓ ", style.green().bold()));
+        }
+        if x.priority == Priority::High {
+            line.push_span(Span::styled("! ", style.red().bold()));
+        }
+        if let Some(to) = recipients(x).filter(|_| outgoing) {
+            line.push_span(Span::styled(format!("To: {} ", to), style.italic()));
+        }
+        line.push_span(Span::styled(format!("{} ", x.subject), style));
+        // keywords and Gmail's own labels, system ones like \\Inbox left out
+        let gmail_labels = x.gmail.iter().flat_map(|x| x.labels.iter());
+        let labels = (x.flags.custom.iter())
+            .chain(gmail_labels.filter(|x| !x.starts_with('\\')))
+            .map(|x| &**x)
+            .collect::<Vec<_>>();
+        if !labels.is_empty() {
+            let labels = format!("[{}] ", labels.join(", "));
+            line.push_span(Span::styled(labels, style.cyan()));
+        }
+        if let Some(size) = x.size.filter(|&x| x >= SHOWN_SIZE) {
+            line.push_span(Span::styled(
+                format!("{} ", human_size(size)),
+                style.yellow(),
+            ));
+        }
+        if let Some(date) = &x.internal_date {
+            line.push_span(Span::styled(
+                format!("{} ", date.short()),
+                style.dark_gray(),
+            ));
+        }
+        if let Some(preview) = &x.preview {
+            line.push_span(Span::styled(&**preview, style.dark_gray()));
+        }
+        return Text::from(line);
+    }))
+    .block(
+        Block::default()
+            .borders(Borders::RIGHT)
+            .border_style(theme.border()),
+    );
+    return list;
+}
+
+/// A byte count like `2.3 MB`.
+fn human_size(bytes: usize) -> String {
+    let units = ["B", "KB", "MB", "GB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        return format!("{} B", bytes);
+    }
+    return format!("{:.1} {}", size, units[unit]);
+}
+
+/// The trust line under the headers, from what the receiving server
+/// checked. Nothing when it couldn't tell either way or the results aren't
+/// from one of `authserv_ids`.
+fn auth_badge(auth: &AuthResults, authserv_ids: &[String]) -> Option<String> {
+    if !authserv_ids.iter().any(|x| auth.added_by(x)) {
+        return None;
+    }
+    let failures = auth.failures();
+    if !failures.is_empty() {
+        return Some(format!("⚠ {} fail", failures.join(", ")));
+    }
+    if auth.authenticated() {
+        return Some("✓ authenticated".to_owned());
+    }
+    return None;
+}
+
+/// A desktop notification naming the sender and subject of `newest`.
+fn notify_new_mail(count: usize, newest: &Message) -> Result<()> {
+    let sender = match newest.from.first() {
+        Some(contact) => contact.name.as_deref().unwrap_or(&contact.email).trim(),
+        None => "unknown sender",
+    };
+    let summary = match count {
+        1 => format!("New mail from {}", sender),
+        _ => format!("{} new messages, latest from {}", count, sender),
+    };
+    notify_rust::Notification::new()
+        .appname("email-tui")
+        .summary(&summary)
+        .body(&newest.subject)
+        .show()
+        .context("Failed to show notification")?;
+    return Ok(());
+}
+
+/// Connects and logs in with the account in the environment.
+fn connect_imap() -> Result<IMap> {
+    let mut builder = IMap::builder("imap.gmail.com", 993);
+    if let Some(trace) = config::wire_trace()? {
+        builder = builder.trace(trace);
+    }
+    let mut imap = builder.connect()?;
+    imap.login(
+        &std::env::var("EMAIL_USERNAME")?,
+        &std::env::var("EMAIL_PASSWORD")?,
+    )?;
+    // some providers hold back commands from clients that don't say who
+    // they are
+    if imap.has_capability("ID")? {
+        imap.id(&[
+            ("name", "email-tui"),
+            ("version", env!("CARGO_PKG_VERSION")),
+        ])?;
+    }
+    return Ok(imap);
+}
+
+/// Whether Esc was pressed, without waiting. Anything else typed while a
+/// download runs is dropped.
+fn cancel_pressed() -> bool {
+    while let Ok(true) = event::poll(Duration::ZERO) {
+        if let Ok(event::Event::Key(key)) = event::read() {
+            if key.kind == KeyEventKind::Press && key.code == KeyCode::Esc {
+                return true;
+            }
+        }
+    }
+    return false;
+}
+
+fn contacts_path() -> Result<PathBuf> {
+    return ContactBook::default_path().context("No data directory");
+}
+
+/// The address book with the hovered contact highlighted, scrolled so it
+/// stays in view.
+fn render_contacts<'a>(
+    contacts: &'a ContactBook,
+    theme: &Theme,
+    hovered: usize,
+    height: usize,
+) -> Text<'a> {
+    let lines = contacts
+        .list()
+        .into_iter()
+        .enumerate()
+        .skip((hovered + 1).saturating_sub(height))
+        .map(|(i, contact)| {
+            let style = if i == hovered {
+                theme.selection()
+            } else {
+                Style::default()
+            };
+            return Line::styled(contact.to_string(), style);
+        });
+    return Text::from(lines.collect::<Vec<_>>());
+}
@@ -0,0 +1,76 @@
+use std::collections::VecDeque;
+
+/// Decoded bodies of recently opened messages keyed by id, the least
+/// recently used is dropped once `capacity` is reached.
+#[derive(Debug)]
+pub struct BodyCache {
+    capacity: usize,
+    /// Most recently used first.
+    entries: VecDeque<(usize, Box<str>)>,
+}
+
+impl BodyCache {
+    pub fn new(capacity: usize) -> Self {
+        return Self {
+            capacity,
+            entries: VecDeque::with_capacity(capacity),
+        };
+    }
+
+    pub fn get(&mut self, id: usize) -> Option<&str> {
+        let position = self.entries.iter().position(|(x, _)| *x == id)?;
+        let entry = self.entries.remove(position)?;
+        self.entries.push_front(entry);
+        return self.entries.front().map(|(_, body)| &**body);
+    }
+
+    pub fn insert(&mut self, id: usize, body: Box<str>) {
+        if self.capacity == 0 {
+            return;
+        }
+        self.entries.retain(|(x, _)| *x != id);
+        self.entries.truncate(self.capacity - 1);
+        self.entries.push_front((id, body));
+    }
+
+    /// Drops `id` after it was expunged and moves the ids above it down to
+    /// match the server's renumbering.
+    pub fn expunged(&mut self, id: usize) {
+        self.entries.retain(|(x, _)| *x != id);
+        for (x, _) in &mut self.entries {
+            if *x > id {
+                *x -= 1;
+            }
+        }
+    }
+
+    /// Ids only mean something within a mailbox.
+    pub fn clear(&mut self) {
+        self.entries.clear();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_body_cache() {
+        let mut cache = BodyCache::new(2);
+        cache.insert(1, "one".into());
+        cache.insert(2, "two".into());
+        assert_eq!(cache.get(1), Some("one"));
+        // 2 is now the least recently used
+        cache.insert(3, "three".into());
+        assert_eq!(cache.get(2), None);
+        assert_eq!(cache.get(3), Some("three"));
+
+        cache.expunged(2);
+        assert_eq!(cache.get(1), Some("one"));
+        assert_eq!(cache.get(2), Some("three"));
+
+        let mut disabled = BodyCache::new(0);
+        disabled.insert(1, "one".into());
+        assert_eq!(disabled.get(1), None);
+    }
+}
@@ -0,0 +1,121 @@
+use ratatui::{
+    style::{Style, Stylize},
+    text::{Line, Span, Text},
+};
+use std::ops::Range;
+
+/// A vim style `/` search in the message body, matching ignores ASCII case.
+#[derive(Debug, Default)]
+pub struct BodySearch {
+    pub query: String,
+    /// Whether the query is still being typed.
+    pub typing: bool,
+    /// The line and byte range of every match.
+    matches: Vec<(usize, Range<usize>)>,
+    current: usize,
+}
+
+impl BodySearch {
+    pub fn start(&mut self) {
+        self.query.clear();
+        self.matches.clear();
+        self.typing = true;
+    }
+
+    /// Finds every match of the query in `text` and makes the first one
+    /// current.
+    pub fn find(&mut self, text: &str) {
+        self.typing = false;
+        self.current = 0;
+        self.matches.clear();
+        if self.query.is_empty() {
+            return;
+        }
+        let query = self.query.to_ascii_lowercase();
+        for (i, line) in text.lines().enumerate() {
+            let line = line.to_ascii_lowercase();
+            self.matches.extend(
+                line.match_indices(&query)
+                    .map(|(start, x)| (i, start..start + x.len())),
+            );
+        }
+    }
+
+    pub fn next(&mut self) {
+        if !self.matches.is_empty() {
+            self.current = (self.current + 1) % self.matches.len();
+        }
+    }
+
+    pub fn prev(&mut self) {
+        if !self.matches.is_empty() {
+            self.current = (self.current + self.matches.len() - 1) % self.matches.len();
+        }
+    }
+
+    /// The line of the current match.
+    pub fn current_line(&self) -> Option<usize> {
+        return self.matches.get(self.current).map(|(line, _)| *line);
+    }
+
+    /// Shown in the status bar, e.g. `/query [2/5]`.
+    pub fn status(&self) -> String {
+        if self.typing || self.matches.is_empty() {
+            return format!("/{}", self.query);
+        }
+        return format!(
+            "/{} [{}/{}]",
+            self.query,
+            self.current + 1,
+            self.matches.len()
+        );
+    }
+
+    /// `text` with the matches highlighted.
+    pub fn highlight<'a>(&self, text: &'a str) -> Text<'a> {
+        let mut matches = self.matches.iter().enumerate().peekable();
+        let lines = text.lines().enumerate().map(|(i, line)| {
+            let mut spans = vec![];
+            let mut pos = 0;
+            while let Some((index, (_, range))) = matches.next_if(|(_, (x, _))| *x == i) {
+                spans.push(Span::raw(&line[pos..range.start]));
+                let style = if index == self.current {
+                    Style::default().black().on_yellow()
+                } else {
+                    Style::default().on_dark_gray()
+                };
+                spans.push(Span::styled(&line[range.clone()], style));
+                pos = range.end;
+            }
+            spans.push(Span::raw(&line[pos..]));
+            return Line::from(spans);
+        });
+        return Text::from(lines.collect::<Vec<_>>());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_body_search() {
+        let text = "Hello there\nnothing here\nhello hello";
+        let mut search = BodySearch::default();
+        search.start();
+        search.query.push_str("HELLO");
+        search.find(text);
+        assert_eq!(search.status(), "/HELLO [1/3]");
+        assert_eq!(search.current_line(), Some(0));
+        search.next();
+        assert_eq!(search.current_line(), Some(2));
+        search.prev();
+        search.prev();
+        assert_eq!(search.current_line(), Some(2));
+
+        let highlighted = search.highlight(text);
+        assert_eq!(highlighted.lines.len(), 3);
+        assert_eq!(highlighted.lines[2].spans.len(), 5);
+        assert_eq!(highlighted.lines[2].spans[3].content, "hello");
+    }
+}
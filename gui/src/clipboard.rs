@@ -0,0 +1,53 @@
+use anyhow::{Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::io::Write;
+
+/// Copies text to the system clipboard, or through the terminal with OSC 52
+/// when there's no display to talk to, e.g. over SSH.
+pub struct Clipboard {
+    /// Kept open, on X11 the copied text goes away with it.
+    system: Option<arboard::Clipboard>,
+}
+
+impl Clipboard {
+    pub fn new() -> Self {
+        // over SSH the system clipboard would be the server's
+        let remote =
+            std::env::var_os("SSH_TTY").is_some() || std::env::var_os("SSH_CONNECTION").is_some();
+        let system = match remote {
+            true => None,
+            false => arboard::Clipboard::new().ok(),
+        };
+        return Self { system };
+    }
+
+    /// Copies `text`, the terminal is used if the system clipboard refuses.
+    pub fn copy(&mut self, text: &str) -> Result<()> {
+        if let Some(system) = &mut self.system {
+            if system.set_text(text).is_ok() {
+                return Ok(());
+            }
+        }
+        let mut stdout = std::io::stdout();
+        stdout
+            .write_all(osc52(text).as_bytes())
+            .and_then(|_| stdout.flush())
+            .context("Failed to write to the terminal")?;
+        return Ok(());
+    }
+}
+
+/// The escape sequence asking the terminal to put `text` on the clipboard.
+fn osc52(text: &str) -> String {
+    return format!("\x1b]52;c;{}\x07", STANDARD.encode(text));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_osc52() {
+        assert_eq!(osc52("hi"), "\x1b]52;c;aGk=\x07");
+    }
+}
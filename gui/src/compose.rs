@@ -0,0 +1,332 @@
+use crate::contacts::ContactBook;
+use crate::quote::{attribution, quote, QUOTE_WIDTH};
+use crate::theme::Theme;
+use imap::message::{Contact, Message};
+use ratatui::{
+    crossterm::event::{KeyCode, KeyEvent},
+    style::Style,
+    text::{Line, Text},
+};
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+enum Field {
+    #[default]
+    To,
+    Cc,
+    Subject,
+    Body,
+}
+
+/// A message being written, addresses are comma separated.
+#[derive(Debug, Default)]
+pub struct Compose {
+    pub to: String,
+    pub cc: String,
+    pub subject: String,
+    pub body: String,
+    /// The `UIDVALIDITY` and UID of the saved draft this was opened from,
+    /// replaced when it's saved again.
+    pub draft: Option<(u32, u32)>,
+    /// Sent below `body` but not edited, the signature and, when
+    /// top-posting, the quoted message.
+    footer: String,
+    field: Field,
+    /// Matches for the address being typed, Tab takes the first.
+    suggestions: Vec<Contact>,
+}
+
+impl Compose {
+    /// A new message to `to`, starting on the subject.
+    pub fn to(to: &str) -> Self {
+        return Self {
+            to: to.to_owned(),
+            field: Field::Subject,
+            ..Self::default()
+        };
+    }
+
+    /// Reopens a saved draft.
+    pub fn from_message(message: &Message, body: &str) -> Self {
+        let addresses = |contacts: &Option<Box<[Contact]>>| {
+            return contacts
+                .iter()
+                .flat_map(|x| x.iter())
+                .map(|x| x.email.trim())
+                .collect::<Vec<_>>()
+                .join(", ");
+        };
+        return Self {
+            to: addresses(&message.to),
+            cc: addresses(&message.cc),
+            subject: message.subject.trim().to_owned(),
+            body: body.replace("\r\n", "\n"),
+            draft: None,
+            footer: String::new(),
+            field: Field::Body,
+            suggestions: vec![],
+        };
+    }
+
+    /// A reply to `message`, addressed to its `Reply-To:` if it has one,
+    /// with `body` quoted under an attribution line. With `top_post` the
+    /// quote goes below what's written.
+    pub fn reply(message: &Message, body: &str, top_post: bool) -> Self {
+        let to = message
+            .reply_recipients()
+            .iter()
+            .map(|x| x.email.trim())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let subject = message.subject.trim();
+        let subject = match subject.get(..3) {
+            Some(prefix) if prefix.eq_ignore_ascii_case("re:") => subject.to_owned(),
+            _ => format!("Re: {}", subject),
+        };
+        let quoted = format!("{}\n{}", attribution(message), quote(body, QUOTE_WIDTH));
+        let (body, footer) = match top_post {
+            true => (String::new(), quoted),
+            false => (format!("{}\n\n", quoted), String::new()),
+        };
+        return Self {
+            to,
+            subject,
+            body,
+            footer,
+            field: Field::Body,
+            ..Self::default()
+        };
+    }
+
+    /// Adds `signature` under the standard `-- ` delimiter, below what's
+    /// written and above a top-posted quote. A blank signature adds nothing.
+    pub fn sign(&mut self, signature: &str) {
+        if signature.trim().is_empty() {
+            return;
+        }
+        let signature = format!("-- \n{}", signature.trim_end());
+        self.footer = match self.footer.is_empty() {
+            true => signature,
+            false => format!("{}\n\n{}", signature, self.footer),
+        };
+    }
+
+    /// What's written followed by the footer.
+    fn full_body(&self) -> String {
+        if self.footer.is_empty() {
+            return self.body.clone();
+        }
+        return format!("{}\n\n{}", self.body.trim_end(), self.footer);
+    }
+
+    fn current(&mut self) -> &mut String {
+        return match self.field {
+            Field::To => &mut self.to,
+            Field::Cc => &mut self.cc,
+            Field::Subject => &mut self.subject,
+            Field::Body => &mut self.body,
+        };
+    }
+
+    /// Tab and Shift-Tab move between fields, everything else edits the
+    /// current one.
+    pub fn handle_key(&mut self, key: KeyEvent) {
+        use Field::*;
+        match key.code {
+            KeyCode::Tab if !self.suggestions.is_empty() => self.accept_suggestion(),
+            KeyCode::Tab => {
+                self.field = match self.field {
+                    To => Cc,
+                    Cc => Subject,
+                    Subject => Body,
+                    Body => To,
+                }
+            }
+            KeyCode::BackTab => {
+                self.field = match self.field {
+                    To => Body,
+                    Cc => To,
+                    Subject => Cc,
+                    Body => Subject,
+                }
+            }
+            KeyCode::Enter if self.field == Body => self.body.push('\n'),
+            KeyCode::Enter => self.handle_key(KeyCode::Tab.into()),
+            KeyCode::Backspace => {
+                self.current().pop();
+            }
+            KeyCode::Char(c) => self.current().push(c),
+            _ => {}
+        }
+    }
+
+    /// Looks up the address after the last comma of the To or Cc field.
+    pub fn update_suggestions(&mut self, contacts: &ContactBook) {
+        self.suggestions = match self.field {
+            Field::To | Field::Cc => {
+                let current = self.current().rsplit(',').next().unwrap_or("").to_owned();
+                contacts.suggest(&current).into_iter().cloned().collect()
+            }
+            _ => vec![],
+        };
+    }
+
+    fn accept_suggestion(&mut self) {
+        let email = self.suggestions.remove(0).email;
+        let field = self.current();
+        let start = field.rfind(',').map_or(0, |x| x + 1);
+        field.truncate(start);
+        if start > 0 {
+            field.push(' ');
+        }
+        field.push_str(email.trim());
+        field.push_str(", ");
+        self.suggestions.clear();
+    }
+
+    pub fn render(&self, theme: &Theme) -> Text<'_> {
+        let style = |field| {
+            if self.field == field {
+                theme.selection()
+            } else {
+                Style::default()
+            }
+        };
+        let suggestions = self
+            .suggestions
+            .iter()
+            .map(|x| Line::styled(format!("    {}", x), theme.border()));
+        let mut lines = vec![Line::styled(format!("To: {}", self.to), style(Field::To))];
+        if self.field == Field::To {
+            lines.extend(suggestions.clone());
+        }
+        lines.push(Line::styled(format!("Cc: {}", self.cc), style(Field::Cc)));
+        if self.field == Field::Cc {
+            lines.extend(suggestions);
+        }
+        lines.extend([
+            Line::styled(format!("Subject: {}", self.subject), style(Field::Subject)),
+            Line::styled("-".repeat(20), style(Field::Body)),
+        ]);
+        lines.extend(self.body.split('\n').map(Line::raw));
+        if !self.footer.is_empty() {
+            lines.push(Line::raw(""));
+            lines.extend(
+                self.footer
+                    .split('\n')
+                    .map(|x| Line::styled(x, theme.border())),
+            );
+        }
+        return Text::from(lines);
+    }
+
+    /// Every To and Cc address.
+    pub fn recipients(&self) -> Vec<String> {
+        return addresses(&self.to)
+            .into_iter()
+            .chain(addresses(&self.cc))
+            .map(str::to_owned)
+            .collect();
+    }
+
+    /// The message as RFC822 text, for saving as a draft or sending. `from`
+    /// is the whole `From:` value, see `smtp::mailbox`.
+    pub fn to_rfc822(&self, from: &str) -> String {
+        let to = addresses(&self.to);
+        let cc = addresses(&self.cc);
+        let cc = if cc.is_empty() { None } else { Some(&cc[..]) };
+        let body = self.full_body();
+        // formatted before connecting, so kept to what any server takes
+        return smtp::format_message(from, &to, cc, &self.subject, &body, false);
+    }
+}
+
+fn addresses(field: &str) -> Vec<&str> {
+    return field
+        .split(',')
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .collect();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_compose_to_rfc822() {
+        let mut compose = Compose::default();
+        for c in "a@x.com, b@x.com".chars() {
+            compose.handle_key(KeyCode::Char(c).into());
+        }
+        compose.handle_key(KeyCode::Tab.into());
+        compose.handle_key(KeyCode::Enter.into());
+        for c in "Hi".chars() {
+            compose.handle_key(KeyCode::Char(c).into());
+        }
+        compose.handle_key(KeyCode::Tab.into());
+        compose.handle_key(KeyCode::Char('a').into());
+        compose.handle_key(KeyCode::Enter.into());
+        compose.handle_key(KeyCode::Char('b').into());
+
+        let raw = compose.to_rfc822("Me <me@x.com>");
+        assert!(raw.starts_with("From: Me <me@x.com>\r\nSubject: Hi\r\nTo: a@x.com, b@x.com\r\n"));
+        assert!(!raw.contains("Cc:"));
+        assert!(raw.ends_with("\r\n\r\na\r\nb"));
+        assert_eq!(compose.recipients(), ["a@x.com", "b@x.com"]);
+    }
+
+    #[test]
+    fn test_compose_reply() {
+        let raw = "* 5 FETCH (FLAGS () BODY[HEADER.FIELDS (SUBJECT FROM REPLY-TO)] {0}\r\nSubject: Lunch\r\nFrom: noreply@x.com\r\nReply-To: list@x.com\r\n";
+        let message: Message = raw.parse().unwrap();
+        let reply = Compose::reply(&message, "See you\r\n", false);
+        assert_eq!(reply.to, "list@x.com");
+        assert_eq!(reply.subject, "Re: Lunch");
+        assert_eq!(reply.body, "noreply@x.com wrote:\n> See you\n\n");
+        assert_eq!(reply.field, Field::Body);
+    }
+
+    #[test]
+    fn test_compose_signature() {
+        let mut compose = Compose::to("a@x.com");
+        compose.sign("Jane\n");
+        compose.body.push_str("Hi\n");
+        assert_eq!(compose.full_body(), "Hi\n\n-- \nJane");
+        let mut blank = Compose::to("a@x.com");
+        blank.sign(" \n\t");
+        blank.body.push_str("Hi\n");
+        assert_eq!(blank.full_body(), "Hi\n");
+
+        let raw = "* 5 FETCH (BODY[HEADER.FIELDS (SUBJECT FROM)] {0}\r\nSubject: Lunch\r\nFrom: b@x.com\r\n";
+        let message: Message = raw.parse().unwrap();
+        let mut reply = Compose::reply(&message, "See you", true);
+        reply.sign("Jane");
+        reply.body.push_str("Sure");
+        assert_eq!(
+            reply.full_body(),
+            "Sure\n\n-- \nJane\n\nb@x.com wrote:\n> See you"
+        );
+        let mut reply = Compose::reply(&message, "See you", false);
+        reply.sign("Jane");
+        reply.body.push_str("Sure");
+        assert_eq!(
+            reply.full_body(),
+            "b@x.com wrote:\n> See you\n\nSure\n\n-- \nJane"
+        );
+    }
+
+    #[test]
+    fn test_compose_autocomplete() {
+        let mut contacts = ContactBook::default();
+        contacts.add(&"Jane Doe <jane@x.com>".parse().unwrap());
+        let mut compose = Compose::default();
+        compose.to.push_str("bob@x.com, ja");
+        compose.update_suggestions(&contacts);
+        compose.handle_key(KeyCode::Tab.into());
+        assert_eq!(compose.to, "bob@x.com, jane@x.com, ");
+        // no suggestions, tab moves on
+        compose.update_suggestions(&contacts);
+        compose.handle_key(KeyCode::Tab.into());
+        assert_eq!(compose.field, Field::Cc);
+    }
+}
@@ -0,0 +1,107 @@
+use crate::keymap::KeysConfig;
+use crate::message_collection::DEFAULT_BODY_CACHE;
+use crate::theme::ThemeConfig;
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::path::PathBuf;
+use std::sync::Mutex;
+
+/// Set to a file path to log the IMAP and SMTP traffic there, for bug
+/// reports. Passwords are left out.
+const TRACE_VAR: &str = "EMAIL_TUI_TRACE";
+
+/// Settings read from `config.toml` in the `email-tui` config directory,
+/// anything left out keeps its default.
+#[derive(Debug, Deserialize)]
+#[serde(default)]
+pub struct Config {
+    pub theme: ThemeConfig,
+    /// Keys for the actions in `keymap::Action`, by their snake_case name.
+    pub keys: KeysConfig,
+    /// Ask before quitting.
+    pub confirm_quit: bool,
+    /// How many read message bodies to keep in memory, 0 turns it off.
+    pub body_cache: usize,
+    /// Shown next to your address in the `From:` of sent mail.
+    pub display_name: Option<String>,
+    /// Ask before opening a message bigger than this many MB, 0 never asks.
+    pub confirm_download_mb: usize,
+    /// Start with only unread messages listed, `U` toggles.
+    pub unread_only: bool,
+    /// Click to select and scroll with the wheel. Turning it off leaves the
+    /// mouse to the terminal so text can be selected as usual.
+    pub mouse: bool,
+    /// Draw a message's images below it in terminals that can (kitty,
+    /// iTerm2, WezTerm), elsewhere they're listed as `[image: name]`.
+    pub inline_images: bool,
+    /// Show a desktop notification when new mail arrives.
+    pub notify: bool,
+    /// Added under a `-- ` line to everything you write.
+    pub signature: Option<String>,
+    /// Write replies, and the signature, above the quoted message instead
+    /// of below it.
+    pub top_post: bool,
+    /// Names your mail server signs its `Authentication-Results:` with,
+    /// results from anyone else get no trust badge.
+    pub authserv_ids: Vec<String>,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Self {
+            theme: ThemeConfig::default(),
+            keys: KeysConfig::new(),
+            confirm_quit: false,
+            body_cache: DEFAULT_BODY_CACHE,
+            display_name: None,
+            confirm_download_mb: 10,
+            unread_only: false,
+            mouse: true,
+            inline_images: false,
+            notify: false,
+            signature: None,
+            top_post: false,
+            // the IMAP server is Gmail's
+            authserv_ids: vec!["mx.google.com".to_owned()],
+        };
+    }
+}
+
+impl Config {
+    pub fn path() -> Option<PathBuf> {
+        return dirs::config_dir().map(|dir| dir.join("email-tui").join("config.toml"));
+    }
+
+    /// Loads the config file, a missing file gives the defaults.
+    pub fn load() -> Result<Self> {
+        let path = match Self::path() {
+            Some(path) if path.exists() => path,
+            _ => return Ok(Self::default()),
+        };
+        let raw =
+            std::fs::read_to_string(&path).context(format!("Failed to read {}", path.display()))?;
+        return toml::from_str(&raw).context(format!("Invalid config {}", path.display()));
+    }
+}
+
+/// A callback appending the wire protocol to the file in `EMAIL_TUI_TRACE`,
+/// none when it isn't set.
+pub fn wire_trace() -> Result<Option<impl Fn(&str) + Send + Sync + 'static>> {
+    let path = match std::env::var_os(TRACE_VAR) {
+        Some(path) => PathBuf::from(path),
+        None => return Ok(None),
+    };
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(&path)
+        .context(format!("Failed to open trace file {}", path.display()))?;
+    let file = Mutex::new(file);
+    return Ok(Some(move |line: &str| {
+        if let Ok(mut file) = file.lock() {
+            let _ = writeln!(file, "{}", line);
+        }
+    }));
+}
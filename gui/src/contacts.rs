@@ -0,0 +1,220 @@
+use anyhow::{Context, Result};
+use imap::message::{Contact, Message};
+use std::collections::{HashMap, HashSet};
+use std::fmt::Display;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+const MAX_SUGGESTIONS: usize = 5;
+
+/// Every address seen in fetched headers, ranked by how often it was seen.
+/// Saved as TSV lines of `email`, `count` and an optional `name`, removed
+/// addresses have `-` for the count.
+#[derive(Debug, Default)]
+pub struct ContactBook {
+    contacts: HashMap<Box<str>, (Contact, usize)>,
+    /// Lowercased addresses the user removed, headers don't add them back.
+    removed: HashSet<Box<str>>,
+}
+
+impl ContactBook {
+    pub fn default_path() -> Option<PathBuf> {
+        return dirs::data_dir().map(|dir| dir.join("email-tui").join("contacts.tsv"));
+    }
+
+    /// Loads a saved book, a missing file gives an empty one.
+    pub fn load(path: &Path) -> Result<Self> {
+        if !path.exists() {
+            return Ok(Self::default());
+        }
+        let raw =
+            std::fs::read_to_string(path).context(format!("Failed to read {}", path.display()))?;
+        return raw
+            .parse()
+            .context(format!("Invalid address book {}", path.display()));
+    }
+
+    pub fn save(&self, path: &Path) -> Result<()> {
+        if let Some(dir) = path.parent() {
+            std::fs::create_dir_all(dir)?;
+        }
+        std::fs::write(path, self.to_string())
+            .context(format!("Failed to write {}", path.display()))?;
+        return Ok(());
+    }
+
+    pub fn add(&mut self, contact: &Contact) {
+        let key = contact.email.trim().to_lowercase();
+        let entry = self
+            .contacts
+            .entry(key.into())
+            .or_insert_with(|| (contact.clone(), 0));
+        entry.1 += 1;
+        // keep a display name once one turns up
+        if entry.0.name.is_none() {
+            entry.0.name = contact.name.clone();
+        }
+    }
+
+    /// Returns whether there was a contact with that address. It stays out
+    /// of the book when seen again in `add_message`.
+    pub fn remove(&mut self, email: &str) -> bool {
+        let key = email.trim().to_lowercase();
+        let found = self.contacts.remove(key.as_str()).is_some();
+        self.removed.insert(key.into());
+        return found;
+    }
+
+    /// Every contact, most frequent first.
+    pub fn list(&self) -> Vec<&Contact> {
+        return self.ranked(|_| true);
+    }
+
+    fn ranked(&self, filter: impl Fn(&Contact) -> bool) -> Vec<&Contact> {
+        let mut result = self
+            .contacts
+            .values()
+            .filter(|(contact, _)| filter(contact))
+            .collect::<Vec<_>>();
+        result.sort_by(|(a, a_count), (b, b_count)| {
+            b_count.cmp(a_count).then_with(|| a.email.cmp(&b.email))
+        });
+        return result.into_iter().map(|(contact, _)| contact).collect();
+    }
+
+    /// Adds every address in the headers of `message`, except removed ones.
+    pub fn add_message(&mut self, message: &Message) {
+        let lists = [&message.to, &message.cc, &message.bcc];
+        let contacts = message
+            .from
+            .iter()
+            .chain(lists.into_iter().flatten().flat_map(|x| x.iter()));
+        for contact in contacts {
+            let key = contact.email.trim().to_lowercase();
+            if !self.removed.contains(key.as_str()) {
+                self.add(contact);
+            }
+        }
+    }
+
+    /// The most frequent contacts whose address or name starts with `query`,
+    /// or has a word that does.
+    pub fn suggest(&self, query: &str) -> Vec<&Contact> {
+        let query = query.trim().to_lowercase();
+        if query.is_empty() {
+            return vec![];
+        }
+        let matches = |value: &str| {
+            let value = value.trim().to_lowercase();
+            return value
+                .split(|c: char| !c.is_alphanumeric())
+                .any(|word| word.starts_with(&query))
+                || value.starts_with(&query);
+        };
+        let mut result = self.ranked(|contact| {
+            matches(&contact.email) || contact.name.as_deref().is_some_and(matches)
+        });
+        result.truncate(MAX_SUGGESTIONS);
+        return result;
+    }
+}
+
+impl FromStr for ContactBook {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut book = Self::default();
+        for line in s.lines().filter(|x| !x.trim().is_empty()) {
+            let mut fields = line.split('\t');
+            let email = fields.next().unwrap_or("").trim();
+            let mut fields = fields.peekable();
+            if fields.peek().map(|x| x.trim()) == Some("-") {
+                book.removed.insert(email.to_lowercase().into());
+                continue;
+            }
+            let count = fields
+                .next()
+                .and_then(|x| x.trim().parse().ok())
+                .context(format!("Invalid contact {}", line))?;
+            let name = fields.next().map(str::trim).filter(|x| !x.is_empty());
+            let contact = Contact {
+                name: name.map(|x| x.into()),
+                email: email.into(),
+            };
+            book.contacts
+                .insert(email.to_lowercase().into(), (contact, count));
+        }
+        return Ok(book);
+    }
+}
+
+impl Display for ContactBook {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        // a tab or newline in a name would break the line up
+        let clean = |x: &str| x.trim().replace(['\t', '\n', '\r'], " ");
+        let mut entries = self.contacts.values().collect::<Vec<_>>();
+        entries.sort_by(|(a, _), (b, _)| a.email.cmp(&b.email));
+        for (contact, count) in entries {
+            write!(f, "{}\t{}", clean(&contact.email), count)?;
+            if let Some(name) = &contact.name {
+                write!(f, "\t{}", clean(name))?;
+            }
+            writeln!(f)?;
+        }
+        let mut removed = self.removed.iter().collect::<Vec<_>>();
+        removed.sort();
+        for email in removed {
+            writeln!(f, "{}\t-", clean(email))?;
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_suggest() {
+        let mut book = ContactBook::default();
+        for raw in [
+            "Jane Doe <jane@x.com>",
+            "jack@x.com",
+            "Jane Doe <JANE@x.com>",
+        ] {
+            book.add(&raw.parse().unwrap());
+        }
+        let suggestions = book.suggest("ja");
+        assert_eq!(suggestions.len(), 2);
+        assert_eq!(suggestions[0].email.to_lowercase(), "jane@x.com");
+        assert_eq!(book.suggest("doe").len(), 1);
+        assert!(book.suggest("zed").is_empty());
+    }
+
+    #[test]
+    fn test_contact_book_round_trip() {
+        let mut book = ContactBook::default();
+        for raw in ["Jane Doe <jane@x.com>", "jack@x.com", "jane@x.com"] {
+            book.add(&raw.parse().unwrap());
+        }
+        let saved = book.to_string();
+        assert_eq!(saved, "jack@x.com\t1\njane@x.com\t2\tJane Doe\n");
+
+        let mut loaded: ContactBook = saved.parse().unwrap();
+        let list = loaded.list();
+        assert_eq!(list[0].name.as_deref(), Some("Jane Doe"));
+        assert_eq!(&*list[1].email, "jack@x.com");
+        assert!(loaded.remove("JACK@x.com"));
+        assert_eq!(loaded.list().len(), 1);
+
+        // a removed address isn't added back by the next fetch, even after
+        // the book is saved and loaded
+        let mut loaded: ContactBook = loaded.to_string().parse().unwrap();
+        assert!(loaded.to_string().ends_with("jack@x.com\t-\n"));
+        let raw = "* 1 FETCH (BODY[HEADER.FIELDS (FROM TO)] {0}\r\nFrom: jack@x.com\r\nTo: jane@x.com\r\n";
+        loaded.add_message(&raw.parse().unwrap());
+        assert_eq!(loaded.list().len(), 1);
+        assert_eq!(loaded.suggest("jane").len(), 1);
+        assert!(loaded.suggest("jack").is_empty());
+    }
+}
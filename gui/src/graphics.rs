@@ -0,0 +1,352 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+use std::time::Duration;
+
+// kitty takes the base64 payload in pieces of at most this many bytes
+const KITTY_CHUNK: usize = 4096;
+// a kitty graphics query followed by DA1, every terminal answers the latter
+// so once its reply is in there's nothing more to wait for
+const QUERY: &str = "\x1b_Gi=31,s=1,v=1,a=q,t=d,f=24;AAAA\x1b\\\x1b[c";
+// how long a terminal gets to answer the query before the variables decide
+const PROBE_TIMEOUT: Duration = Duration::from_millis(200);
+// the cell size assumed when the terminal doesn't report its pixels
+const CELL_PIXELS: (usize, usize) = (10, 20);
+
+/// A terminal graphics protocol images can be drawn with.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    Kitty,
+    /// iTerm2's inline images, also understood by WezTerm.
+    Iterm,
+    Sixel,
+}
+
+impl Protocol {
+    /// Asks the terminal what it can draw, falling back to the variables it
+    /// sets if it doesn't answer in time. Expects raw mode to be enabled.
+    /// Inside tmux the sequences would need wrapping, so it's left out.
+    pub fn detect() -> Option<Self> {
+        if std::env::var_os("TMUX").is_some() {
+            return None;
+        }
+        return query()
+            .and_then(|reply| parse_reply(&reply))
+            .or_else(detect_env);
+    }
+
+    /// Whether an `IMAGE/<file_type>` part can be sent as is.
+    pub fn supports(&self, file_type: &str) -> bool {
+        return match self {
+            // kitty decodes PNG itself, anything else needs raw pixels
+            Self::Kitty => file_type.eq_ignore_ascii_case("PNG"),
+            Self::Iterm => true,
+            Self::Sixel => file_type.eq_ignore_ascii_case("PNG"),
+        };
+    }
+
+    /// The sequence drawing `image` at the cursor, scaled into `columns` by
+    /// `rows` cells.
+    pub fn draw(&self, image: &[u8], columns: u16, rows: u16) -> String {
+        let payload = match self {
+            Self::Sixel => String::new(),
+            _ => STANDARD.encode(image),
+        };
+        return match self {
+            Self::Kitty => {
+                let chunks = payload.as_bytes().chunks(KITTY_CHUNK).collect::<Vec<_>>();
+                let mut result = String::new();
+                for (i, chunk) in chunks.iter().enumerate() {
+                    let more = u8::from(i + 1 < chunks.len());
+                    // q=2 keeps the terminal from answering into our input
+                    let control = match i {
+                        0 => format!("a=T,f=100,q=2,C=1,c={},r={},m={}", columns, rows, more),
+                        _ => format!("m={}", more),
+                    };
+                    let chunk = std::str::from_utf8(chunk).unwrap_or_default();
+                    result.push_str(&format!("\x1b_G{};{}\x1b\\", control, chunk));
+                }
+                result
+            }
+            Self::Iterm => format!(
+                "\x1b]1337;File=inline=1;size={};width={};height={};preserveAspectRatio=1:{}\x07",
+                image.len(),
+                columns,
+                rows,
+                payload
+            ),
+            Self::Sixel => {
+                let (width, height) = cell_pixels();
+                let width = usize::from(columns) * width;
+                let height = usize::from(rows) * height;
+                sixel(image, width, height).unwrap_or_default()
+            }
+        };
+    }
+
+    /// The sequence removing everything drawn, empty if redrawing the cells
+    /// is enough.
+    pub fn clear(&self) -> &'static str {
+        return match self {
+            Self::Kitty => "\x1b_Ga=d,q=2\x1b\\",
+            Self::Iterm | Self::Sixel => "",
+        };
+    }
+}
+
+/// Guesses from the variables the terminal sets.
+fn detect_env() -> Option<Protocol> {
+    let var = |name| std::env::var(name).unwrap_or_default();
+    if std::env::var_os("KITTY_WINDOW_ID").is_some()
+        || var("TERM").contains("kitty")
+        || var("TERM_PROGRAM") == "ghostty"
+    {
+        return Some(Protocol::Kitty);
+    }
+    return match var("TERM_PROGRAM").as_str() {
+        "iTerm.app" | "WezTerm" => Some(Protocol::Iterm),
+        _ => None,
+    };
+}
+
+/// Sends `QUERY` and collects the answer up to the DA1 reply, none if the
+/// terminal stays quiet for `PROBE_TIMEOUT`.
+#[cfg(unix)]
+fn query() -> Option<Vec<u8>> {
+    use ratatui::crossterm::terminal;
+    use std::io::Write;
+    use std::time::Instant;
+
+    // SAFETY: isatty only inspects the descriptor
+    if unsafe { libc::isatty(libc::STDIN_FILENO) } != 1 {
+        return None;
+    }
+    let raw = terminal::is_raw_mode_enabled().ok()?;
+    if !raw {
+        terminal::enable_raw_mode().ok()?;
+    }
+    let mut stdout = std::io::stdout();
+    let mut reply = Vec::new();
+    let sent = stdout
+        .write_all(QUERY.as_bytes())
+        .and_then(|_| stdout.flush());
+    let deadline = Instant::now() + PROBE_TIMEOUT;
+    while sent.is_ok() && da1(&reply).is_none() {
+        let left = deadline.saturating_duration_since(Instant::now());
+        let mut fd = libc::pollfd {
+            fd: libc::STDIN_FILENO,
+            events: libc::POLLIN,
+            revents: 0,
+        };
+        // SAFETY: fd points at a single pollfd
+        let ready = unsafe { libc::poll(&mut fd, 1, left.as_millis() as libc::c_int) };
+        if ready <= 0 {
+            break;
+        }
+        let mut buffer = [0u8; 256];
+        // SAFETY: buffer is valid for its length
+        let read =
+            unsafe { libc::read(libc::STDIN_FILENO, buffer.as_mut_ptr().cast(), buffer.len()) };
+        if read <= 0 {
+            break;
+        }
+        reply.extend_from_slice(&buffer[..read as usize]);
+    }
+    if !raw {
+        let _ = terminal::disable_raw_mode();
+    }
+    return da1(&reply).is_some().then_some(reply);
+}
+
+#[cfg(not(unix))]
+fn query() -> Option<Vec<u8>> {
+    return None;
+}
+
+/// The parameters of the DA1 reply `ESC [ ? … c` in `reply`, if complete.
+fn da1(reply: &[u8]) -> Option<Vec<&str>> {
+    let start = reply.windows(3).position(|w| w == b"\x1b[?")? + 3;
+    let len = reply[start..].iter().position(|&b| b == b'c')?;
+    let params = std::str::from_utf8(&reply[start..start + len]).ok()?;
+    return Some(params.split(';').collect());
+}
+
+/// Picks the protocol from the answer to `QUERY`, kitty if the graphics
+/// query was acknowledged, sixel if DA1 lists it.
+fn parse_reply(reply: &[u8]) -> Option<Protocol> {
+    let ok = b"\x1b_Gi=31;OK";
+    if reply.windows(ok.len()).any(|w| w == ok) {
+        return Some(Protocol::Kitty);
+    }
+    return match da1(reply)?.contains(&"4") {
+        true => Some(Protocol::Sixel),
+        false => None,
+    };
+}
+
+/// The size of a cell in pixels, as reported by the terminal.
+fn cell_pixels() -> (usize, usize) {
+    return match ratatui::crossterm::terminal::window_size() {
+        Ok(size) if size.columns > 0 && size.rows > 0 && size.width > 0 && size.height > 0 => (
+            usize::from(size.width / size.columns),
+            usize::from(size.height / size.rows),
+        ),
+        _ => CELL_PIXELS,
+    };
+}
+
+/// Decodes a PNG into its width, height and RGBA pixels.
+fn decode_png(image: &[u8]) -> Option<(usize, usize, Vec<[u8; 4]>)> {
+    let mut decoder = png::Decoder::new(image);
+    decoder.set_transformations(png::Transformations::EXPAND | png::Transformations::STRIP_16);
+    let mut reader = decoder.read_info().ok()?;
+    let mut buffer = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut buffer).ok()?;
+    let pixels = buffer[..info.buffer_size()]
+        .chunks(info.color_type.samples())
+        .map(|pixel| match *pixel {
+            [v] => [v, v, v, 255],
+            [v, a] => [v, v, v, a],
+            [r, g, b] => [r, g, b, 255],
+            [r, g, b, a] => [r, g, b, a],
+            _ => [0; 4],
+        })
+        .collect();
+    return Some((info.width as usize, info.height as usize, pixels));
+}
+
+/// Encodes a PNG as sixel, scaled to fit `width` by `height` pixels and
+/// reduced to a 6×6×6 colour cube. Transparent pixels are left untouched.
+fn sixel(image: &[u8], width: usize, height: usize) -> Option<String> {
+    let (source_width, source_height, pixels) = decode_png(image)?;
+    if source_width == 0 || source_height == 0 || width == 0 || height == 0 {
+        return None;
+    }
+    let scale = f64::min(
+        width as f64 / source_width as f64,
+        height as f64 / source_height as f64,
+    );
+    let width = ((source_width as f64 * scale) as usize).max(1);
+    let height = ((source_height as f64 * scale) as usize).max(1);
+    let level = |v: u8| usize::from(v) * 5 / 255;
+    let mut grid = Vec::with_capacity(width * height);
+    for y in 0..height {
+        for x in 0..width {
+            let [r, g, b, a] =
+                pixels[y * source_height / height * source_width + x * source_width / width];
+            grid.push((a >= 128).then(|| 36 * level(r) + 6 * level(g) + level(b)));
+        }
+    }
+
+    // P2=1 keeps the pixels no colour is drawn into
+    let mut result = format!("\x1bP0;1;0q\"1;1;{};{}", width, height);
+    let mut used = grid.iter().flatten().copied().collect::<Vec<_>>();
+    used.sort_unstable();
+    used.dedup();
+    for &colour in &used {
+        let percent = |level: usize| level * 20;
+        result.push_str(&format!(
+            "#{};2;{};{};{}",
+            colour,
+            percent(colour / 36),
+            percent(colour / 6 % 6),
+            percent(colour % 6)
+        ));
+    }
+    for top in (0..height).step_by(6) {
+        let band = top..(top + 6).min(height);
+        let mut colours = band
+            .clone()
+            .flat_map(|y| grid[y * width..(y + 1) * width].iter().flatten().copied())
+            .collect::<Vec<_>>();
+        colours.sort_unstable();
+        colours.dedup();
+        for colour in colours {
+            result.push_str(&format!("#{}", colour));
+            let mut run = (b'?', 0);
+            for x in 0..width {
+                let bits = band
+                    .clone()
+                    .filter(|y| grid[y * width + x] == Some(colour))
+                    .fold(0, |bits, y| bits | 1 << (y - top));
+                let byte = b'?' + bits;
+                if byte != run.0 {
+                    push_run(&mut result, run);
+                    run = (byte, 0);
+                }
+                run.1 += 1;
+            }
+            push_run(&mut result, run);
+            // back to the start of the band for the next colour
+            result.push('$');
+        }
+        result.push('-');
+    }
+    result.push_str("\x1b\\");
+    return Some(result);
+}
+
+/// Appends `count` sixels `byte`, run-length encoded when that's shorter.
+fn push_run(result: &mut String, (byte, count): (u8, usize)) {
+    match count {
+        0..=3 => result.extend(std::iter::repeat_n(byte as char, count)),
+        _ => result.push_str(&format!("!{}{}", count, byte as char)),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_draw() {
+        assert_eq!(
+            Protocol::Kitty.draw(b"hi", 10, 5),
+            "\x1b_Ga=T,f=100,q=2,C=1,c=10,r=5,m=0;aGk=\x1b\\"
+        );
+        // 3 bytes encode to 4, this splits into two chunks
+        let big = Protocol::Kitty.draw(&[0; KITTY_CHUNK / 4 * 3 + 1], 1, 1);
+        assert!(big.contains(",m=1;"));
+        assert!(big.ends_with("\x1b_Gm=0;AA==\x1b\\"));
+
+        assert_eq!(
+            Protocol::Iterm.draw(b"hi", 10, 5),
+            "\x1b]1337;File=inline=1;size=2;width=10;height=5;preserveAspectRatio=1:aGk=\x07"
+        );
+        assert!(!Protocol::Kitty.supports("JPEG"));
+    }
+
+    #[test]
+    fn test_parse_reply() {
+        let kitty = b"\x1b_Gi=31;OK\x1b\\\x1b[?62;22c";
+        assert_eq!(parse_reply(kitty), Some(Protocol::Kitty));
+        let refused = b"\x1b_Gi=31;ENOTSUPPORTED\x1b\\\x1b[?62;4;22c";
+        assert_eq!(parse_reply(refused), Some(Protocol::Sixel));
+        assert_eq!(parse_reply(b"\x1b[?64;1;4c"), Some(Protocol::Sixel));
+        assert_eq!(parse_reply(b"\x1b[?1;2c"), None);
+        assert_eq!(da1(b"\x1b[?1;2"), None);
+        assert_eq!(da1(b"junk\x1b[?1;2c"), Some(vec!["1", "2"]));
+    }
+
+    #[test]
+    fn test_sixel() {
+        // 2×2: red, transparent / white, black
+        let mut image = Vec::new();
+        let mut encoder = png::Encoder::new(&mut image, 2, 2);
+        encoder.set_color(png::ColorType::Rgba);
+        let mut writer = encoder.write_header().unwrap();
+        let pixels = [255, 0, 0, 255, 0, 0, 0, 0, 255, 255, 255, 255, 0, 0, 0, 255];
+        writer.write_image_data(&pixels).unwrap();
+        writer.finish().unwrap();
+
+        assert_eq!(
+            sixel(&image, 2, 2).unwrap(),
+            "\x1bP0;1;0q\"1;1;2;2#0;2;0;0;0#180;2;100;0;0#215;2;100;100;100\
+            #0?A$#180@?$#215A?$-\x1b\\"
+        );
+        // scaled to fit, keeping the aspect ratio
+        assert!(sixel(&image, 8, 4)
+            .unwrap()
+            .starts_with("\x1bP0;1;0q\"1;1;4;4"));
+        assert_eq!(sixel(b"not a png", 2, 2), None);
+        assert!(Protocol::Sixel.clear().is_empty());
+    }
+}
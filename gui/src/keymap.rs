@@ -0,0 +1,247 @@
+use anyhow::{bail, Context, Result};
+use ratatui::crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::str::FromStr;
+
+/// Something a key in the message view does, named in the `[keys]` section
+/// of the config, e.g. `move_down = ["j", "down"]`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Quit,
+    Open,
+    MoveDown,
+    MoveUp,
+    FocusLeft,
+    FocusRight,
+    FocusNext,
+    FocusPrev,
+    First,
+    Last,
+    NextPage,
+    PrevPage,
+    Compose,
+    Reply,
+    Delete,
+    Purge,
+    Undo,
+    Select,
+    MarkRead,
+    MarkAllRead,
+    Flag,
+    CopyBody,
+    CopySender,
+    Unsubscribe,
+    AddressBook,
+    ToggleSource,
+    ToggleHeaders,
+    TogglePreviews,
+    UnreadOnly,
+    Drafts,
+    Quota,
+    Search,
+    ClearSearch,
+    NextMatch,
+    PrevMatch,
+    Narrower,
+    Wider,
+}
+
+/// The `[keys]` section, each action listed replaces all of its default
+/// keys.
+pub type KeysConfig = HashMap<Action, Vec<String>>;
+
+/// A key with the modifiers that matter, written like `ctrl-h`, `G`, `enter`
+/// or `space`. Shift is part of the character rather than a modifier.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct KeyCombo {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl KeyCombo {
+    fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        // terminals differ on whether shift comes along with `G` or BackTab
+        let modifiers = modifiers & (KeyModifiers::CONTROL | KeyModifiers::ALT);
+        return Self { code, modifiers };
+    }
+}
+
+impl FromStr for KeyCombo {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = s;
+        // `-` on its own is a key, not a separator
+        while let Some((modifier, key)) = rest.split_once('-').filter(|(_, key)| !key.is_empty()) {
+            modifiers |= match modifier.to_ascii_lowercase().as_str() {
+                "ctrl" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                _ => bail!("Unknown modifier {} in {}", modifier, s),
+            };
+            rest = key;
+        }
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => match rest.to_ascii_lowercase().as_str() {
+                "enter" => KeyCode::Enter,
+                "esc" => KeyCode::Esc,
+                "tab" => KeyCode::Tab,
+                "backtab" => KeyCode::BackTab,
+                "space" => KeyCode::Char(' '),
+                "backspace" => KeyCode::Backspace,
+                "up" => KeyCode::Up,
+                "down" => KeyCode::Down,
+                "left" => KeyCode::Left,
+                "right" => KeyCode::Right,
+                "home" => KeyCode::Home,
+                "end" => KeyCode::End,
+                "pageup" => KeyCode::PageUp,
+                "pagedown" => KeyCode::PageDown,
+                _ => bail!("Unknown key {}", s),
+            },
+        };
+        return Ok(Self::new(code, modifiers));
+    }
+}
+
+/// Which action each key runs.
+#[derive(Debug)]
+pub struct Keymap {
+    bindings: HashMap<KeyCombo, Action>,
+}
+
+impl Keymap {
+    /// The keys used when the config doesn't say otherwise.
+    fn defaults() -> Vec<(Action, &'static [&'static str])> {
+        return vec![
+            (Action::Quit, &["q"]),
+            (Action::Open, &["enter"]),
+            (Action::MoveDown, &["j"]),
+            (Action::MoveUp, &["k"]),
+            (Action::FocusLeft, &["ctrl-h"]),
+            (Action::FocusRight, &["ctrl-l"]),
+            (Action::FocusNext, &["tab"]),
+            (Action::FocusPrev, &["backtab"]),
+            (Action::First, &["g"]),
+            (Action::Last, &["G"]),
+            (Action::NextPage, &["ctrl-f"]),
+            (Action::PrevPage, &["ctrl-b"]),
+            (Action::Compose, &["c"]),
+            (Action::Reply, &["r"]),
+            (Action::Delete, &["d"]),
+            (Action::Purge, &["X"]),
+            (Action::Undo, &["u"]),
+            (Action::Select, &["space"]),
+            (Action::MarkRead, &["m"]),
+            (Action::MarkAllRead, &["R"]),
+            (Action::Flag, &["s"]),
+            (Action::CopyBody, &["y"]),
+            (Action::CopySender, &["Y"]),
+            (Action::Unsubscribe, &["L"]),
+            (Action::AddressBook, &["a"]),
+            (Action::ToggleSource, &["S"]),
+            (Action::ToggleHeaders, &["h"]),
+            (Action::TogglePreviews, &["p"]),
+            (Action::UnreadOnly, &["U"]),
+            (Action::Drafts, &["D"]),
+            (Action::Quota, &["Q"]),
+            (Action::Search, &["/"]),
+            (Action::ClearSearch, &["esc"]),
+            (Action::NextMatch, &["n"]),
+            (Action::PrevMatch, &["N"]),
+            (Action::Narrower, &["<"]),
+            (Action::Wider, &[">"]),
+        ];
+    }
+
+    /// The default keys with `config` applied, a key the config gives to
+    /// another action no longer runs its default one.
+    pub fn from_config(config: &KeysConfig) -> Result<Self> {
+        let mut bindings = HashMap::new();
+        for (action, keys) in Self::defaults() {
+            if !config.contains_key(&action) {
+                for key in keys {
+                    bindings.insert(key.parse()?, action);
+                }
+            }
+        }
+        for (action, keys) in config {
+            for key in keys {
+                let combo = key
+                    .parse()
+                    .context(format!("Invalid key for {:?}", action))?;
+                bindings.insert(combo, *action);
+            }
+        }
+        return Ok(Self { bindings });
+    }
+
+    pub fn action(&self, key: KeyEvent) -> Option<Action> {
+        return self
+            .bindings
+            .get(&KeyCombo::new(key.code, key.modifiers))
+            .copied();
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_key_combo() {
+        let combo: KeyCombo = "ctrl-h".parse().unwrap();
+        assert_eq!(
+            combo,
+            KeyCombo::new(KeyCode::Char('h'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            "-".parse::<KeyCombo>().unwrap(),
+            KeyCombo::new(KeyCode::Char('-'), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            "alt--".parse::<KeyCombo>().unwrap(),
+            KeyCombo::new(KeyCode::Char('-'), KeyModifiers::ALT)
+        );
+        assert!("super-h".parse::<KeyCombo>().is_err());
+        assert!("nope".parse::<KeyCombo>().is_err());
+    }
+
+    #[test]
+    fn test_keymap_from_config() {
+        let config: KeysConfig =
+            toml::from_str("move_down = [\"down\", \"ctrl-n\"]\nquit = [\"j\"]").unwrap();
+        let keymap = Keymap::from_config(&config).unwrap();
+        let press = |code, modifiers| keymap.action(KeyEvent::new(code, modifiers));
+        assert_eq!(
+            press(KeyCode::Down, KeyModifiers::NONE),
+            Some(Action::MoveDown)
+        );
+        assert_eq!(
+            press(KeyCode::Char('n'), KeyModifiers::CONTROL),
+            Some(Action::MoveDown)
+        );
+        assert_eq!(
+            press(KeyCode::Char('j'), KeyModifiers::NONE),
+            Some(Action::Quit)
+        );
+        assert_eq!(press(KeyCode::Char('q'), KeyModifiers::NONE), None);
+        // shift comes with capitals in some terminals
+        assert_eq!(
+            press(KeyCode::Char('G'), KeyModifiers::SHIFT),
+            Some(Action::Last)
+        );
+        assert_eq!(
+            press(KeyCode::Char('h'), KeyModifiers::NONE),
+            Some(Action::ToggleHeaders)
+        );
+
+        assert!(toml::from_str::<KeysConfig>("fly = [\"f\"]").is_err());
+        let config: KeysConfig = toml::from_str("quit = [\"hyper-q\"]").unwrap();
+        assert!(Keymap::from_config(&config).is_err());
+    }
+}
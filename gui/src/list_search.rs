@@ -0,0 +1,150 @@
+use anyhow::{bail, Context, Result};
+use imap::{SearchDate, SearchQuery};
+
+/// A server side search of the message list. The query is words optionally
+/// scoped with a `from:`, `to:`, `subject:` or `body:` prefix, a prefix
+/// applies until the next one, unscoped words match anywhere.
+///
+/// `since:`, `before:` and `on:` take a `YYYY-MM-DD` date or a number of
+/// days or weeks ago like `7d` or `2w`.
+#[derive(Debug, Default)]
+pub struct ListSearch {
+    pub query: String,
+    /// Whether the query is still being typed.
+    pub typing: bool,
+    /// How many messages matched the applied query.
+    pub results: Option<usize>,
+}
+
+impl ListSearch {
+    pub fn start(&mut self) {
+        self.query.clear();
+        self.typing = true;
+    }
+
+    /// Shown in the status bar, e.g. `from:bob (3 results)`.
+    pub fn status(&self) -> String {
+        return match self.results {
+            Some(count) if !self.typing => {
+                format!("{} ({} results, Esc clears)", self.query, count)
+            }
+            _ => format!("search: {}", self.query),
+        };
+    }
+
+    pub fn to_query(&self) -> Result<SearchQuery> {
+        return parse_query(&self.query);
+    }
+}
+
+fn parse_query(input: &str) -> Result<SearchQuery> {
+    let mut terms = vec![];
+    let mut field = "all";
+    let mut words = vec![];
+    for word in input.split_whitespace() {
+        match word.split_once(':') {
+            Some((name, value)) if is_field(name) => {
+                if !words.is_empty() {
+                    terms.push(term(field, &words.join(" "))?);
+                }
+                field = name;
+                words = vec![value].into_iter().filter(|x| !x.is_empty()).collect();
+            }
+            _ => words.push(word),
+        }
+    }
+    if !words.is_empty() {
+        terms.push(term(field, &words.join(" "))?);
+    }
+
+    if terms.len() == 1 {
+        return Ok(terms.remove(0));
+    }
+    if terms.is_empty() {
+        bail!("Nothing to search for");
+    }
+    return Ok(SearchQuery::And(terms.into()));
+}
+
+fn is_field(name: &str) -> bool {
+    return [
+        "from", "to", "subject", "body", "all", "since", "before", "on",
+    ]
+    .iter()
+    .any(|x| x.eq_ignore_ascii_case(name));
+}
+
+fn term(field: &str, value: &str) -> Result<SearchQuery> {
+    let value = value.into();
+    return Ok(match field.to_ascii_lowercase().as_str() {
+        "from" => SearchQuery::From(value),
+        "to" => SearchQuery::To(value),
+        "subject" => SearchQuery::Subject(value),
+        "body" => SearchQuery::Body(value),
+        "all" => SearchQuery::Text(value),
+        "since" => SearchQuery::Since(parse_date(&value)?),
+        "before" => SearchQuery::Before(parse_date(&value)?),
+        "on" => SearchQuery::On(parse_date(&value)?),
+        _ => bail!("Unknown search field {}", field),
+    });
+}
+
+fn parse_date(value: &str) -> Result<SearchDate> {
+    let days = |count: &str, unit| {
+        let count = count
+            .parse::<i64>()
+            .context(format!("Invalid date {}", value))?;
+        // 10,000 years is past anything dated and keeps the calendar math from
+        // overflowing
+        return match count.checked_mul(unit) {
+            Some(days) if days.abs() <= 3_652_425 => Ok(SearchDate::today().days_before(days)),
+            _ => bail!("Date {} is too far away", value),
+        };
+    };
+    if let Some(count) = value.strip_suffix('d') {
+        return days(count, 1);
+    }
+    if let Some(count) = value.strip_suffix('w') {
+        return days(count, 7);
+    }
+    return value.parse();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_query() {
+        assert_eq!(
+            parse_query("lunch plans").unwrap(),
+            SearchQuery::Text("lunch plans".into())
+        );
+        assert_eq!(
+            parse_query("Subject:lunch plans from: bob").unwrap(),
+            SearchQuery::And(Box::new([
+                SearchQuery::Subject("lunch plans".into()),
+                SearchQuery::From("bob".into()),
+            ]))
+        );
+        assert_eq!(
+            parse_query("re: hi").unwrap(),
+            SearchQuery::Text("re: hi".into())
+        );
+        assert!(parse_query("from:  ").is_err());
+
+        assert_eq!(
+            parse_query("from:bob since:2024-03-01 before:2024-04-01")
+                .unwrap()
+                .to_string(),
+            r#"(FROM "bob" SINCE 1-Mar-2024 BEFORE 1-Apr-2024)"#
+        );
+        assert_eq!(
+            parse_query("since:1w").unwrap(),
+            SearchQuery::Since(SearchDate::today().days_before(7))
+        );
+        assert!(parse_query("on:tuesday").is_err());
+        assert!(parse_query("since:99999999999w").is_err());
+        assert!(parse_query("since:-9223372036854775807d").is_err());
+    }
+}
@@ -1,5 +1,18 @@
 mod app;
+mod body_cache;
+mod body_search;
+mod clipboard;
+mod compose;
+mod config;
+mod contacts;
+mod graphics;
+mod keymap;
+mod list_search;
 mod message_collection;
+mod outbox;
+mod quote;
+mod theme;
+mod unsubscribe;
 use app::App;
 
 fn main() {
@@ -1,24 +1,104 @@
-use anyhow::Result;
-use imap::{message::Message, IMap};
-use std::ops::Range;
+use crate::body_cache::BodyCache;
+use crate::contacts::ContactBook;
+use anyhow::{bail, Context, Result};
+use imap::{
+    message::{Flag, Message},
+    FileMeta, IMap, Inbox, SearchQuery, SpecialUse,
+};
+use std::ops::{ControlFlow, Range};
+
+pub const DEFAULT_BODY_CACHE: usize = 20;
+
+/// What it takes to reverse an action.
+pub enum Undo {
+    /// A message moved to the Trash, found again by its Message-ID.
+    Trash {
+        message_id: Box<str>,
+        subject: Box<str>,
+        trash: Inbox,
+        origin: Inbox,
+        /// The folder `origin` was opened as, shown again on undo.
+        folder: Option<SpecialUse>,
+    },
+    /// A flag changed on `ids`, which go stale once `take_renumbered` says
+    /// so.
+    Flag {
+        ids: Vec<usize>,
+        flag: Flag,
+        previous: bool,
+    },
+}
 
 pub struct MessageCollection {
     imap: IMap,
     messages: Vec<Message>,
     pub page_size: usize,
     pub current_page: usize,
+    /// Whether to fetch body previews, costs extra round trips per page.
+    pub previews: bool,
+    /// Whether the server is Gmail, labels and thread ids are fetched then.
+    pub gmail: bool,
+    /// The open mailbox, `None` is the INBOX.
+    pub folder: Option<SpecialUse>,
+    /// Addresses from every header fetched so far.
+    pub contacts: ContactBook,
+    /// Recently read bodies so going back to a message is instant.
+    pub bodies: BodyCache,
+    /// Ids matching the applied search, newest first. Pages come from these
+    /// instead of the whole mailbox while set.
+    results: Option<Vec<usize>>,
+    /// Whether ids shifted or the folder changed since `take_renumbered`.
+    renumbered: bool,
+    /// Set when a download was cut off, `imap` is then partway through a
+    /// response and unusable until `reconnect` replaces it.
+    needs_reconnect: bool,
 }
 
 impl MessageCollection {
+    /// Whether the open folder holds your own mail, where who it went to
+    /// says more than who sent it.
+    pub fn outgoing(&self) -> bool {
+        return matches!(self.folder, Some(SpecialUse::Sent | SpecialUse::Drafts));
+    }
+
     pub fn new(imap: IMap, page_size: usize) -> Self {
         return Self {
             imap,
             messages: vec![],
             page_size,
             current_page: 0,
+            previews: false,
+            gmail: false,
+            folder: None,
+            contacts: ContactBook::default(),
+            bodies: BodyCache::new(DEFAULT_BODY_CACHE),
+            results: None,
+            renumbered: false,
+            needs_reconnect: false,
         };
     }
 
+    /// The connection, unless it's been left unusable, see `reconnect`.
+    fn imap(&mut self) -> Result<&mut IMap> {
+        if self.needs_reconnect {
+            bail!("Not connected, waiting to reconnect");
+        }
+        return Ok(&mut self.imap);
+    }
+
+    /// Whether the connection has to be replaced with `reconnect` before
+    /// anything else works.
+    pub fn needs_reconnect(&self) -> bool {
+        return self.needs_reconnect;
+    }
+
+    /// Whether message ids kept elsewhere, e.g. for undo, may now point at
+    /// other messages because some were expunged or another folder opened.
+    /// Resets until the next change.
+    pub fn take_renumbered(&mut self) -> bool {
+        return std::mem::take(&mut self.renumbered);
+    }
+
     pub fn update_page_size(&mut self, h: usize, currently_hovered: usize) -> usize {
         let current_idx = currently_hovered + (self.page_size * self.current_page);
         self.page_size = h;
@@ -33,37 +113,566 @@ impl MessageCollection {
         return start..end;
     }
 
-    pub fn next_page(&mut self) {
+    /// How many messages there are to page through, the search results or
+    /// the whole mailbox. Uses the count from the last `SELECT` or refresh so
+    /// it costs no round trip.
+    pub fn total(&mut self) -> usize {
+        return match &self.results {
+            Some(results) => results.len(),
+            None => self.imap().and_then(|x| x.get_inbox_count()).unwrap_or(0),
+        };
+    }
+
+    /// Moves to the next page unless this is the last one, returns whether
+    /// it moved.
+    pub fn next_page(&mut self) -> bool {
+        if (self.current_page + 1) * self.page_size >= self.total() {
+            return false;
+        }
         self.current_page += 1;
+        return true;
     }
     pub fn prev_page(&mut self) {
         self.current_page = self.current_page.saturating_sub(1);
     }
 
+    /// Checks for mail that arrived since the newest loaded message and
+    /// prepends it, returns how many messages were added.
+    pub fn check_new(&mut self) -> Result<usize> {
+        // new mail shows up once the search is cleared
+        if self.results.is_some() {
+            return Ok(0);
+        }
+        let inbox_count = self.imap()?.refresh_inbox_count()?;
+        // another client deleted these, what's loaded and cached shifts
+        for id in self.imap()?.take_expunged() {
+            self.forget(id);
+        }
+        let newest = match self.messages.first() {
+            Some(x) => x.id,
+            None => return Ok(0),
+        };
+        if inbox_count <= newest {
+            return Ok(0);
+        }
+
+        let ids = (newest + 1..=inbox_count).collect::<Vec<_>>();
+        let mut headers = self.imap()?.fetch_headers(&ids)?;
+        let new = ids
+            .iter()
+            .rev()
+            .filter_map(|id| headers.remove(id))
+            .collect::<Vec<_>>();
+        let count = new.len();
+        new.iter().for_each(|x| self.contacts.add_message(x));
+        self.messages.splice(0..0, new);
+        return Ok(count);
+    }
+
+    /// Switches to the mailbox flagged `special_use`, or the INBOX for `None`.
+    pub fn open_folder(&mut self, special_use: Option<SpecialUse>) -> Result<()> {
+        let inbox = self
+            .imap()?
+            .list_special_use()?
+            .into_iter()
+            .find(|x| match special_use {
+                Some(_) => x.special_use == special_use,
+                None => &*x.name == "INBOX",
+            })
+            .context("Folder not found")?;
+        self.imap()?.select_inbox(inbox)?;
+        self.messages.clear();
+        self.bodies.clear();
+        self.results = None;
+        self.current_page = 0;
+        self.folder = special_use;
+        self.renumbered = true;
+        return Ok(());
+    }
+
+    /// Saves `message` to the Drafts folder, deleting `replaces`, the copy
+    /// it was opened from, see `draft_uid`. Returns the new copy's, when the
+    /// server says what it is.
+    pub fn save_draft(
+        &mut self,
+        message: &str,
+        replaces: Option<(u32, u32)>,
+    ) -> Result<Option<(u32, u32)>> {
+        let drafts = self
+            .imap()?
+            .find_special_use(SpecialUse::Drafts)?
+            .context("No Drafts folder")?;
+        let saved =
+            self.imap()?
+                .append(&drafts.name, &[Flag::Draft, Flag::Seen], message.as_bytes())?;
+        // the UID is only good in the Drafts folder it came from
+        if let Some((validity, uid)) = replaces {
+            if self.folder == Some(SpecialUse::Drafts)
+                && self.imap()?.uid_validity() == Some(validity)
+            {
+                let expunged = self.imap()?.purge_uid(uid)?;
+                self.forget_all(&expunged);
+            }
+        }
+        return Ok(saved);
+    }
+
+    /// The `UIDVALIDITY` and UID of the message at `index`, so it can be
+    /// found after other messages are expunged.
+    pub fn draft_uid(&mut self, index: usize) -> Result<Option<(u32, u32)>> {
+        let id = self.message_id(index)?;
+        let validity = match self.imap()?.uid_validity() {
+            Some(validity) => validity,
+            None => return Ok(None),
+        };
+        return Ok(Some((validity, self.imap()?.fetch_uid(id)?)));
+    }
+
+    /// Server alerts that should be shown to the user, see `IMap::take_alerts`.
+    pub fn take_alerts(&mut self) -> Vec<Box<str>> {
+        return self.imap.take_alerts();
+    }
+
+    /// Storage used and available in MB, from the INBOX quota root.
+    pub fn storage_quota(&mut self) -> Result<(u64, u64)> {
+        let storage = self
+            .imap()?
+            .quota_root("INBOX")?
+            .into_iter()
+            .find(|x| &*x.name == "STORAGE")
+            .context("No storage quota")?;
+        return Ok((storage.usage / 1024, storage.limit / 1024));
+    }
+
+    /// Shows only the messages matching `query`, returns how many there are.
+    pub fn apply_search(&mut self, query: &SearchQuery) -> Result<usize> {
+        let mut ids = self.imap()?.search(query)?;
+        ids.sort_unstable_by(|a, b| b.cmp(a));
+        let count = ids.len();
+        self.results = Some(ids);
+        self.messages.clear();
+        self.current_page = 0;
+        return Ok(count);
+    }
+
+    /// Goes back to the whole mailbox.
+    pub fn clear_search(&mut self) {
+        if self.results.take().is_some() {
+            self.messages.clear();
+            self.current_page = 0;
+        }
+    }
+
+    /// The most recent message loaded, the first one `check_new` adds.
+    pub fn newest(&self) -> Option<&Message> {
+        return self.messages.first();
+    }
+
+    /// Number of messages loaded so far.
+    pub fn loaded(&self) -> usize {
+        return self.messages.len();
+    }
+
+    /// Moves to the page holding `index`, returns its index on that page.
+    pub fn jump_to(&mut self, index: usize) -> usize {
+        let index = index.min(self.total().saturating_sub(1));
+        self.current_page = index / self.page_size;
+        return index % self.page_size;
+    }
+
+    /// Flips `flag` on the message at `index` of the current page, the cached
+    /// flags are only changed once the server accepted it.
+    pub fn toggle_flag(&mut self, index: usize, flag: Flag) -> Result<Undo> {
+        if !self.imap()?.can_store(flag) {
+            bail!("This folder can't keep the {} flag", flag);
+        }
+        let message = self
+            .get_current_page()?
+            .get(index)
+            .context("No message selected")?;
+        let (id, value) = (message.id, !message.flags.get(flag));
+        if value {
+            self.imap()?.add_flags(&[id], &[flag])?;
+        } else {
+            self.imap()?.remove_flags(&[id], &[flag])?;
+        }
+        let index = self.get_range_from_page().start + index;
+        self.messages[index].flags.set(flag, value);
+        return Ok(Undo::Flag {
+            ids: vec![id],
+            flag,
+            previous: !value,
+        });
+    }
+
+    /// Moves the message at `index` of the current page to the Trash, returns
+    /// whether it was moved and how to undo it. Without a Trash, or in it,
+    /// the message stays where it is, `purge` deletes it for good. There's
+    /// no undo if it has no Message-ID to find it by.
+    pub fn trash(&mut self, index: usize) -> Result<(bool, Option<Undo>)> {
+        let message = self
+            .get_current_page()?
+            .get(index)
+            .context("No message selected")?;
+        let (id, message_id) = (message.id, message.message_id.clone());
+        let subject = message.subject.trim().into();
+        let origin = self.imap()?.selected_inbox().cloned();
+        let trash = match self.imap()?.trash_message(id)? {
+            Some(trash) => trash,
+            None => return Ok((false, None)),
+        };
+        self.forget(id);
+        let undo = match (message_id, origin) {
+            (Some(message_id), Some(origin)) => Some(Undo::Trash {
+                message_id,
+                subject,
+                trash,
+                origin,
+                folder: self.folder,
+            }),
+            _ => None,
+        };
+        return Ok((true, undo));
+    }
+
+    /// Reverses `undo`, returns a description of what was undone.
+    pub fn undo(&mut self, undo: Undo) -> Result<String> {
+        match undo {
+            Undo::Trash {
+                message_id,
+                subject,
+                trash,
+                origin,
+                folder,
+            } => {
+                let imap = self.imap()?;
+                imap.select_inbox(trash)?;
+                let query = SearchQuery::Header("Message-ID".into(), message_id);
+                let found = imap.search(&query).and_then(|ids| {
+                    let id = ids.into_iter().max().context("Not found in the Trash")?;
+                    return imap.move_messages(&[id], &origin.name);
+                });
+                // go back even if it wasn't found
+                imap.select_inbox(origin)?;
+                found?;
+                self.renumbered = true;
+                self.folder = folder;
+                self.messages.clear();
+                self.bodies.clear();
+                self.results = None;
+                self.current_page = 0;
+                return Ok(format!("Restored \"{}\" from the Trash", subject));
+            }
+            Undo::Flag {
+                ids,
+                flag,
+                previous,
+            } => {
+                if previous {
+                    self.imap()?.add_flags(&ids, &[flag])?;
+                } else {
+                    self.imap()?.remove_flags(&ids, &[flag])?;
+                }
+                for message in &mut self.messages {
+                    if ids.contains(&message.id) {
+                        message.flags.set(flag, previous);
+                    }
+                }
+                let action = if previous { "set" } else { "cleared" };
+                return Ok(match ids.len() {
+                    1 => format!("{} {} again", flag, action),
+                    n => format!("{} {} again on {} messages", flag, action, n),
+                });
+            }
+        }
+    }
+
+    /// Permanently deletes the message at `index` of the current page.
+    pub fn purge(&mut self, index: usize) -> Result<()> {
+        let id = self.message_id(index)?;
+        self.imap()?.purge_message(id)?;
+        self.forget(id);
+        return Ok(());
+    }
+
+    /// Moves the messages `ids` to the Trash in one go, `false` if there was
+    /// no Trash and they were left alone, see `purge_ids`.
+    pub fn trash_ids(&mut self, ids: &[usize]) -> Result<bool> {
+        if self.imap()?.trash_messages(ids)?.is_none() {
+            return Ok(false);
+        }
+        self.forget_all(ids);
+        return Ok(true);
+    }
+
+    pub fn purge_ids(&mut self, ids: &[usize]) -> Result<()> {
+        self.imap()?.purge_messages(ids)?;
+        self.forget_all(ids);
+        return Ok(());
+    }
+
+    /// Marks `ids` read, returns how to undo it for the ones that weren't.
+    pub fn mark_read_ids(&mut self, ids: &[usize]) -> Result<Option<Undo>> {
+        self.imap()?.add_flags(ids, &[Flag::Seen])?;
+        let mut unread = vec![];
+        for message in &mut self.messages {
+            if ids.contains(&message.id) && !message.flags.seen {
+                message.flags.seen = true;
+                unread.push(message.id);
+            }
+        }
+        return Ok(unread_undo(unread));
+    }
+
+    fn message_id(&mut self, index: usize) -> Result<usize> {
+        return Ok(self
+            .get_current_page()?
+            .get(index)
+            .context("No message selected")?
+            .id);
+    }
+
+    /// Drops expunged messages, highest first so each id is still right
+    /// when its turn comes.
+    fn forget_all(&mut self, ids: &[usize]) {
+        let mut ids = ids.to_vec();
+        ids.sort_unstable_by(|a, b| b.cmp(a));
+        for id in ids {
+            self.forget(id);
+        }
+    }
+
+    /// Drops an expunged message, the ids above it shift down by one.
+    fn forget(&mut self, id: usize) {
+        self.renumbered = true;
+        self.messages.retain(|x| x.id != id);
+        self.bodies.expunged(id);
+        for message in &mut self.messages {
+            if message.id > id {
+                message.id -= 1;
+            }
+        }
+        if let Some(results) = &mut self.results {
+            results.retain(|&x| x != id);
+            results
+                .iter_mut()
+                .filter(|x| **x > id)
+                .for_each(|x| *x -= 1);
+        }
+    }
+
+    /// The raw source of the message at `index` of the current page.
+    pub fn get_source(&mut self, index: usize) -> Result<Box<str>> {
+        let message_id = self
+            .get_current_page()?
+            .get(index)
+            .context("No message selected")?
+            .id;
+        let raw = self.imap()?.fetch_raw(message_id)?;
+        return Ok(String::from_utf8_lossy(&raw).into());
+    }
+
     pub fn get_body(&mut self, index: usize) -> Result<Box<str>> {
-        let message_id = self.get_current_page()?[index].id;
-        return self.imap.read_email(message_id);
+        let mut body = String::new();
+        // never broken, so the body is always whole
+        let _ = self.get_body_streaming(index, |text, _, _| {
+            body.push_str(text);
+            return ControlFlow::Continue(());
+        })?;
+        return Ok(body.into());
+    }
+
+    /// Like `get_body` but hands the text to `sink` as it downloads, see
+    /// `IMap::read_email_streaming`. A cached body comes in one piece.
+    /// When `sink` breaks everything fails until the connection is
+    /// replaced with `reconnect`.
+    pub fn get_body_streaming(
+        &mut self,
+        index: usize,
+        mut sink: impl FnMut(&str, usize, usize) -> ControlFlow<()>,
+    ) -> Result<ControlFlow<()>> {
+        let message_id = self.message_id(index)?;
+        if let Some(body) = self.bodies.get(message_id) {
+            return Ok(sink(body, body.len(), body.len()));
+        }
+        let mut body = String::new();
+        let flow = self
+            .imap()?
+            .read_email_streaming(message_id, |text, read, total| {
+                body.push_str(text);
+                return sink(text, read, total);
+            })?;
+        // a cut off body mustn't be served from the cache later, and the
+        // rest of it is still coming on the connection
+        match flow {
+            ControlFlow::Continue(()) => self.bodies.insert(message_id, body.into()),
+            ControlFlow::Break(()) => self.needs_reconnect = true,
+        }
+        return Ok(flow);
+    }
+
+    /// Swaps in a fresh connection, e.g. after a cancelled download left the
+    /// old one mid-response, and selects the open folder on it again.
+    pub fn reconnect(&mut self, mut imap: IMap) -> Result<()> {
+        // the old connection is only asked which folder was open, nothing is
+        // sent on it
+        let inbox = self
+            .imap
+            .selected_inbox()
+            .cloned()
+            .context("No folder open")?;
+        imap.select_inbox(inbox)?;
+        self.imap = imap;
+        self.needs_reconnect = false;
+        // others may have expunged while the old connection hung
+        self.renumbered = true;
+        return Ok(());
+    }
+
+    /// Marks the whole open folder read, returns how many were unread and
+    /// how to undo it. `progress` gets how many are done out of the total
+    /// as it goes.
+    pub fn mark_all_read(
+        &mut self,
+        progress: impl FnMut(usize, usize),
+    ) -> Result<(usize, Option<Undo>)> {
+        let unread = self.imap()?.search(&SearchQuery::Unseen)?;
+        // rather than `IMap::mark_all_seen`, which can't report progress
+        self.imap()?
+            .add_flags_with_progress(&unread, &[Flag::Seen], progress)?;
+        for message in &mut self.messages {
+            message.flags.seen = true;
+        }
+        return Ok((unread.len(), unread_undo(unread)));
+    }
+
+    /// The image parts of the message at `index`, with their sections.
+    pub fn images(&mut self, index: usize) -> Result<Vec<(Box<str>, FileMeta)>> {
+        let message_id = self.message_id(index)?;
+        let structure = self.imap()?.get_body_structure(message_id)?;
+        return Ok(structure
+            .images()
+            .into_iter()
+            .map(|(section, meta)| (section, meta.clone()))
+            .collect());
+    }
+
+    /// The decoded bytes of one of the `images` of the message at `index`.
+    pub fn fetch_image(
+        &mut self,
+        index: usize,
+        section: &str,
+        meta: &FileMeta,
+    ) -> Result<Box<[u8]>> {
+        let message_id = self.message_id(index)?;
+        return self
+            .imap()?
+            .fetch_section(message_id, section, &meta.encoding);
     }
 
     pub fn get_current_page(&mut self) -> Result<&[Message]> {
         let range = self.get_range_from_page();
-        if range.end <= self.messages.len() {
-            return Ok(&self.messages[range]);
+        // jumps can land several pages past what's loaded
+        while range.end > self.messages.len() {
+            let loaded = self.messages.len();
+            self.load_page()?;
+            if self.messages.len() == loaded {
+                break;
+            }
+        }
+        // the last page can be short
+        let len = self.messages.len();
+        let range = range.start.min(len)..range.end.min(len);
+        if self.previews {
+            self.load_previews(range.clone())?;
+        }
+        if self.gmail {
+            self.load_gmail_info(range.clone())?;
         }
+        return Ok(&self.messages[range]);
+    }
 
-        let inbox_count = self.imap.get_inbox_count()?;
+    fn load_previews(&mut self, range: Range<usize>) -> Result<()> {
+        let missing = self.messages[range.clone()]
+            .iter()
+            .filter(|x| x.preview.is_none())
+            .map(|x| x.id)
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let mut previews = self.imap()?.fetch_previews(&missing)?;
+        for message in &mut self.messages[range] {
+            if message.preview.is_none() {
+                // an empty preview so messages without text aren't refetched
+                message.preview = Some(previews.remove(&message.id).unwrap_or_default());
+            }
+        }
+        return Ok(());
+    }
 
-        let last_loaded = self.messages.last().map(|x| x.id).unwrap_or(inbox_count);
+    fn load_gmail_info(&mut self, range: Range<usize>) -> Result<()> {
+        let missing = self.messages[range.clone()]
+            .iter()
+            .filter(|x| x.gmail.is_none())
+            .map(|x| x.id)
+            .collect::<Vec<_>>();
+        if missing.is_empty() {
+            return Ok(());
+        }
+        let mut info = self.imap()?.fetch_gmail_info(&missing)?;
+        for message in &mut self.messages[range] {
+            if message.gmail.is_none() {
+                message.gmail = Some(info.remove(&message.id).unwrap_or_default());
+            }
+        }
+        return Ok(());
+    }
 
-        let headers = self
-            .imap
-            .get_n_email_headers((last_loaded - 1)..last_loaded - self.page_size - 1)?;
+    fn load_page(&mut self) -> Result<()> {
+        if let Some(results) = &self.results {
+            // continue after the oldest loaded result
+            let start = self
+                .messages
+                .last()
+                .and_then(|last| results.iter().position(|&x| x == last.id))
+                .map_or(0, |x| x + 1);
+            let end = (start + self.page_size).min(results.len());
+            let ids = results[start..end].to_vec();
+            let mut headers = self.imap()?.fetch_headers(&ids)?;
+            let page = ids.iter().filter_map(|id| headers.remove(id));
+            self.messages.extend(page);
+            return Ok(());
+        }
+        // ids count down from the newest, the next page is the ids just
+        // below the oldest one loaded
+        let newest = match self.messages.last() {
+            Some(x) => x.id - 1,
+            None => self.imap()?.get_inbox_count()?,
+        };
+        if newest == 0 {
+            return Ok(());
+        }
+        let oldest = newest.saturating_sub(self.page_size - 1).max(1);
+
+        let headers = self.imap()?.get_n_email_headers(oldest..=newest)?;
 
+        headers.iter().for_each(|x| self.contacts.add_message(x));
         self.messages.extend(headers.iter().rev().cloned());
+        return Ok(());
+    }
+}
 
-        assert!(range.end <= self.messages.len());
-        return Ok(&self.messages[range]);
+/// Marks `ids` unread again, none if nothing changed.
+fn unread_undo(ids: Vec<usize>) -> Option<Undo> {
+    if ids.is_empty() {
+        return None;
     }
+    return Some(Undo::Flag {
+        ids,
+        flag: Flag::Seen,
+        previous: false,
+    });
 }
 
 // #[cfg(test)]
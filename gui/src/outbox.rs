@@ -0,0 +1,215 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use smtp::{Refused, Rejected, SMTP};
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A composed message waiting to be sent. The message is the full RFC822
+/// text so headers and attachments are kept as they were written.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Queued {
+    pub recipients: Vec<String>,
+    pub message: String,
+}
+
+/// What a `flush` did.
+#[derive(Debug, Default)]
+pub struct Flushed {
+    pub sent: usize,
+    /// Recipients the server refused in messages sent to the others.
+    pub rejected: Vec<Rejected>,
+    /// Why each message the server refused for good was moved to `failed/`.
+    pub failed: Vec<String>,
+}
+
+impl Flushed {
+    /// What went wrong, for the status bar, none if everything was sent.
+    pub fn problems(&self) -> Option<String> {
+        let mut problems = vec![];
+        if !self.rejected.is_empty() {
+            let rejected = self.rejected.iter().map(|x| x.to_string());
+            problems.push(format!(
+                "Not delivered to {}",
+                rejected.collect::<Vec<_>>().join(", ")
+            ));
+        }
+        if !self.failed.is_empty() {
+            problems.push(format!(
+                "{} refused, moved to the outbox's failed folder: {}",
+                self.failed.len(),
+                self.failed.join(", ")
+            ));
+        }
+        return match problems.is_empty() {
+            true => None,
+            false => Some(problems.join(". ")),
+        };
+    }
+}
+
+/// Messages that failed to send, kept on disk one file each until a send
+/// succeeds. Ones the server refuses for good are moved to `failed/` so
+/// they don't hold up the rest.
+#[derive(Debug)]
+pub struct Outbox {
+    dir: PathBuf,
+    /// Oldest first, with the file each is saved in.
+    pending: Vec<(PathBuf, Queued)>,
+}
+
+impl Outbox {
+    pub fn default_dir() -> Option<PathBuf> {
+        return dirs::data_dir().map(|dir| dir.join("email-tui").join("outbox"));
+    }
+
+    /// Opens the outbox in `dir`, creating it if needed.
+    pub fn open(dir: &Path) -> Result<Self> {
+        std::fs::create_dir_all(dir).context(format!("Failed to create {}", dir.display()))?;
+        let mut paths = std::fs::read_dir(dir)?
+            .map(|x| Ok(x?.path()))
+            .collect::<Result<Vec<_>>>()?;
+        paths.retain(|x| x.extension().is_some_and(|x| x == "toml"));
+        paths.sort();
+
+        let mut pending = vec![];
+        for path in paths {
+            let raw = std::fs::read_to_string(&path)
+                .context(format!("Failed to read {}", path.display()))?;
+            let queued = toml::from_str(&raw).context(format!("Invalid {}", path.display()))?;
+            pending.push((path, queued));
+        }
+        return Ok(Self {
+            dir: dir.to_owned(),
+            pending,
+        });
+    }
+
+    pub fn pending(&self) -> usize {
+        return self.pending.len();
+    }
+
+    /// Saves a message to be sent by the next `flush`.
+    pub fn enqueue(&mut self, queued: Queued) -> Result<()> {
+        // the time keeps the files in the order they were queued
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH)?.as_nanos();
+        let path = self.dir.join(format!("{:020}.toml", nanos));
+        std::fs::write(&path, toml::to_string(&queued)?)
+            .context(format!("Failed to write {}", path.display()))?;
+        self.pending.push((path, queued));
+        return Ok(());
+    }
+
+    /// Sends the pending messages in order, stopping at the first failure
+    /// that may go away so it can be retried.
+    pub fn flush(&mut self, smtp: &mut SMTP) -> Result<Flushed> {
+        let mut flushed = Flushed::default();
+        while let Some((path, queued)) = self.pending.first() {
+            let recipients = queued.recipients.iter().map(|x| &**x).collect::<Vec<_>>();
+            match smtp.send_raw(&recipients, &queued.message) {
+                Ok(rejected) => {
+                    std::fs::remove_file(path)
+                        .context(format!("Failed to remove {}", path.display()))?;
+                    flushed.rejected.extend(rejected);
+                    flushed.sent += 1;
+                }
+                Err(e) => match e.downcast_ref::<Refused>() {
+                    Some(refused) if refused.permanent => {
+                        self.move_to_failed(path)?;
+                        flushed.failed.push(refused.reason.to_string());
+                    }
+                    _ => return Err(e),
+                },
+            }
+            self.pending.remove(0);
+        }
+        return Ok(flushed);
+    }
+
+    /// Moves a message out of the queue into `failed/`, kept for the user
+    /// to look at.
+    fn move_to_failed(&self, path: &Path) -> Result<()> {
+        let failed = self.dir.join("failed");
+        std::fs::create_dir_all(&failed)
+            .context(format!("Failed to create {}", failed.display()))?;
+        let name = path.file_name().context("No file name")?;
+        std::fs::rename(path, failed.join(name))
+            .context(format!("Failed to move {}", path.display()))?;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_outbox_persists() {
+        let dir = std::env::temp_dir().join(format!("email-tui-outbox-{}", std::process::id()));
+        let first = Queued {
+            recipients: vec!["a@x.com".to_owned()],
+            message: "Subject: one\r\n\r\nbody".to_owned(),
+        };
+        let second = Queued {
+            recipients: vec!["b@x.com".to_owned(), "c@x.com".to_owned()],
+            message: "Subject: two\r\n\r\nbody".to_owned(),
+        };
+        let mut outbox = Outbox::open(&dir).unwrap();
+        outbox.enqueue(first.clone()).unwrap();
+        outbox.enqueue(second.clone()).unwrap();
+
+        let reopened = Outbox::open(&dir).unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(reopened.pending(), 2);
+        assert_eq!(reopened.pending[0].1, first);
+        assert_eq!(reopened.pending[1].1, second);
+    }
+
+    /// Plays back the server's side of a dialog, ignoring what is sent.
+    struct Canned(std::collections::VecDeque<u8>);
+
+    impl std::io::Read for Canned {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            return self.0.read(buf);
+        }
+    }
+
+    impl std::io::Write for Canned {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            return Ok(buf.len());
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn test_flush_moves_refused_aside() {
+        let dir = std::env::temp_dir().join(format!("email-tui-failed-{}", std::process::id()));
+        let mut outbox = Outbox::open(&dir).unwrap();
+        for to in ["gone@x.com", "b@x.com"] {
+            outbox
+                .enqueue(Queued {
+                    recipients: vec![to.to_owned()],
+                    message: "Subject: hi\r\n\r\nbody".to_owned(),
+                })
+                .unwrap();
+        }
+        let replies = "220 ready\r\n250-smtp.x.com\r\n250 AUTH LOGIN\r\n\
+                       334 VXNlcm5hbWU6\r\n334 UGFzc3dvcmQ6\r\n235 OK\r\n\
+                       250 OK\r\n250 OK\r\n550 5.1.1 No such user\r\n250 OK\r\n\
+                       250 OK\r\n250 OK\r\n250 OK\r\n354 Go ahead\r\n250 OK queued\r\n";
+        let stream = Canned(replies.bytes().collect());
+        let mut smtp = SMTP::from_stream(Box::new(stream)).unwrap();
+        smtp.login("a@x.com".into(), "pw").unwrap();
+
+        let flushed = outbox.flush(&mut smtp).unwrap();
+        let failed = std::fs::read_dir(dir.join("failed")).unwrap().count();
+        std::fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(flushed.sent, 1);
+        assert_eq!(flushed.failed.len(), 1);
+        assert_eq!(outbox.pending(), 0);
+        assert_eq!(failed, 1);
+        assert!(flushed.problems().unwrap().contains("No such user"));
+    }
+}
@@ -0,0 +1,109 @@
+use imap::message::Message;
+
+/// Where quoted lines are wrapped, counting the `> ` prefix.
+pub const QUOTE_WIDTH: usize = 72;
+
+/// The line introducing a quote, e.g.
+/// `On Wed, 12 Jun 2024 at 09:31, Jane Doe <jane@x.com> wrote:`.
+pub fn attribution(message: &Message) -> String {
+    let sender = match message.from.first() {
+        Some(contact) => match &contact.name {
+            Some(name) => format!("{} <{}>", name.trim(), contact.email.trim()),
+            None => contact.email.trim().to_owned(),
+        },
+        None => "(unknown sender)".to_owned(),
+    };
+    let date = match (&message.internal_date, &message.date) {
+        (Some(date), _) => Some(date.long()),
+        (None, Some(date)) => Some(date.trim().to_owned()),
+        (None, None) => None,
+    };
+    return match date {
+        Some(date) => format!("On {}, {} wrote:", date, sender),
+        None => format!("{} wrote:", sender),
+    };
+}
+
+/// `body` with every line quoted one level deeper, wrapped at `width`.
+/// Lines already quoted keep their level, so `> hi` becomes `> > hi`.
+pub fn quote(body: &str, width: usize) -> String {
+    let mut lines = vec![];
+    for line in body.replace("\r\n", "\n").trim_end().split('\n') {
+        let (depth, text) = quote_level(line);
+        let prefix = "> ".repeat(depth + 1);
+        if text.is_empty() {
+            lines.push(prefix.trim_end().to_owned());
+            continue;
+        }
+        for part in wrap(text, width.saturating_sub(prefix.len())) {
+            lines.push(format!("{}{}", prefix, part));
+        }
+    }
+    return lines.join("\n");
+}
+
+/// How many `>` a line starts with and the text after them.
+fn quote_level(line: &str) -> (usize, &str) {
+    let mut depth = 0;
+    let mut rest = line;
+    while let Some(tail) = rest.strip_prefix('>') {
+        depth += 1;
+        rest = tail.strip_prefix(' ').unwrap_or(tail);
+    }
+    return (depth, rest.trim_end());
+}
+
+/// Splits `text` at spaces into lines of at most `width` characters, a word
+/// longer than that gets a line of its own.
+fn wrap(text: &str, width: usize) -> Vec<String> {
+    if text.chars().count() <= width {
+        return vec![text.to_owned()];
+    }
+    let mut lines = vec![];
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        if !current.is_empty() && current.chars().count() + 1 + word.chars().count() > width {
+            lines.push(std::mem::take(&mut current));
+        }
+        if !current.is_empty() {
+            current.push(' ');
+        }
+        current.push_str(word);
+    }
+    if !current.is_empty() {
+        lines.push(current);
+    }
+    return lines;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_attribution() {
+        let raw = "* 5 FETCH (INTERNALDATE \"12-Jun-2024 09:31:00 +0200\" BODY[HEADER.FIELDS (SUBJECT FROM)] {0}\r\nSubject: Lunch\r\nFrom: Jane Doe <jane@x.com>\r\n";
+        let message: Message = raw.parse().unwrap();
+        assert_eq!(
+            attribution(&message),
+            "On Wed, 12 Jun 2024 at 09:31, Jane Doe <jane@x.com> wrote:"
+        );
+        let raw = "* 5 FETCH (BODY[HEADER.FIELDS (FROM)] {0}\r\nFrom: jane@x.com\r\n";
+        let message: Message = raw.parse().unwrap();
+        assert_eq!(attribution(&message), "jane@x.com wrote:");
+    }
+
+    #[test]
+    fn test_quote() {
+        assert_eq!(
+            quote("Hi,\r\n\r\n> earlier\r\n>> older\r\n", 72),
+            "> Hi,\n>\n> > earlier\n> > > older"
+        );
+        assert_eq!(
+            quote("one two three four\n> five six", 12),
+            "> one two\n> three four\n> > five six"
+        );
+        // nowhere to break
+        assert_eq!(quote("abcdefghij", 6), "> abcdefghij");
+    }
+}
@@ -0,0 +1,120 @@
+use anyhow::{bail, Context, Result};
+use ratatui::style::{Color, Modifier, Style};
+use serde::Deserialize;
+
+/// The `[theme]` section of the config, a preset with optional overrides.
+/// Colors are anything ratatui can parse, e.g. `blue` or `#1e1e2e`.
+#[derive(Debug, Default, Deserialize)]
+#[serde(default)]
+pub struct ThemeConfig {
+    pub preset: Option<String>,
+    pub selection_bg: Option<String>,
+    pub selection_fg: Option<String>,
+    pub unread_fg: Option<String>,
+    pub border: Option<String>,
+    pub status_bar_bg: Option<String>,
+    pub status_bar_fg: Option<String>,
+}
+
+/// Every color the UI uses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Theme {
+    pub selection_bg: Color,
+    pub selection_fg: Color,
+    pub unread_fg: Color,
+    pub border: Color,
+    pub status_bar_bg: Color,
+    pub status_bar_fg: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        return Self::dark();
+    }
+}
+
+impl Theme {
+    pub fn dark() -> Self {
+        return Self {
+            selection_bg: Color::Blue,
+            selection_fg: Color::Reset,
+            unread_fg: Color::Reset,
+            border: Color::DarkGray,
+            status_bar_bg: Color::Blue,
+            status_bar_fg: Color::Reset,
+        };
+    }
+
+    pub fn light() -> Self {
+        return Self {
+            selection_bg: Color::LightBlue,
+            selection_fg: Color::Black,
+            unread_fg: Color::Black,
+            border: Color::Gray,
+            status_bar_bg: Color::LightBlue,
+            status_bar_fg: Color::Black,
+        };
+    }
+
+    pub fn from_config(config: &ThemeConfig) -> Result<Self> {
+        let mut theme = match config.preset.as_deref() {
+            None | Some("dark") => Self::dark(),
+            Some("light") => Self::light(),
+            Some(x) => bail!("Unknown theme preset {}", x),
+        };
+        let overrides = [
+            (&config.selection_bg, &mut theme.selection_bg),
+            (&config.selection_fg, &mut theme.selection_fg),
+            (&config.unread_fg, &mut theme.unread_fg),
+            (&config.border, &mut theme.border),
+            (&config.status_bar_bg, &mut theme.status_bar_bg),
+            (&config.status_bar_fg, &mut theme.status_bar_fg),
+        ];
+        for (value, color) in overrides {
+            if let Some(value) = value {
+                *color = value
+                    .parse()
+                    .ok()
+                    .context(format!("Invalid theme color {}", value))?;
+            }
+        }
+        return Ok(theme);
+    }
+
+    pub fn selection(&self) -> Style {
+        return Style::default().bg(self.selection_bg).fg(self.selection_fg);
+    }
+
+    pub fn unread(&self) -> Style {
+        return Style::default()
+            .fg(self.unread_fg)
+            .add_modifier(Modifier::BOLD);
+    }
+
+    pub fn border(&self) -> Style {
+        return Style::default().fg(self.border);
+    }
+
+    pub fn status_bar(&self) -> Style {
+        return Style::default()
+            .bg(self.status_bar_bg)
+            .fg(self.status_bar_fg);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_theme_from_config() {
+        let config: ThemeConfig =
+            toml::from_str("preset = \"light\"\nselection_bg = \"#102030\"").unwrap();
+        let theme = Theme::from_config(&config).unwrap();
+        assert_eq!(theme.selection_bg, Color::Rgb(0x10, 0x20, 0x30));
+        assert_eq!(theme.status_bar_bg, Theme::light().status_bar_bg);
+
+        let config: ThemeConfig = toml::from_str("border = \"not a color\"").unwrap();
+        assert!(Theme::from_config(&config).is_err());
+    }
+}
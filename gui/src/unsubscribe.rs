@@ -0,0 +1,131 @@
+use anyhow::{bail, Context, Result};
+use imap::message::Unsubscribe;
+use std::str::FromStr;
+
+/// The message a `mailto:` unsubscribe asks for.
+#[derive(Debug, PartialEq, Eq)]
+pub struct Mailto {
+    pub to: String,
+    pub subject: String,
+    pub body: String,
+}
+
+impl FromStr for Mailto {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let rest = match s.get(..7) {
+            Some(scheme) if scheme.eq_ignore_ascii_case("mailto:") => &s[7..],
+            _ => bail!("Not a mailto URI: {}", s),
+        };
+        let (to, query) = rest.split_once('?').unwrap_or((rest, ""));
+        let to = percent_decode(to)?;
+        if to.is_empty() {
+            bail!("No address in {}", s);
+        }
+        let mut result = Self {
+            to,
+            subject: "unsubscribe".to_owned(),
+            body: String::new(),
+        };
+        for pair in query.split('&') {
+            match pair.split_once('=') {
+                Some((key, value)) if key.eq_ignore_ascii_case("subject") => {
+                    result.subject = percent_decode(value)?;
+                }
+                Some((key, value)) if key.eq_ignore_ascii_case("body") => {
+                    result.body = percent_decode(value)?;
+                }
+                _ => {}
+            }
+        }
+        return Ok(result);
+    }
+}
+
+fn percent_decode(s: &str) -> Result<String> {
+    let mut bytes = Vec::with_capacity(s.len());
+    let mut rest = s.as_bytes();
+    while let Some((&byte, tail)) = rest.split_first() {
+        if byte == b'%' {
+            let hex = tail.get(..2).context("Truncated escape")?;
+            let hex = std::str::from_utf8(hex)?;
+            bytes.push(u8::from_str_radix(hex, 16).context("Invalid escape")?);
+            rest = &tail[2..];
+        } else {
+            bytes.push(byte);
+            rest = tail;
+        }
+    }
+    return Ok(String::from_utf8(bytes)?);
+}
+
+/// Who gets told when leaving by `unsubscribe`: the one-click host or the
+/// mailto address. `None` when it can only be opened by hand.
+pub fn target(unsubscribe: &Unsubscribe) -> Result<Option<String>> {
+    if let (true, Some(url)) = (unsubscribe.one_click, &unsubscribe.https) {
+        let rest = url
+            .strip_prefix("https://")
+            .context(format!("Not an https URL: {}", url))?;
+        let host = rest.split(['/', '?', '#']).next().unwrap_or(rest);
+        // drop any userinfo, it's not where the request goes
+        let host = host.rsplit('@').next().unwrap_or(host);
+        if host.is_empty() {
+            bail!("No host in {}", url);
+        }
+        return Ok(Some(host.to_owned()));
+    }
+    return match &unsubscribe.mailto {
+        Some(uri) => Ok(Some(uri.parse::<Mailto>()?.to)),
+        None => Ok(None),
+    };
+}
+
+/// Unsubscribes with the RFC 8058 POST, no browser needed.
+pub fn one_click(url: &str) -> Result<()> {
+    ureq::post(url)
+        .send_form(&[("List-Unsubscribe", "One-Click")])
+        .context(format!("POST to {} failed", url))?;
+    return Ok(());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_mailto() {
+        let mailto: Mailto = "mailto:leave%2Bme@x.com?Subject=stop%20it&body=bye"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            mailto,
+            Mailto {
+                to: "leave+me@x.com".to_owned(),
+                subject: "stop it".to_owned(),
+                body: "bye".to_owned(),
+            }
+        );
+        let plain: Mailto = "mailto:leave@x.com".parse().unwrap();
+        assert_eq!(plain.subject, "unsubscribe");
+        assert!("https://x.com".parse::<Mailto>().is_err());
+        assert!("mailto:a@x.com?subject=%4".parse::<Mailto>().is_err());
+    }
+
+    #[test]
+    fn test_target() {
+        let mut unsubscribe = Unsubscribe {
+            mailto: Some("mailto:leave@x.com?subject=stop".into()),
+            https: Some("https://user@list.x.com/leave?id=1".into()),
+            one_click: true,
+        };
+        assert_eq!(target(&unsubscribe).unwrap().unwrap(), "list.x.com");
+        unsubscribe.one_click = false;
+        assert_eq!(target(&unsubscribe).unwrap().unwrap(), "leave@x.com");
+        unsubscribe.mailto = None;
+        assert_eq!(target(&unsubscribe).unwrap(), None);
+        unsubscribe.one_click = true;
+        unsubscribe.https = Some("https:///leave".into());
+        assert!(target(&unsubscribe).is_err());
+    }
+}
@@ -0,0 +1,28 @@
+use criterion::{black_box, criterion_group, criterion_main, Criterion};
+use imap::BodyStructure;
+
+/// A multipart/mixed with `count` attachments, like a mail with a big batch
+/// of photos.
+fn large_body_structure(count: usize) -> String {
+    let mut raw = String::from(
+        r#"* 1 FETCH (BODYSTRUCTURE (("TEXT" "PLAIN" ("CHARSET" "UTF-8") NIL NIL "7BIT" 12 1 NIL NIL NIL NIL)"#,
+    );
+    for i in 0..count {
+        raw.push_str(&format!(
+            r#"("IMAGE" "JPEG" ("NAME" "IMG_{:04}.jpg") NIL NIL "BASE64" 123456 NIL ("ATTACHMENT" ("FILENAME" "IMG_{:04}.jpg")) NIL NIL)"#,
+            i, i
+        ));
+    }
+    raw.push_str(r#" "MIXED" ("BOUNDARY" "----=_Part_0") NIL NIL NIL))"#);
+    return raw;
+}
+
+fn parse(c: &mut Criterion) {
+    let raw = large_body_structure(500);
+    c.bench_function("parse 500 part BODYSTRUCTURE", |b| {
+        b.iter(|| black_box(&raw).parse::<BodyStructure>().unwrap())
+    });
+}
+
+criterion_group!(benches, parse);
+criterion_main!(benches);
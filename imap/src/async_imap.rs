@@ -0,0 +1,293 @@
+use crate::{
+    check_completion, decode, extract_literal, header_items, literal_len,
+    message::{HeaderField, Message},
+    needs_literal, parse_alert, parse_exists, parse_header_responses, quoted, redact_secret,
+    BodyStructure, Inbox, InboxRangeStr,
+};
+use anyhow::{bail, Context, Result};
+use std::{ops::RangeBounds, str::FromStr, sync::Arc};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt, BufReader},
+    net::TcpStream,
+};
+use tokio_rustls::{
+    rustls::{pki_types::ServerName, ClientConfig, RootCertStore},
+    TlsConnector,
+};
+
+/// Any byte stream `AsyncIMap` can talk over.
+pub trait AsyncStream: AsyncRead + AsyncWrite + Unpin + Send {}
+impl<T: AsyncRead + AsyncWrite + Unpin + Send> AsyncStream for T {}
+
+/// The same client as [`crate::IMap`] for async code, covering the commands
+/// needed to read mail. Responses go through the same parsers.
+pub struct AsyncIMap {
+    stream: BufReader<Box<dyn AsyncStream>>,
+    selected_inbox: Option<Inbox>,
+    /// The `EXISTS` count from the last `SELECT`.
+    exists: usize,
+    /// `[ALERT]` texts the user hasn't been shown yet.
+    alerts: Vec<Box<str>>,
+}
+
+impl AsyncIMap {
+    /// The `[ALERT]` texts received since the last call, oldest first.
+    pub fn take_alerts(&mut self) -> Vec<Box<str>> {
+        return std::mem::take(&mut self.alerts);
+    }
+
+    /// Connects over TLS, trusting the webpki roots.
+    pub async fn connect(server: &str, port: u32) -> Result<Self> {
+        let mut roots = RootCertStore::empty();
+        roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+        let config = ClientConfig::builder()
+            .with_root_certificates(roots)
+            .with_no_client_auth();
+        let domain = ServerName::try_from(server.to_owned()).context("Invalid server name")?;
+
+        let tcp = TcpStream::connect(format!("{}:{}", server, port))
+            .await
+            .context(format!("Failed to connect to {}:{}", server, port))?;
+        let tls = TlsConnector::from(Arc::new(config))
+            .connect(domain, tcp)
+            .await
+            .context("TLS handshake failed")?;
+        return Self::from_stream(Box::new(tls)).await;
+    }
+
+    /// Talks IMAP over an already connected `stream`, starting with the
+    /// server greeting.
+    pub async fn from_stream(stream: Box<dyn AsyncStream>) -> Result<Self> {
+        let mut obj = Self {
+            stream: BufReader::new(stream),
+            selected_inbox: None,
+            exists: 0,
+            alerts: vec![],
+        };
+        let mut greeting = Vec::new();
+        obj.readline(&mut greeting).await?;
+        return Ok(obj);
+    }
+
+    pub async fn login(&mut self, username: &str, password: &str) -> Result<()> {
+        let result = match self.send_login(username, password).await {
+            Ok(()) => self.read_response_bytes().await.map(|_| ()),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = result {
+            bail!(
+                "Login failed: {}",
+                redact_secret(&format!("{:#}", e), password)
+            );
+        }
+        return Ok(());
+    }
+
+    /// Sends `LOGIN` with each value quoted, or as a literal if it has
+    /// characters a quoted string can't hold.
+    async fn send_login(&mut self, username: &str, password: &str) -> Result<()> {
+        let mut line = "? LOGIN".to_owned();
+        for value in [username, password] {
+            if needs_literal(value) {
+                line.push_str(&format!(" {{{}}}", value.len()));
+                self.run_cmd(&line).await?;
+                self.wait_continuation().await?;
+                line = value.to_owned();
+            } else {
+                line.push(' ');
+                line.push_str(&quoted(value));
+            }
+        }
+        return self.run_cmd(&line).await;
+    }
+
+    /// Reads until the server's `+` asking for a literal.
+    async fn wait_continuation(&mut self) -> Result<()> {
+        loop {
+            let mut buf = Vec::new();
+            if self.readline(&mut buf).await? == 0 {
+                bail!("connection ended");
+            }
+            if buf.starts_with(b"+") {
+                return Ok(());
+            }
+            if buf.starts_with(b"? ") {
+                bail!(
+                    "LOGIN rejected: {}",
+                    String::from_utf8_lossy(&buf[2..]).trim_end()
+                );
+            }
+        }
+    }
+
+    pub async fn list_inbox(&mut self) -> Result<Vec<Inbox>> {
+        let result = self.execute_cmd("? LIST \"*\" \"*\"").await?;
+        return result.trim_end().split('\n').map(Inbox::from_str).collect();
+    }
+
+    pub async fn select_inbox(&mut self, inbox: Inbox) -> Result<()> {
+        if !inbox.selectable {
+            bail!("Error: Inbox not selectable")
+        }
+        let result = self
+            .execute_cmd(format!("? SELECT {}", quoted(&inbox.name)).as_str())
+            .await?;
+        self.exists = parse_exists(&result).context("SELECT response has no EXISTS count")?;
+        self.selected_inbox = Some(inbox);
+        return Ok(());
+    }
+
+    /// The number of messages in the selected inbox, as of the `SELECT`.
+    pub fn get_inbox_count(&self) -> Result<usize> {
+        if self.selected_inbox.is_none() {
+            bail!("No inbox selected, call select_inbox first");
+        }
+        return Ok(self.exists);
+    }
+
+    pub async fn get_n_email_headers<R: RangeBounds<usize>>(
+        &mut self,
+        range: R,
+    ) -> Result<Box<[Message]>> {
+        return self
+            .get_n_email_headers_with(range, HeaderField::DEFAULT)
+            .await;
+    }
+
+    /// `get_n_email_headers` asking only for `fields`.
+    pub async fn get_n_email_headers_with<R: RangeBounds<usize>>(
+        &mut self,
+        range: R,
+        fields: &[HeaderField],
+    ) -> Result<Box<[Message]>> {
+        let InboxRangeStr(lhs, rhs) = range.into();
+        let cmd = format!("? FETCH {}:{} (FLAGS {})", lhs, rhs, header_items(fields));
+        self.run_cmd(cmd.as_str()).await?;
+        let raw = self.read_response_bytes().await?;
+        return Ok(parse_header_responses(&raw));
+    }
+
+    pub async fn get_body_structure(&mut self, id: usize) -> Result<BodyStructure> {
+        let cmd = format!("? FETCH {} (BODYSTRUCTURE)", id);
+        return self.execute_cmd(cmd.as_str()).await?.parse();
+    }
+
+    pub async fn read_email(&mut self, id: usize) -> Result<Box<str>> {
+        let body_structure = self.get_body_structure(id).await?;
+        let section = body_structure.find_text().context("No Text found")?;
+        let meta = body_structure
+            .part(&section)
+            .and_then(BodyStructure::text_meta)
+            .cloned()
+            .unwrap_or_default();
+        let cmd = format!("? FETCH {} BODY[{}]", id, section);
+        self.run_cmd(cmd.as_str()).await?;
+        let raw = self.read_response_bytes().await?;
+
+        let body = extract_literal(&raw).context("Couldn't find message start")?;
+        let body = decode::decode_transfer_encoding(body, &meta.encoding)?;
+        let text = decode::decode_charset(&body, &meta.charset);
+        let mut reflow = decode::Reflow::new(&meta);
+        let mut result = reflow.push(&text);
+        result.push_str(&reflow.finish());
+        return Ok(result.into());
+    }
+
+    /// Reads everything up to the tagged completion line, `{n}` literals are
+    /// read by length.
+    async fn read_response_bytes(&mut self) -> Result<Vec<u8>> {
+        let mut result = Vec::new();
+        loop {
+            let mut buf = Vec::new();
+            if self.readline(&mut buf).await? == 0 {
+                bail!("connection ended");
+            }
+            if check_completion(&buf)? {
+                break;
+            }
+            result.extend_from_slice(&buf);
+            if let Some(len) = literal_len(&buf) {
+                let start = result.len();
+                result.resize(start + len, 0);
+                self.stream
+                    .read_exact(&mut result[start..])
+                    .await
+                    .context("Failed to read literal")?;
+            }
+        }
+        return Ok(result);
+    }
+
+    async fn readline(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
+        let start = buf.len();
+        let count = self
+            .stream
+            .read_until(0x0a, buf)
+            .await
+            .context("Failed to read line from buffer")?;
+        self.alerts.extend(parse_alert(&buf[start..]));
+        return Ok(count);
+    }
+
+    async fn run_cmd(&mut self, cmd: &str) -> Result<()> {
+        let stream = self.stream.get_mut();
+        stream.write_all(format!("{}\r\n", cmd).as_bytes()).await?;
+        stream.flush().await?;
+        return Ok(());
+    }
+
+    async fn execute_cmd(&mut self, cmd: &str) -> Result<Box<str>> {
+        self.run_cmd(cmd).await?;
+        let raw = self.read_response_bytes().await?;
+        return Ok(String::from_utf8(raw)?.into());
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use tokio::io::{duplex, DuplexStream};
+
+    /// Answers each command line with the scripted response, like the sync
+    /// `MockStream`.
+    async fn serve(server: DuplexStream, script: Vec<(&'static str, &'static str)>) {
+        let (read, mut write) = tokio::io::split(server);
+        let mut read = BufReader::new(read);
+        write.write_all(b"* OK ready\r\n").await.unwrap();
+        for (expected, response) in script {
+            let mut line = String::new();
+            read.read_line(&mut line).await.unwrap();
+            assert_eq!(line.trim_end(), expected);
+            write.write_all(response.as_bytes()).await.unwrap();
+        }
+    }
+
+    #[tokio::test]
+    async fn test_async_read_email() {
+        let (client, server) = duplex(4096);
+        let script = vec![
+            ("? LOGIN \"me\" \"pw\"", "? OK LOGIN completed\r\n"),
+            (
+                "? SELECT \"INBOX\"",
+                "* 3 EXISTS\r\n? OK [READ-WRITE] SELECT completed\r\n",
+            ),
+            (
+                "? FETCH 3 (BODYSTRUCTURE)",
+                "* 3 FETCH (BODYSTRUCTURE (\"TEXT\" \"PLAIN\" (\"CHARSET\" \"UTF-8\") NIL NIL \"QUOTED-PRINTABLE\" 12 1 NIL NIL NIL NIL))\r\n? OK FETCH completed\r\n",
+            ),
+            (
+                "? FETCH 3 BODY[1]",
+                "* 3 FETCH (BODY[1] {12}\r\ncaf=C3=A9 ok)\r\n? OK FETCH completed\r\n",
+            ),
+        ];
+        let server = tokio::spawn(serve(server, script));
+
+        let mut imap = AsyncIMap::from_stream(Box::new(client)).await.unwrap();
+        imap.login("me", "pw").await.unwrap();
+        let inbox = Inbox::from_str("* LIST (\\HasNoChildren) \"/\" \"INBOX\"").unwrap();
+        imap.select_inbox(inbox).await.unwrap();
+        assert_eq!(imap.get_inbox_count().unwrap(), 3);
+        assert_eq!(&*imap.read_email(3).await.unwrap(), "café ok");
+        server.await.unwrap();
+    }
+}
@@ -1,44 +1,87 @@
 use anyhow::{bail, Context, Result};
+use std::borrow::Cow;
 use std::str::FromStr;
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub struct FileMeta {
-    file_type: Box<str>,
-    name: Box<str>,
+    pub file_type: Box<str>,
+    pub name: Box<str>,
+    /// The transfer encoding, e.g. `BASE64`.
+    pub encoding: Box<str>,
+    /// The size in bytes before decoding, BODYSTRUCTURE's octet count.
+    pub size: Option<usize>,
+}
+
+/// How a text part's bytes are encoded, `charset` defaults to UTF-8 and
+/// `encoding` to 7BIT when the server doesn't say.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TextMeta {
+    pub charset: Box<str>,
+    pub encoding: Box<str>,
+    /// `format=flowed` (RFC 3676), lines ending in a space continue on the
+    /// next one.
+    pub flowed: bool,
+    /// `delsp=yes`, the space before a flowed line break isn't part of the
+    /// text.
+    pub delsp: bool,
+}
+
+impl Default for TextMeta {
+    fn default() -> Self {
+        return Self {
+            charset: "UTF-8".into(),
+            encoding: "7BIT".into(),
+            flowed: false,
+            delsp: false,
+        };
+    }
 }
 
 type NestedBodyStructure = (Box<[BodyStructure]>, Box<str>);
 
 #[derive(Clone, Debug, PartialEq, Eq)]
 pub enum BodyStructure {
-    Plain,
-    Html,
+    Plain(TextMeta),
+    Html(TextMeta),
+    /// Any other `TEXT/*` part, e.g. `CALENDAR` or `ENRICHED`.
+    Text {
+        subtype: Box<str>,
+        meta: TextMeta,
+    },
     Image(FileMeta),
+    Audio(FileMeta),
+    Video(FileMeta),
     Application(FileMeta),
     Mixed(NestedBodyStructure),
     Related(NestedBodyStructure),
     Alternative(NestedBodyStructure),
+    /// An attached `MESSAGE/RFC822`, holds the embedded message's structure
+    /// as its only child. There is no boundary so it's left empty.
+    Message(NestedBodyStructure),
+    /// A part of a type we don't otherwise handle, e.g. `FONT/WOFF2`. The
+    /// file type holds the full `TYPE/SUBTYPE`.
+    Other(FileMeta),
 }
 
 #[derive(Clone)]
 struct StrReader<'a> {
     val: &'a str,
+    /// Byte offset of the next char.
     index: usize,
 }
-
 impl<'a> StrReader<'a> {
     fn new(val: &'a str) -> Self {
         Self { val, index: 0 }
     }
 
     fn peek(&self) -> Option<char> {
-        self.val.chars().nth(self.index)
+        self.val[self.index..].chars().next()
     }
 
     fn read(&mut self) -> Option<char> {
-        let result = self.val.chars().nth(self.index);
-        if let Some(_) = result {
-            self.index += 1
+        let result = self.peek();
+        if let Some(c) = result {
+            self.index += c.len_utf8();
         }
         return result;
     }
@@ -54,65 +97,149 @@ impl<'a> StrReader<'a> {
         return (callback)(&self.val[self.index..]);
     }
 
-    fn get_quoted(&mut self) -> Option<&'a str> {
-        let mut str_reader = self.clone();
-        let starts_with_quote = str_reader.read().map(|x| x == '"').unwrap_or(false);
-        let start = str_reader.index;
-        if !starts_with_quote {
-            return None;
+    fn skip_space(&mut self) {
+        while matches!(self.peek(), Some(' ' | '\r' | '\n')) {
+            self.read();
+        }
+    }
+
+    fn skip_quoted(&mut self) -> bool {
+        if self.read() != Some('"') {
+            return false;
         }
         loop {
-            let val = if let Some(val) = str_reader.read() {
-                val
-            } else {
-                return None;
-            };
-            let peek = str_reader.peek();
-
-            match (val, peek) {
-                ('\\', Some('"')) => {}
-                (_, Some('"')) => {
-                    str_reader.consume(1);
-                    *self = str_reader;
-                    return Some(&self.val[start..self.index - 1]);
+            match self.read() {
+                Some('\\') => {
+                    self.read();
                 }
-                _ => {}
-            };
+                Some('"') => return true,
+                Some(_) => {}
+                None => return false,
+            }
         }
     }
 
-    fn consume_until_end_paren(&mut self) -> bool {
-        let mut str_reader = self.clone();
-        let mut p_count = 0;
-        let mut in_quotes = false;
-        loop {
-            let val = if let Some(val) = str_reader.read() {
-                val
-            } else {
-                return false;
-            };
-            let peek = str_reader.peek();
+    /// Reads a quoted string, undoing the `\\` escapes of `"` and `\\`.
+    fn read_quoted(&mut self) -> Option<Cow<'a, str>> {
+        let start = self.index;
+        if !self.skip_quoted() {
+            return None;
+        }
+        let raw = &self.val[start + 1..self.index - 1];
+        if !raw.contains('\\') {
+            return Some(Cow::Borrowed(raw));
+        }
+        let mut result = String::with_capacity(raw.len());
+        let mut chars = raw.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '\\' => result.extend(chars.next()),
+                c => result.push(c),
+            }
+        }
+        return Some(Cow::Owned(result));
+    }
 
-            match (val, peek, in_quotes) {
-                ('(', _, false) => p_count += 1,
+    /// Reads a `{n}\r\n` literal.
+    fn read_literal(&mut self) -> Option<&'a str> {
+        if self.read() != Some('{') {
+            return None;
+        }
+        let rest = &self.val[self.index..];
+        let digits = rest.split_once('}')?.0;
+        let len: usize = digits.parse().ok()?;
+        // as written, `{007}` is still 7
+        self.consume(digits.len() + 1);
+        if !self.act_on_slice(|s| s.starts_with("\r\n")) {
+            return None;
+        }
+        self.consume(2);
+        let start = self.index;
+        let result = self.val.get(start..start.checked_add(len)?)?;
+        self.consume(len);
+        return Some(result);
+    }
 
-                (')', _, false) => p_count -= 1,
+    /// Reads a string, quoted or literal, `NIL` is read as an empty string.
+    fn read_string(&mut self) -> Option<Cow<'a, str>> {
+        self.skip_space();
+        return match self.peek()? {
+            '"' => self.read_quoted(),
+            '{' => self.read_literal().map(Cow::Borrowed),
+            _ if self
+                .act_on_slice(|s| s.get(..3)?.eq_ignore_ascii_case("NIL").then_some(()))
+                .is_some() =>
+            {
+                self.consume(3);
+                Some(Cow::Borrowed(""))
+            }
+            _ => None,
+        };
+    }
 
-                ('"', _, false) => in_quotes = true,
+    /// Reads a number, leaving anything else unread.
+    fn read_number(&mut self) -> Option<usize> {
+        self.skip_space();
+        let len = self.act_on_slice(|s| s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len()));
+        let number = self.act_on_slice(|s| s[..len].parse().ok())?;
+        self.consume(len);
+        return Some(number);
+    }
 
-                ('\\', Some('"'), true) => str_reader.consume(1),
+    /// Reads a body parameter list `("KEY" "VALUE" ...)` or `NIL`.
+    fn read_params(&mut self) -> Option<Vec<(Cow<'a, str>, Cow<'a, str>)>> {
+        self.skip_space();
+        if self.peek() != Some('(') {
+            return self.read_string().map(|_| vec![]);
+        }
+        self.read();
+        let mut params = vec![];
+        loop {
+            self.skip_space();
+            if self.peek() == Some(')') {
+                self.read();
+                return Some(params);
+            }
+            let key = self.read_string()?;
+            let value = self.read_string()?;
+            params.push((key, value));
+        }
+    }
 
-                (_, Some('"'), true) => {
-                    in_quotes = false;
-                    str_reader.consume(1);
+    /// Skips one value, a parenthesized list, a string or an atom like a
+    /// number.
+    fn skip_value(&mut self) -> bool {
+        self.skip_space();
+        match self.peek() {
+            Some('(') => {
+                self.read();
+                return self.skip_to_end_paren();
+            }
+            Some('"') | Some('{') => self.read_string().is_some(),
+            Some(')') | None => false,
+            Some(_) => {
+                while let Some(val) = self.peek() {
+                    if val == ' ' || val == '(' || val == ')' {
+                        break;
+                    }
+                    self.read();
                 }
-                _ => {}
-            };
+                true
+            }
+        }
+    }
 
-            if p_count == -1 {
-                *self = str_reader;
+    /// Skips whatever is left of the current list including its `)`.
+    fn skip_to_end_paren(&mut self) -> bool {
+        loop {
+            self.skip_space();
+            if self.peek() == Some(')') {
+                self.read();
                 return true;
             }
+            if !self.skip_value() {
+                return false;
+            }
         }
     }
 }
@@ -121,209 +248,361 @@ impl FromStr for BodyStructure {
     type Err = anyhow::Error;
 
     fn from_str(s: &str) -> Result<Self> {
-        let cmd = s
-            .splitn(3, '(')
-            .last()
-            .context("Invalid couldn't find 2 '(' ")?;
-
-        let mut reader = StrReader::new(cmd);
-        let mut v: Vec<Self> = vec![];
-        let mut splits = vec![0];
-
-        while let Some(val) = reader.read() {
-            let peek = reader.peek();
-            let mut result = match (val, peek) {
-                ('(', Some('(')) => {
-                    splits.push(v.len());
-                    None
-                }
-                ('T', _) => Self::parse_t(&mut reader),
-                ('I', _) => Self::parse_i(&mut reader),
-                ('A', _) => Self::parse_a(&mut reader),
-                ('R', _) => Self::parse_r(&mut reader),
-                ('M', _) => Self::parse_m(&mut reader),
-                _ => None,
-            };
-            if let Some(BodyStructure::Alternative((arr, _)))
-            | Some(BodyStructure::Mixed((arr, _)))
-            | Some(BodyStructure::Related((arr, _))) = &mut result
-            {
-                let last_split = splits.pop().context("Invalid str couldn't find split")?;
-                let (a, b) = v.split_at(last_split);
-                *arr = b.into();
-                v = a.into();
-            }
-
-            if let Some(x) = result {
-                v.push(x);
-            }
-        }
-
-        if v.len() != 1 {
-            bail!("Error parsing")
+        let start = s
+            .find("BODYSTRUCTURE")
+            .context("Invalid couldn't find BODYSTRUCTURE")?;
+        let mut reader = StrReader::new(&s[start + "BODYSTRUCTURE".len()..]);
+        reader.skip_space();
+        if reader.read() != Some('(') {
+            bail!("Invalid couldn't find body structure start");
         }
-        // this unwrap is safe due to the above
-        return Ok(v.pop().unwrap());
+        return Self::parse_body(&mut reader).context("Error parsing");
     }
 }
 
 impl BodyStructure {
-    fn parse_t(str_reader: &mut StrReader) -> Option<Self> {
-        let is_plain = str_reader.act_on_slice(|s| s.starts_with("EXT\" \"PLAIN\""));
-        let is_html = str_reader.act_on_slice(|s| s.starts_with("EXT\" \"HTML\""));
-        if !is_plain && !is_html {
-            return None;
-        }
-        str_reader.consume(4);
-        if !str_reader.consume_until_end_paren() {
-            return None;
-        }
-        if is_html {
-            return Some(Self::Html);
+    /// Parses a body from just after its opening paren through its closing
+    /// paren.
+    fn parse_body(str_reader: &mut StrReader) -> Option<Self> {
+        str_reader.skip_space();
+        if str_reader.peek() == Some('(') {
+            return Self::parse_multipart(str_reader);
         }
-        Some(Self::Plain)
+        return Self::parse_leaf(str_reader);
     }
 
-    fn parse_i(str_reader: &mut StrReader) -> Option<Self> {
-        if !str_reader.act_on_slice(|s| s.starts_with("MAGE\" \"")) {
-            return None;
-        }
-        let mut str_reader_copy = str_reader.clone();
-        // skip the MAGE " "
-        str_reader_copy.consume(6);
-        let file_type = str_reader_copy.get_quoted()?.into();
-        // skip the filetype and the end "
-        if !str_reader_copy.act_on_slice(|s| s.starts_with(" (\"NAME\" \"")) {
-            // if there is no name then we didn't find an image.
-            return None;
+    /// `(body)(body)... "SUBTYPE" ("BOUNDARY" "...") ...)`
+    fn parse_multipart(str_reader: &mut StrReader) -> Option<Self> {
+        let mut parts = vec![];
+        loop {
+            str_reader.skip_space();
+            if str_reader.peek() != Some('(') {
+                break;
+            }
+            str_reader.read();
+            parts.push(Self::parse_body(str_reader)?);
         }
-        str_reader_copy.consume(9);
-        let name = str_reader_copy.get_quoted()?.into();
+        let subtype = str_reader.read_string()?;
 
-        if !str_reader_copy.consume_until_end_paren() {
+        str_reader.skip_space();
+        let mut boundary: Box<str> = "".into();
+        if str_reader.peek() != Some(')') {
+            let params = str_reader.read_params()?;
+            boundary = find_param(&params, "BOUNDARY").unwrap_or("").into();
+        }
+        if !str_reader.skip_to_end_paren() {
             return None;
         }
 
-        return Some(Self::Image(FileMeta { file_type, name }));
+        let nested = (parts.into(), boundary);
+        // unknown multipart subtypes are treated as mixed (RFC 2046)
+        return Some(match subtype.to_ascii_uppercase().as_str() {
+            "ALTERNATIVE" => Self::Alternative(nested),
+            "RELATED" => Self::Related(nested),
+            _ => Self::Mixed(nested),
+        });
     }
 
-    fn find_boundray<'a>(str_reader: &mut StrReader<'a>) -> Option<&'a str> {
-        while let Some(val) = str_reader.read() {
-            if val != '(' {
-                continue;
-            }
-            if !str_reader.act_on_slice(|s| s.starts_with("\"BOUNDARY\" \"")) {
-                return None;
-            }
-            str_reader.consume(11);
-            return str_reader.get_quoted();
+    /// `"TYPE" "SUBTYPE" params id description encoding octets ...)`, parts
+    /// of a type we don't know are kept as `Other` so section numbers of the
+    /// parts after them still line up.
+    fn parse_leaf(str_reader: &mut StrReader) -> Option<Self> {
+        let mime_type = str_reader.read_string()?.to_ascii_uppercase();
+        let subtype = str_reader.read_string()?.to_ascii_uppercase();
+        let params = str_reader.read_params()?;
+        // skip the id and description to get to the encoding
+        if !str_reader.skip_value() || !str_reader.skip_value() {
+            return None;
         }
-        return None;
-    }
+        let encoding = str_reader.read_string()?;
 
-    fn parse_a(str_reader: &mut StrReader) -> Option<Self> {
-        if str_reader.act_on_slice(|s| s.starts_with("LTERNATIVE\"")) {
-            let mut str_reader_copy = str_reader.clone();
-            str_reader_copy.consume(11);
-            let boundry = Self::find_boundray(&mut str_reader_copy)?.into();
-            return Some(Self::Alternative((Default::default(), boundry)));
+        if mime_type == "MESSAGE" && subtype == "RFC822" {
+            return Self::parse_message(str_reader);
         }
-        if !str_reader.act_on_slice(|s| s.starts_with("PPLICATION\" \"")) {
+        let size = str_reader.read_number();
+        if !str_reader.skip_to_end_paren() {
             return None;
         }
-        let mut str_reader_copy = str_reader.clone();
-        str_reader_copy.consume(12);
-        let file_type = str_reader_copy.get_quoted()?.into();
-        if !str_reader_copy.act_on_slice(|s| s.starts_with(" (\"NAME\") \"")) {
+
+        let is_param = |key, value: &str| {
+            find_param(&params, key).is_some_and(|x| x.eq_ignore_ascii_case(value))
+        };
+        let encoding: Box<str> = if encoding.is_empty() {
+            "7BIT"
+        } else {
+            &encoding
+        }
+        .to_ascii_uppercase()
+        .into();
+        let text_meta = || TextMeta {
+            charset: find_param(&params, "CHARSET").unwrap_or("UTF-8").into(),
+            flowed: is_param("FORMAT", "flowed"),
+            delsp: is_param("DELSP", "yes"),
+            encoding: encoding.clone(),
+        };
+        let file_meta = |file_type: &str| FileMeta {
+            file_type: file_type.into(),
+            name: find_param(&params, "NAME").unwrap_or("").into(),
+            encoding: encoding.clone(),
+            size,
+        };
+
+        return Some(match (mime_type.as_str(), subtype.as_str()) {
+            ("TEXT", "PLAIN") => Self::Plain(text_meta()),
+            ("TEXT", "HTML") => Self::Html(text_meta()),
+            ("TEXT", _) => Self::Text {
+                subtype: subtype.as_str().into(),
+                meta: text_meta(),
+            },
+            ("IMAGE", _) => Self::Image(file_meta(&subtype)),
+            ("AUDIO", _) => Self::Audio(file_meta(&subtype)),
+            ("VIDEO", _) => Self::Video(file_meta(&subtype)),
+            ("APPLICATION", _) => Self::Application(file_meta(&subtype)),
+            _ => Self::Other(file_meta(&format!("{}/{}", mime_type, subtype))),
+        });
+    }
+
+    /// Parses the rest of a `MESSAGE/RFC822` part after its encoding,
+    /// `octets envelope (body) lines ...)`.
+    fn parse_message(str_reader: &mut StrReader) -> Option<Self> {
+        // octets and the envelope
+        if !str_reader.skip_value() || !str_reader.skip_value() {
             return None;
         }
-        str_reader_copy.consume(11);
-        let name = str_reader_copy.get_quoted()?.into();
-        if !str_reader_copy.consume_until_end_paren() {
+        str_reader.skip_space();
+        if str_reader.read() != Some('(') {
             return None;
         }
-        return Some(Self::Application(FileMeta { file_type, name }));
-    }
-    fn parse_r(str_reader: &mut StrReader) -> Option<Self> {
-        if !str_reader.act_on_slice(|s| s.starts_with("ELATED\"")) {
+        let inner = Self::parse_body(str_reader)?;
+        if !str_reader.skip_to_end_paren() {
             return None;
         }
-        let mut str_reader_copy = str_reader.clone();
-        str_reader_copy.consume(7);
-        let boundry = Self::find_boundray(&mut str_reader_copy)?.into();
-        return Some(Self::Related((Default::default(), boundry)));
+        return Some(Self::Message((Box::new([inner]), "".into())));
     }
 
-    fn parse_m(str_reader: &mut StrReader) -> Option<Self> {
-        if !str_reader.act_on_slice(|s| s.starts_with("IXED\"")) {
-            return None;
+    /// Every file part (images, audio, video and applications), including
+    /// ones inside attached messages.
+    pub fn attachments(&self) -> Vec<&FileMeta> {
+        use BodyStructure::*;
+        return match self {
+            Plain(_) | Html(_) | Text { .. } => vec![],
+            Image(meta) | Audio(meta) | Video(meta) | Application(meta) | Other(meta) => {
+                vec![meta]
+            }
+            Alternative((arr, _)) | Mixed((arr, _)) | Related((arr, _)) | Message((arr, _)) => {
+                arr.iter().flat_map(|x| x.attachments()).collect()
+            }
+        };
+    }
+
+    /// Every image part with its section, in order.
+    pub fn images(&self) -> Vec<(Box<str>, &FileMeta)> {
+        let mut leaves = vec![];
+        Self::collect_leaves(self, vec![], &mut leaves);
+        return leaves
+            .into_iter()
+            .filter_map(|(path, part)| match part {
+                BodyStructure::Image(meta) => Some((section(&path), meta)),
+                _ => None,
+            })
+            .collect();
+    }
+
+    /// Numbers parts the same way as `find_text_dfs`.
+    fn collect_leaves<'a>(
+        current: &'a Self,
+        path: Vec<usize>,
+        result: &mut Vec<(Vec<usize>, &'a Self)>,
+    ) {
+        use BodyStructure::*;
+        match current {
+            Alternative((arr, _)) | Mixed((arr, _)) | Related((arr, _)) => {
+                for (i, part) in arr.iter().enumerate() {
+                    let mut new_path = path.clone();
+                    new_path.push(i + 1);
+                    Self::collect_leaves(part, new_path, result);
+                }
+            }
+            Message((arr, _)) => match arr.first() {
+                Some(inner @ (Alternative(_) | Mixed(_) | Related(_))) => {
+                    Self::collect_leaves(inner, path, result);
+                }
+                Some(inner) => {
+                    let mut new_path = path;
+                    new_path.push(1);
+                    Self::collect_leaves(inner, new_path, result);
+                }
+                None => {}
+            },
+            // a single part message is section 1
+            leaf if path.is_empty() => result.push((vec![1], leaf)),
+            leaf => result.push((path, leaf)),
         }
-        let mut str_reader_copy = str_reader.clone();
-        str_reader_copy.consume(5);
-        let boundry = Self::find_boundray(&mut str_reader_copy)?.into();
-        return Some(Self::Mixed((Default::default(), boundry)));
     }
+
+    /// The section of the first `TEXT/PLAIN` part, or failing that the first
+    /// other text part that isn't HTML.
     pub fn find_text(&self) -> Option<Box<str>> {
-        let (path, found) = BodyStructure::find_text_dfs(&self, vec![]);
+        let (mut path, mut found) =
+            BodyStructure::find_text_dfs(self, vec![], |x| matches!(x, Self::Plain(_)));
+        if !found {
+            (path, found) =
+                BodyStructure::find_text_dfs(self, vec![], |x| matches!(x, Self::Text { .. }));
+        }
         if !found {
             return None;
         }
 
-        return Some(
-            path.iter()
-                .map(|x| format!("{}", x))
-                .collect::<Vec<String>>()
-                .join(".")
-                .into(),
-        );
+        return Some(section(&path));
+    }
+
+    /// The part at `section`, e.g. `2.1`.
+    pub fn part(&self, section: &str) -> Option<&BodyStructure> {
+        use BodyStructure::*;
+        let mut current = self;
+        for index in section.split('.') {
+            let index: usize = index.parse().ok()?;
+            current = match current {
+                Alternative((arr, _)) | Mixed((arr, _)) | Related((arr, _)) => {
+                    arr.get(index.checked_sub(1)?)?
+                }
+                // see find_text_dfs for how message parts are numbered
+                Message((arr, _)) => match arr.first()? {
+                    Alternative((arr, _)) | Mixed((arr, _)) | Related((arr, _)) => {
+                        arr.get(index.checked_sub(1)?)?
+                    }
+                    inner if index == 1 => inner,
+                    _ => return None,
+                },
+                leaf if index == 1 => leaf,
+                _ => return None,
+            };
+        }
+        return Some(current);
+    }
+
+    /// The encoding details of a text part.
+    pub fn text_meta(&self) -> Option<&TextMeta> {
+        return match self {
+            BodyStructure::Plain(meta)
+            | BodyStructure::Html(meta)
+            | BodyStructure::Text { meta, .. } => Some(meta),
+            _ => None,
+        };
     }
 
-    fn find_text_dfs(current: &BodyStructure, path: Vec<usize>) -> (Vec<usize>, bool) {
+    fn find_text_dfs(
+        current: &BodyStructure,
+        path: Vec<usize>,
+        is_text: fn(&BodyStructure) -> bool,
+    ) -> (Vec<usize>, bool) {
         use BodyStructure::*;
         return match current {
-            Plain => (if path.is_empty() { vec![1] } else { path }, true),
-            Html | Application(_) | Image(_) => (path, false),
+            // a multipart inside a message is numbered as the message's own
+            // parts, anything else is the message's part 1
+            Message((arr, _)) => match arr.first() {
+                Some(inner @ (Alternative(_) | Mixed(_) | Related(_))) => {
+                    let (resp_path, done) =
+                        BodyStructure::find_text_dfs(inner, path.clone(), is_text);
+                    if done {
+                        (resp_path, true)
+                    } else {
+                        (path, false)
+                    }
+                }
+                Some(inner) => {
+                    let mut new_path = path.clone();
+                    new_path.push(1);
+                    let (resp_path, done) = BodyStructure::find_text_dfs(inner, new_path, is_text);
+                    if done {
+                        (resp_path, true)
+                    } else {
+                        (path, false)
+                    }
+                }
+                None => (path, false),
+            },
             Alternative((arr, _)) | Mixed((arr, _)) | Related((arr, _)) => {
                 let mut new_path = path.clone();
                 new_path.push(1);
                 for (i, el) in arr.iter().enumerate().map(|(i, x)| (i + 1, x)) {
                     // I just added a value so this should be safe
                     *new_path.last_mut().unwrap() = i;
-                    let (resp_path, done) = BodyStructure::find_text_dfs(el, new_path.clone());
+                    let (resp_path, done) =
+                        BodyStructure::find_text_dfs(el, new_path.clone(), is_text);
                     if done {
                         return (resp_path, true);
                     }
                 }
                 return (path, false);
             }
+            leaf if is_text(leaf) => (if path.is_empty() { vec![1] } else { path }, true),
+            _ => (path, false),
         };
     }
 }
 
+/// Joins a part path into a section, e.g. `2.1`.
+fn section(path: &[usize]) -> Box<str> {
+    return path
+        .iter()
+        .map(|x| format!("{}", x))
+        .collect::<Vec<String>>()
+        .join(".")
+        .into();
+}
+
+fn find_param<'a>(params: &'a [(Cow<str>, Cow<str>)], key: &str) -> Option<&'a str> {
+    return params
+        .iter()
+        .find(|(x, _)| x.eq_ignore_ascii_case(key))
+        .map(|(_, value)| &**value);
+}
+
 #[cfg(test)]
 mod test {
 
     use super::*;
-    const BS_STRING: &'static str = r#"* 123123 FETCH (BODYSTRUCTURE (("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "QUOTED-PRINTABLE" 495 10 NIL NIL NIL)(("TEXT" "HTML" ("CHARSET" "utf-8") NIL NIL "QUOTED-PRINTABLE" 6328 127 NIL NIL NIL)("IMAGE" "PNG" ("NAME" "og-image.png" "X-UNIX-MODE" "0666") "<34A362DC-C052-41DA-B3C2-C6782B912403>" NIL "BASE64" 68590 NIL ("INLINE" ("FILENAME" "og-image.png")) NIL)("IMAGE" "PNG" ("NAME" "1*jtOTreOJuxO8FtLYyU9Uyw.png" "X-UNIX-MODE" "0666") "<E80B1254-3757-4EB9-AC92-C2E2EC312001>" NIL "BASE64" 180504 NIL ("INLINE" ("FILENAME" "1*jtOTreOJuxO8FtLYyU9Uyw.png")) NIL) "RELATED" ("BOUNDARY" "Apple-Mail=_A6722D8A-5BBB-478B-8940-7B14BCE39030" "TYPE" "text/html") NIL NIL) "ALTERNATIVE" ("BOUNDARY" "Apple-Mail=_D5EF70C3-5230-4B9A-A34D-20255319DA45") NIL NIL))
+    const BS_STRING: &str = r#"* 123123 FETCH (BODYSTRUCTURE (("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "QUOTED-PRINTABLE" 495 10 NIL NIL NIL)(("TEXT" "HTML" ("CHARSET" "utf-8") NIL NIL "QUOTED-PRINTABLE" 6328 127 NIL NIL NIL)("IMAGE" "PNG" ("NAME" "og-image.png" "X-UNIX-MODE" "0666") "<34A362DC-C052-41DA-B3C2-C6782B912403>" NIL "BASE64" 68590 NIL ("INLINE" ("FILENAME" "og-image.png")) NIL)("IMAGE" "PNG" ("NAME" "1*jtOTreOJuxO8FtLYyU9Uyw.png" "X-UNIX-MODE" "0666") "<E80B1254-3757-4EB9-AC92-C2E2EC312001>" NIL "BASE64" 180504 NIL ("INLINE" ("FILENAME" "1*jtOTreOJuxO8FtLYyU9Uyw.png")) NIL) "RELATED" ("BOUNDARY" "Apple-Mail=_A6722D8A-5BBB-478B-8940-7B14BCE39030" "TYPE" "text/html") NIL NIL) "ALTERNATIVE" ("BOUNDARY" "Apple-Mail=_D5EF70C3-5230-4B9A-A34D-20255319DA45") NIL NIL))
 "#;
 
+    fn utf8_qp() -> TextMeta {
+        return TextMeta {
+            charset: "utf-8".into(),
+            encoding: "QUOTED-PRINTABLE".into(),
+            ..TextMeta::default()
+        };
+    }
+
+    fn utf8_7bit() -> TextMeta {
+        return TextMeta {
+            charset: "utf-8".into(),
+            encoding: "7BIT".into(),
+            ..TextMeta::default()
+        };
+    }
+
     #[test]
     fn test_bodystruct_parse() {
         use BodyStructure::*;
         let val: BodyStructure = BS_STRING.parse().unwrap();
         let expected_val = Alternative((
             Box::new([
-                Plain,
+                Plain(utf8_qp()),
                 Related((
                     Box::new([
-                        Html,
+                        Html(utf8_qp()),
                         Image(FileMeta {
                             file_type: "PNG".into(),
                             name: "og-image.png".into(),
+                            encoding: "BASE64".into(),
+                            size: Some(68590),
                         }),
                         Image(FileMeta {
                             file_type: "PNG".into(),
                             name: "1*jtOTreOJuxO8FtLYyU9Uyw.png".into(),
+                            encoding: "BASE64".into(),
+                            size: Some(180504),
                         }),
                     ]),
                     "Apple-Mail=_A6722D8A-5BBB-478B-8940-7B14BCE39030".into(),
@@ -332,5 +611,205 @@ mod test {
             "Apple-Mail=_D5EF70C3-5230-4B9A-A34D-20255319DA45".into(),
         ));
         assert_eq!(val, expected_val);
+        let sections = val.images().into_iter().map(|(x, _)| x).collect::<Vec<_>>();
+        assert_eq!(sections, ["2.2".into(), "2.3".into()]);
+    }
+
+    #[test]
+    fn test_bodystruct_parse_message() {
+        use BodyStructure::*;
+        let raw = r#"* 7 FETCH (BODYSTRUCTURE (("TEXT" "HTML" ("CHARSET" "utf-8") NIL NIL "7BIT" 20 1 NIL NIL NIL)("MESSAGE" "RFC822" ("NAME" "fwd.eml") NIL NIL "7BIT" 1234 ("Mon, 1 Jan 2024 10:00:00 +0000" "Fwd: (TEXT) \"quoted\"" (("Bob" NIL "bob" "x.com")) (("Bob" NIL "bob" "x.com")) (("Bob" NIL "bob" "x.com")) (("Al" NIL "al" "y.com")) NIL NIL NIL "<id@x.com>") (("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 10 1 NIL NIL NIL)("IMAGE" "PNG" ("NAME" "a.png") NIL NIL "BASE64" 100 NIL NIL NIL) "MIXED" ("BOUNDARY" "inner") NIL NIL) 40 NIL NIL NIL) "MIXED" ("BOUNDARY" "outer") NIL NIL))
+"#;
+        let val: BodyStructure = raw.parse().unwrap();
+        let image = FileMeta {
+            file_type: "PNG".into(),
+            name: "a.png".into(),
+            encoding: "BASE64".into(),
+            size: Some(100),
+        };
+        let expected_val = Mixed((
+            Box::new([
+                Html(utf8_7bit()),
+                Message((
+                    Box::new([Mixed((
+                        Box::new([Plain(utf8_7bit()), Image(image.clone())]),
+                        "inner".into(),
+                    ))]),
+                    "".into(),
+                )),
+            ]),
+            "outer".into(),
+        ));
+        assert_eq!(val, expected_val);
+        assert_eq!(val.find_text().as_deref(), Some("2.1"));
+        assert_eq!(val.attachments(), vec![&image]);
+        assert_eq!(val.images(), vec![("2.2".into(), &image)]);
+    }
+
+    #[test]
+    fn test_bodystruct_parse_text_subtypes() {
+        use BodyStructure::*;
+        let raw = r#"* 9 FETCH (BODYSTRUCTURE (("TEXT" "WATCH-HTML" ("CHARSET" "utf-8") NIL NIL "7BIT" 20 1 NIL NIL NIL)("TEXT" "HTML" ("CHARSET" "utf-8") NIL NIL "7BIT" 20 1 NIL NIL NIL)("TEXT" "CALENDAR" ("CHARSET" "utf-8" "METHOD" "REQUEST") NIL NIL "7BIT" 20 1 NIL NIL NIL) "ALTERNATIVE" ("BOUNDARY" "b1") NIL NIL))
+"#;
+        let val: BodyStructure = raw.parse().unwrap();
+        let expected_val = Alternative((
+            Box::new([
+                Text {
+                    subtype: "WATCH-HTML".into(),
+                    meta: utf8_7bit(),
+                },
+                Html(utf8_7bit()),
+                Text {
+                    subtype: "CALENDAR".into(),
+                    meta: utf8_7bit(),
+                },
+            ]),
+            "b1".into(),
+        ));
+        assert_eq!(val, expected_val);
+        assert_eq!(val.find_text().as_deref(), Some("1"));
+    }
+
+    #[test]
+    fn test_bodystruct_parse_media() {
+        use BodyStructure::*;
+        let raw = r#"* 3 FETCH (BODYSTRUCTURE (("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 20 1 NIL NIL NIL)("AUDIO" "MPEG" ("NAME" "memo.mp3") NIL NIL "BASE64" 4000 NIL ("ATTACHMENT" ("FILENAME" "memo.mp3")) NIL)("VIDEO" "MP4" ("NAME" "clip.mp4") NIL NIL "BASE64" 9000 NIL ("ATTACHMENT" ("FILENAME" "clip.mp4")) NIL)("APPLICATION" "PDF" ("NAME" "doc.pdf") NIL NIL "BASE64" 500 NIL ("ATTACHMENT" ("FILENAME" "doc.pdf")) NIL) "MIXED" ("BOUNDARY" "b2") NIL NIL))
+"#;
+        let val: BodyStructure = raw.parse().unwrap();
+        let meta = |file_type: &str, name: &str, size| FileMeta {
+            file_type: file_type.into(),
+            name: name.into(),
+            encoding: "BASE64".into(),
+            size: Some(size),
+        };
+        let expected_val = Mixed((
+            Box::new([
+                Plain(utf8_7bit()),
+                Audio(meta("MPEG", "memo.mp3", 4000)),
+                Video(meta("MP4", "clip.mp4", 9000)),
+                Application(meta("PDF", "doc.pdf", 500)),
+            ]),
+            "b2".into(),
+        ));
+        assert_eq!(val, expected_val);
+        assert_eq!(val.attachments().len(), 3);
+    }
+
+    #[test]
+    fn test_bodystruct_parse_charset() {
+        let raw = r#"* 4 FETCH (BODYSTRUCTURE ("TEXT" "PLAIN" ("FORMAT" "flowed" "CHARSET" "ISO-8859-1") NIL NIL "BASE64" 120 2 NIL NIL NIL))
+"#;
+        let val: BodyStructure = raw.parse().unwrap();
+        let section = val.find_text().unwrap();
+        assert_eq!(&*section, "1");
+        let meta = val.part(&section).and_then(|x| x.text_meta()).unwrap();
+        assert_eq!(&*meta.charset, "ISO-8859-1");
+        assert_eq!(&*meta.encoding, "BASE64");
+        assert!(meta.flowed);
+        assert!(!meta.delsp);
+    }
+
+    /// Real world BODYSTRUCTURE responses that have broken the parser before.
+    const CORPUS: &[&str] = &[
+        // gmail plain text
+        r#"* 1 FETCH (BODYSTRUCTURE ("TEXT" "PLAIN" ("CHARSET" "UTF-8") NIL NIL "7BIT" 12 1 NIL NIL NIL NIL))"#,
+        // PGP signed
+        r#"* 2 FETCH (BODYSTRUCTURE (("TEXT" "PLAIN" ("CHARSET" "us-ascii") NIL NIL "QUOTED-PRINTABLE" 310 9 NIL NIL NIL NIL)("APPLICATION" "PGP-SIGNATURE" ("NAME" "signature.asc") NIL "OpenPGP digital signature" "7BIT" 833 NIL ("ATTACHMENT" ("FILENAME" "signature.asc")) NIL NIL) "SIGNED" ("MICALG" "pgp-sha256" "PROTOCOL" "application/pgp-signature" "BOUNDARY" "sig") NIL NIL NIL))"#,
+        // bounce with a delivery status
+        r#"* 3 FETCH (BODYSTRUCTURE (("TEXT" "PLAIN" ("CHARSET" "utf-8") NIL NIL "7BIT" 120 4 NIL NIL NIL NIL)("MESSAGE" "DELIVERY-STATUS" NIL NIL NIL "7BIT" 400 NIL NIL NIL NIL)("TEXT" "RFC822-HEADERS" ("CHARSET" "utf-8") NIL NIL "7BIT" 900 20 NIL NIL NIL NIL) "REPORT" ("REPORT-TYPE" "delivery-status" "BOUNDARY" "rep") NIL NIL NIL))"#,
+        // calendar invite
+        r#"* 4 FETCH (BODYSTRUCTURE (("TEXT" "PLAIN" ("CHARSET" "UTF-8") NIL NIL "7BIT" 50 2 NIL NIL NIL NIL)("TEXT" "CALENDAR" ("CHARSET" "UTF-8" "METHOD" "REQUEST") NIL NIL "BASE64" 2000 26 NIL NIL NIL NIL)("APPLICATION" "ICS" ("NAME" "invite.ics") NIL NIL "BASE64" 2000 NIL ("ATTACHMENT" ("FILENAME" "invite.ics")) NIL NIL) "MIXED" ("BOUNDARY" "cal") NIL NIL NIL))"#,
+        // unknown top level type, application without a name, lowercase
+        r#"* 5 FETCH (BODYSTRUCTURE (("text" "html" ("charset" "utf-8") NIL NIL "quoted-printable" 80 3 NIL NIL NIL NIL)("FONT" "WOFF2" NIL NIL NIL "BASE64" 4000 NIL NIL NIL NIL)("APPLICATION" "OCTET-STREAM" NIL NIL NIL "BASE64" 10 NIL NIL NIL NIL) "mixed" ("boundary" "x") NIL NIL NIL))"#,
+        // literal filename and empty strings
+        "* 6 FETCH (BODYSTRUCTURE ((\"TEXT\" \"PLAIN\" (\"CHARSET\" \"\") NIL \"\" \"7BIT\" 1 1 NIL NIL NIL NIL)(\"IMAGE\" \"PNG\" (\"NAME\" {9}\r\nimage.png) NIL NIL \"BASE64\" 10 NIL NIL NIL NIL) \"MIXED\" (\"BOUNDARY\" \"lit\") NIL NIL NIL))",
+        // no extension data at all
+        r#"* 7 FETCH (BODYSTRUCTURE (("TEXT" "PLAIN" NIL NIL NIL "7BIT" 1 1)("TEXT" "HTML" NIL NIL NIL "7BIT" 1 1) "ALTERNATIVE"))"#,
+    ];
+
+    #[test]
+    fn test_bodystruct_parse_corpus() {
+        use BodyStructure::*;
+        for raw in CORPUS {
+            let val = raw.parse::<BodyStructure>();
+            assert!(val.is_ok(), "failed to parse {}", raw);
+        }
+
+        let val: BodyStructure = CORPUS[1].parse().unwrap();
+        assert!(matches!(&val, Mixed((_, boundary)) if &**boundary == "sig"));
+        assert_eq!(val.attachments()[0].name.as_ref(), "signature.asc");
+
+        let val: BodyStructure = CORPUS[4].parse().unwrap();
+        assert!(matches!(val.part("1"), Some(Html(_))));
+        let attachments = val.attachments();
+        assert_eq!(attachments.len(), 2);
+        assert_eq!(&*attachments[0].file_type, "FONT/WOFF2");
+        assert_eq!(&*attachments[1].name, "");
+        assert!(matches!(val.part("3"), Some(Application(_))));
+
+        let val: BodyStructure = CORPUS[5].parse().unwrap();
+        assert_eq!(&*val.attachments()[0].name, "image.png");
+
+        let val: BodyStructure = CORPUS[6].parse().unwrap();
+        assert!(matches!(val, Alternative(_)));
+    }
+
+    #[test]
+    fn test_bodystruct_parse_non_ascii() {
+        // literal lengths count bytes, not chars
+        let raw = "* 8 FETCH (BODYSTRUCTURE ((\"TEXT\" \"PLAIN\" (\"CHARSET\" \"UTF-8\") NIL \"résumé\" \"7BIT\" 1 1 NIL NIL NIL NIL)(\"IMAGE\" \"PNG\" (\"NAME\" {9}\r\ncafé.png) NIL NIL \"BASE64\" 10 NIL NIL NIL NIL) \"MIXED\" (\"BOUNDARY\" \"b\") NIL NIL NIL))";
+        let val: BodyStructure = raw.parse().unwrap();
+        assert_eq!(&*val.attachments()[0].name, "café.png");
+        assert!(matches!(val, BodyStructure::Mixed((_, boundary)) if &*boundary == "b"));
+    }
+
+    #[test]
+    fn test_read_quoted_escapes() {
+        let mut reader = StrReader::new(r#""a\"b" "c:\\dir" "plain""#);
+        assert_eq!(reader.read_string().as_deref(), Some(r#"a"b"#));
+        assert_eq!(reader.read_string().as_deref(), Some(r"c:\dir"));
+        assert!(matches!(reader.read_string(), Some(Cow::Borrowed("plain"))));
+
+        let raw = r#"* 9 FETCH (BODYSTRUCTURE (("TEXT" "PLAIN" NIL NIL NIL "7BIT" 1 1)("IMAGE" "PNG" ("NAME" "say \"hi\".png") NIL NIL "BASE64" 10 NIL NIL NIL NIL) "MIXED" ("BOUNDARY" "b\\\"q")))"#;
+        let val: BodyStructure = raw.parse().unwrap();
+        assert_eq!(&*val.attachments()[0].name, r#"say "hi".png"#);
+        assert!(matches!(val, BodyStructure::Mixed((_, boundary)) if &*boundary == r#"b\"q"#));
+    }
+
+    #[test]
+    fn test_read_literal_lengths() {
+        let mut reader = StrReader::new("{007}\r\nabcdefg \"next\"");
+        assert_eq!(reader.read_string().as_deref(), Some("abcdefg"));
+        assert_eq!(reader.read_string().as_deref(), Some("next"));
+        let mut reader = StrReader::new("{18446744073709551615}\r\nabc");
+        assert_eq!(reader.read_string(), None);
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_parse_never_panics(s in r#"[()" A-Za-z0-9{}+\r\n]{0,200}"#) {
+            let _ = s.parse::<BodyStructure>();
+            let _ = format!("* 1 FETCH (BODYSTRUCTURE ({}", s).parse::<BodyStructure>();
+        }
+
+        #[test]
+        fn test_literal_never_panics(
+            padding in "[0+]{0,3}",
+            len in proptest::prop_oneof![0..40usize, usize::MAX - 40..=usize::MAX],
+            rest in "[ -~]{0,20}",
+        ) {
+            let raw = format!(
+                "* 1 FETCH (BODYSTRUCTURE (\"TEXT\" \"PLAIN\" (\"NAME\" {{{}{}}}\r\n{}",
+                padding, len, rest
+            );
+            let _ = raw.parse::<BodyStructure>();
+        }
+
+        #[test]
+        fn test_truncated_never_panics(end in 0..BS_STRING.len()) {
+            if let Some(prefix) = BS_STRING.get(..end) {
+                let _ = prefix.parse::<BodyStructure>();
+            }
+        }
     }
 }
@@ -0,0 +1,107 @@
+use crate::{
+    tls::{self, TlsOptions, TlsVersion},
+    trace::Trace,
+    IMap,
+};
+use anyhow::{Context, Result};
+use std::{
+    net::{TcpStream, ToSocketAddrs},
+    path::PathBuf,
+    time::Duration,
+};
+
+/// Configures the TLS and socket options used by [`IMap`] before connecting.
+#[derive(Debug, Clone)]
+pub struct ImapBuilder {
+    server: Box<str>,
+    port: u32,
+    sni: Option<Box<str>>,
+    tls: TlsOptions,
+    connect_timeout: Option<Duration>,
+    read_timeout: Option<Duration>,
+    write_timeout: Option<Duration>,
+    trace: Option<Trace>,
+}
+
+impl ImapBuilder {
+    pub fn new(server: &str, port: u32) -> Self {
+        return Self {
+            server: server.into(),
+            port,
+            sni: None,
+            tls: TlsOptions::default(),
+            connect_timeout: None,
+            read_timeout: None,
+            write_timeout: None,
+            trace: None,
+        };
+    }
+
+    /// Host name sent for SNI and checked against the certificate, defaults
+    /// to the server address.
+    pub fn sni(mut self, host: &str) -> Self {
+        self.sni = Some(host.into());
+        return self;
+    }
+
+    /// Trust the CA certificates in the given PEM file in addition to the
+    /// system roots.
+    pub fn ca_file(mut self, path: impl Into<PathBuf>) -> Self {
+        self.tls.ca_file = Some(path.into());
+        return self;
+    }
+
+    /// Skip certificate verification entirely. Only use this for development
+    /// servers, it makes the connection trivially interceptable.
+    pub fn danger_accept_invalid_certs(mut self, accept: bool) -> Self {
+        self.tls.accept_invalid_certs = accept;
+        return self;
+    }
+
+    pub fn min_tls_version(mut self, version: TlsVersion) -> Self {
+        self.tls.min_tls_version = Some(version);
+        return self;
+    }
+
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.connect_timeout = Some(timeout);
+        return self;
+    }
+
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.read_timeout = Some(timeout);
+        return self;
+    }
+
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.write_timeout = Some(timeout);
+        return self;
+    }
+
+    /// Calls `callback` with every line sent and received, prefixed with
+    /// `C: ` or `S: `. Passwords are redacted. Off by default.
+    pub fn trace(mut self, callback: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        self.trace = Some(Trace::new(callback));
+        return self;
+    }
+
+    pub fn connect(self) -> Result<IMap> {
+        let address = format!("{}:{}", self.server, self.port);
+        let stream = match self.connect_timeout {
+            Some(timeout) => {
+                let socket_addr = address
+                    .to_socket_addrs()?
+                    .next()
+                    .context(format!("Couldn't resolve {}", address))?;
+                TcpStream::connect_timeout(&socket_addr, timeout)?
+            }
+            None => TcpStream::connect(&address)?,
+        };
+        stream.set_read_timeout(self.read_timeout)?;
+        stream.set_write_timeout(self.write_timeout)?;
+
+        let domain = self.sni.as_deref().unwrap_or(&self.server);
+        let stream = tls::wrap(&self.tls, domain, stream)?;
+        return IMap::with_trace(stream, self.trace);
+    }
+}
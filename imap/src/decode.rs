@@ -0,0 +1,339 @@
+use crate::TextMeta;
+use anyhow::{Context, Result};
+use base64::{
+    alphabet,
+    engine::{DecodePaddingMode, GeneralPurpose, GeneralPurposeConfig},
+    Engine,
+};
+use encoding_rs::Encoding;
+
+const BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// Undoes a `Content-Transfer-Encoding`, 7BIT/8BIT/BINARY come back as is.
+pub fn decode_transfer_encoding(bytes: &[u8], encoding: &str) -> Result<Vec<u8>> {
+    if encoding.eq_ignore_ascii_case("BASE64") {
+        let stripped: Vec<u8> = bytes
+            .iter()
+            .copied()
+            .filter(|x| !x.is_ascii_whitespace())
+            .collect();
+        return BASE64.decode(stripped).context("Invalid base64 body");
+    }
+    if encoding.eq_ignore_ascii_case("QUOTED-PRINTABLE") {
+        return Ok(decode_quoted_printable(bytes));
+    }
+    return Ok(bytes.to_vec());
+}
+
+/// Transcodes `bytes` from `charset` to UTF-8, unknown charsets are treated
+/// as UTF-8 and invalid sequences are replaced.
+pub fn decode_charset(bytes: &[u8], charset: &str) -> String {
+    let encoding = Encoding::for_label(charset.as_bytes()).unwrap_or(encoding_rs::UTF_8);
+    let (decoded, _, _) = encoding.decode(bytes);
+    return decoded.into_owned();
+}
+
+/// A single line preview of the start of a text part, quoted lines are
+/// dropped and whitespace is collapsed. `bytes` may be cut off anywhere.
+pub fn preview(bytes: &[u8], encoding: &str, charset: &str, max_len: usize) -> String {
+    let mut bytes = bytes.to_vec();
+    if encoding.eq_ignore_ascii_case("BASE64") {
+        // only decode whole base64 groups
+        bytes.retain(|x| !x.is_ascii_whitespace());
+        bytes.truncate(bytes.len() / 4 * 4);
+    }
+    let decoded = decode_transfer_encoding(&bytes, encoding).unwrap_or_default();
+    let text = decode_charset(&decoded, charset);
+    // a multibyte char cut in half decodes to a replacement char
+    let text = text.trim_end_matches('\u{FFFD}');
+
+    let words = text
+        .lines()
+        .map(str::trim)
+        .filter(|line| !line.starts_with('>'))
+        .flat_map(str::split_whitespace)
+        .collect::<Vec<_>>()
+        .join(" ");
+    return words.chars().take(max_len).collect();
+}
+
+/// Decodes a text part piece by piece as it arrives. Input is held back
+/// until it can be decoded on its own, whole lines for quoted-printable and
+/// whole groups for base64, and the charset decoder keeps multibyte
+/// sequences cut between pieces.
+pub struct StreamDecoder {
+    encoding: Box<str>,
+    pending: Vec<u8>,
+    decoder: encoding_rs::Decoder,
+}
+
+impl StreamDecoder {
+    pub fn new(encoding: &str, charset: &str) -> Self {
+        let charset = Encoding::for_label(charset.as_bytes()).unwrap_or(encoding_rs::UTF_8);
+        return Self {
+            encoding: encoding.into(),
+            pending: vec![],
+            decoder: charset.new_decoder(),
+        };
+    }
+
+    /// Decodes as much of `bytes` as it can, the rest waits for the next
+    /// call or `finish`.
+    pub fn push(&mut self, bytes: &[u8]) -> Result<String> {
+        let ready = if self.encoding.eq_ignore_ascii_case("BASE64") {
+            self.pending
+                .extend(bytes.iter().filter(|x| !x.is_ascii_whitespace()));
+            self.pending.len() / 4 * 4
+        } else {
+            self.pending.extend_from_slice(bytes);
+            match self.pending.iter().rposition(|x| *x == b'\n') {
+                Some(end) => end + 1,
+                None => 0,
+            }
+        };
+        let rest = self.pending.split_off(ready);
+        let ready = std::mem::replace(&mut self.pending, rest);
+        return self.decode(&ready, false);
+    }
+
+    /// Decodes whatever was held back.
+    pub fn finish(mut self) -> Result<String> {
+        let rest = std::mem::take(&mut self.pending);
+        return self.decode(&rest, true);
+    }
+
+    fn decode(&mut self, bytes: &[u8], last: bool) -> Result<String> {
+        let decoded = decode_transfer_encoding(bytes, &self.encoding)?;
+        let capacity = self
+            .decoder
+            .max_utf8_buffer_length(decoded.len())
+            .context("Body too large to decode")?;
+        let mut result = String::with_capacity(capacity);
+        let _ = self.decoder.decode_to_string(&decoded, &mut result, last);
+        return Ok(result);
+    }
+}
+
+/// Reflows `format=flowed` text (RFC 3676) as it arrives: lines ending in
+/// a space are joined with the next one at the same quote depth, so
+/// paragraphs wrap to the reader's width instead of the sender's. Input that
+/// isn't flowed passes through.
+pub struct Reflow {
+    flowed: bool,
+    delsp: bool,
+    /// The unfinished last line.
+    pending: String,
+    /// The quote depth of the paragraph being joined, if the last line was
+    /// flowed.
+    joining: Option<usize>,
+}
+
+impl Reflow {
+    pub fn new(meta: &TextMeta) -> Self {
+        return Self {
+            flowed: meta.flowed,
+            delsp: meta.delsp,
+            pending: String::new(),
+            joining: None,
+        };
+    }
+
+    /// Reflows the complete lines of `text`, the rest waits for the next
+    /// call or `finish`.
+    pub fn push(&mut self, text: &str) -> String {
+        if !self.flowed {
+            return text.to_owned();
+        }
+        self.pending.push_str(text);
+        let end = match self.pending.rfind('\n') {
+            Some(end) => end + 1,
+            None => return String::new(),
+        };
+        let rest = self.pending.split_off(end);
+        let lines = std::mem::replace(&mut self.pending, rest);
+        let mut result = String::new();
+        for line in lines.lines() {
+            self.line(line, &mut result);
+        }
+        return result;
+    }
+
+    pub fn finish(mut self) -> String {
+        let mut result = String::new();
+        if !self.pending.is_empty() {
+            let line = std::mem::take(&mut self.pending);
+            self.line(&line, &mut result);
+        }
+        // a flowed last line has nothing to join
+        if self.joining.is_some() {
+            result.push_str("\r\n");
+        }
+        return result;
+    }
+
+    fn line(&mut self, line: &str, result: &mut String) {
+        let line = line.trim_end_matches('\r');
+        let depth = line.chars().take_while(|x| *x == '>').count();
+        let content = &line[depth..];
+        // a leading space is stuffed to protect the line, see RFC 3676 4.4
+        let content = content.strip_prefix(' ').unwrap_or(content);
+        // the signature separator stays a line of its own
+        let flowed = content.ends_with(' ') && content != "-- ";
+        let content = if flowed && self.delsp {
+            &content[..content.len() - 1]
+        } else {
+            content
+        };
+
+        match self.joining {
+            Some(joining) if joining == depth => {}
+            joining => {
+                if joining.is_some() {
+                    result.push_str("\r\n");
+                }
+                if depth > 0 {
+                    result.push_str(&">".repeat(depth));
+                    result.push(' ');
+                }
+            }
+        }
+        result.push_str(content);
+        if flowed {
+            self.joining = Some(depth);
+        } else {
+            result.push_str("\r\n");
+            self.joining = None;
+        }
+    }
+}
+
+fn decode_quoted_printable(bytes: &[u8]) -> Vec<u8> {
+    let mut result = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        if bytes[i] != b'=' {
+            result.push(bytes[i]);
+            i += 1;
+            continue;
+        }
+        let rest = &bytes[i + 1..];
+        // soft line break
+        if rest.starts_with(b"\r\n") {
+            i += 3;
+            continue;
+        }
+        if rest.starts_with(b"\n") {
+            i += 2;
+            continue;
+        }
+        let hex = rest
+            .get(..2)
+            .and_then(|hex| std::str::from_utf8(hex).ok())
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        match hex {
+            Some(byte) => {
+                result.push(byte);
+                i += 3;
+            }
+            // not valid quoted-printable, keep it as is
+            None => {
+                result.push(b'=');
+                i += 1;
+            }
+        }
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_decode() {
+        let qp = b"caf=E9 au lait, a very long line that got =\r\nwrapped =3D done";
+        let decoded = decode_transfer_encoding(qp, "QUOTED-PRINTABLE").unwrap();
+        assert_eq!(
+            decode_charset(&decoded, "ISO-8859-1"),
+            "café au lait, a very long line that got wrapped = done"
+        );
+
+        let b64 = b"Y2Fmw6k=\r\n";
+        let decoded = decode_transfer_encoding(b64, "base64").unwrap();
+        assert_eq!(decode_charset(&decoded, "utf-8"), "café");
+    }
+
+    #[test]
+    fn test_stream_decoder() {
+        let qp = b"caf=C3=A9 au lait, a very long line that got =\r\nwrapped =3D done";
+        let mut decoder = StreamDecoder::new("QUOTED-PRINTABLE", "UTF-8");
+        let mut text = String::new();
+        for piece in qp.chunks(5) {
+            text.push_str(&decoder.push(piece).unwrap());
+        }
+        text.push_str(&decoder.finish().unwrap());
+        assert_eq!(
+            text,
+            "café au lait, a very long line that got wrapped = done"
+        );
+
+        // a multibyte char split across base64 groups
+        let b64 = b"SGkgYWxsLCBjYWbDqSBhdCBub29u\r\nIQ==";
+        let mut decoder = StreamDecoder::new("BASE64", "UTF-8");
+        let mut text = String::new();
+        for piece in b64.chunks(3) {
+            text.push_str(&decoder.push(piece).unwrap());
+        }
+        text.push_str(&decoder.finish().unwrap());
+        assert_eq!(text, "Hi all, café at noon!");
+    }
+
+    #[test]
+    fn test_reflow() {
+        let meta = TextMeta {
+            flowed: true,
+            ..TextMeta::default()
+        };
+        let text = "Hello there, this is \r\na long line.\r\n> quoted and \r\n> joined\r\n>> deeper\r\n \r\n-- \r\nSig \r\n";
+        let mut reflow = Reflow::new(&meta);
+        let mut result = String::new();
+        for piece in [&text[..7], &text[7..30], &text[30..]] {
+            result.push_str(&reflow.push(piece));
+        }
+        result.push_str(&reflow.finish());
+        assert_eq!(
+            result,
+            "Hello there, this is a long line.\r\n> quoted and joined\r\n>> deeper\r\n\r\n-- \r\nSig \r\n"
+        );
+
+        let meta = TextMeta {
+            flowed: true,
+            delsp: true,
+            ..TextMeta::default()
+        };
+        let mut reflow = Reflow::new(&meta);
+        let mut result = reflow.push("Hel \r\nlo\r\n");
+        result.push_str(&reflow.finish());
+        assert_eq!(result, "Hello\r\n");
+
+        let mut plain = Reflow::new(&TextMeta::default());
+        assert_eq!(plain.push("as is \r\nkept"), "as is \r\nkept");
+    }
+
+    #[test]
+    fn test_preview() {
+        let body = b"Hi all,\r\n\r\n> quoted   reply\r\nSee  you\tat caf\xc3";
+        assert_eq!(
+            preview(body, "7BIT", "UTF-8", 100),
+            "Hi all, See you at caf"
+        );
+        assert_eq!(preview(body, "7BIT", "UTF-8", 6), "Hi all");
+
+        // cut off in the middle of a base64 group
+        let b64 = b"SGkgYWxsLCBjYWbDqSBhdCBub29u\r\nIQ";
+        assert_eq!(preview(b64, "BASE64", "UTF-8", 100), "Hi all, café at noon");
+    }
+}
@@ -1,12 +1,57 @@
-use anyhow::{Context, Result};
-use std::ops::{Range, RangeBounds};
+use anyhow::{bail, Context, Result};
+use std::ops::RangeBounds;
 use std::str::FromStr;
 
-#[derive(Debug)]
+/// The role of a mailbox advertised with a special-use flag (RFC 6154).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpecialUse {
+    All,
+    Archive,
+    Drafts,
+    Flagged,
+    Junk,
+    Sent,
+    Trash,
+}
+
+impl SpecialUse {
+    /// The usual name of the mailbox, for servers that don't flag it.
+    pub fn default_name(&self) -> &'static str {
+        return match self {
+            Self::All => "All Mail",
+            Self::Archive => "Archive",
+            Self::Drafts => "Drafts",
+            Self::Flagged => "Flagged",
+            Self::Junk => "Junk",
+            Self::Sent => "Sent",
+            Self::Trash => "Trash",
+        };
+    }
+}
+
+impl FromStr for SpecialUse {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        return Ok(match s.to_ascii_lowercase().as_str() {
+            "\\all" => Self::All,
+            "\\archive" => Self::Archive,
+            "\\drafts" => Self::Drafts,
+            "\\flagged" => Self::Flagged,
+            "\\junk" => Self::Junk,
+            "\\sent" => Self::Sent,
+            "\\trash" => Self::Trash,
+            _ => bail!("Not a special-use flag {}", s),
+        });
+    }
+}
+
+#[derive(Debug, Clone)]
 pub struct Inbox {
     pub name: Box<str>,
     pub selectable: bool,
     pub has_children: bool,
+    pub special_use: Option<SpecialUse>,
 }
 
 impl FromStr for Inbox {
@@ -15,39 +60,34 @@ impl FromStr for Inbox {
     fn from_str(s: &str) -> Result<Self, Self::Err> {
         // assume this is one line
         // format is `* LIST (\{FLAG} \{FLAG}...) "{PATH}" "{NAME}"`
-        let (selectable, has_children) = s
-            .split(" ")
-            .skip(2)
-            .take_while(|&s| s.ends_with(")"))
-            .map(|mut word| {
-                if word.starts_with('(') {
-                    word = &word[1..];
-                }
-                if word.ends_with(')') {
-                    word = &word[0..word.len() - 1];
-                }
-                return word;
-            })
-            .fold((true, true), |(mut selectable, mut has_children), flag| {
-                if flag == "\\Noselect" {
-                    selectable = false;
-                } else if flag == "\\HasNoChildren" {
-                    has_children = false;
-                }
-                return (selectable, has_children);
-            });
+        let flags = s
+            .split_once('(')
+            .and_then(|(_, rest)| rest.split_once(')'))
+            .map_or("", |(flags, _)| flags);
+        let mut selectable = true;
+        let mut has_children = true;
+        let mut special_use = None;
+        for flag in flags.split_whitespace() {
+            if flag.eq_ignore_ascii_case("\\Noselect") {
+                selectable = false;
+            } else if flag.eq_ignore_ascii_case("\\HasNoChildren") {
+                has_children = false;
+            } else if let Ok(x) = flag.parse() {
+                special_use = Some(x);
+            }
+        }
 
         let name = s
             .split('"')
             .rev()
-            .skip(1)
-            .next()
+            .nth(1)
             .context(format!("Couldn't find name for {}", s))?;
 
         return Ok(Self {
             name: name.into(),
             selectable,
             has_children,
+            special_use,
         });
     }
 }
@@ -79,5 +119,20 @@ mod test {
         assert_eq!(&*inbox.name, "Deleted Messages");
         assert!(!inbox.has_children);
         assert!(inbox.selectable);
+        assert_eq!(inbox.special_use, None);
+
+        let test = "* LIST (\\HasNoChildren \\Drafts) \"/\" \"[Gmail]/Drafts\"";
+        let inbox: Inbox = test.parse().expect("Inbox parse fails");
+        assert_eq!(&*inbox.name, "[Gmail]/Drafts");
+        assert!(!inbox.has_children);
+        assert_eq!(inbox.special_use, Some(SpecialUse::Drafts));
+    }
+
+    proptest::proptest! {
+        #[test]
+        fn test_parse_never_panics(s in r#"[\\()" /A-Za-z]{0,100}"#) {
+            let _ = s.parse::<Inbox>();
+            let _ = format!("* LIST {}", s).parse::<Inbox>();
+        }
     }
 }
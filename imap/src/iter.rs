@@ -0,0 +1,58 @@
+use crate::{message::Message, IMap, FETCH_CHUNK_SIZE};
+use anyhow::Result;
+use std::collections::VecDeque;
+
+/// The messages of the selected inbox oldest first, fetching the headers
+/// a chunk at a time as they're needed. Made by `IMap::iter_messages`.
+pub struct Messages<'a> {
+    imap: &'a mut IMap,
+    /// The next id to fetch.
+    next_id: usize,
+    /// Read from the inbox on the first call to `next`.
+    count: Option<usize>,
+    buffer: VecDeque<Message>,
+}
+
+impl<'a> Messages<'a> {
+    pub(crate) fn new(imap: &'a mut IMap) -> Self {
+        return Self {
+            imap,
+            next_id: 1,
+            count: None,
+            buffer: VecDeque::new(),
+        };
+    }
+}
+
+impl Iterator for Messages<'_> {
+    type Item = Result<Message>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let count = match self.count {
+            Some(count) => count,
+            None => match self.imap.get_inbox_count() {
+                Ok(count) => *self.count.insert(count),
+                Err(e) => {
+                    self.count = Some(0);
+                    return Some(Err(e));
+                }
+            },
+        };
+        // a chunk can come back empty if its messages were all expunged,
+        // later ones may still have some
+        while self.buffer.is_empty() && self.next_id <= count {
+            let last = (self.next_id + FETCH_CHUNK_SIZE - 1).min(count);
+            let chunk = self.imap.get_n_email_headers(self.next_id..=last);
+            self.next_id = last + 1;
+            match chunk {
+                Ok(chunk) => self.buffer.extend(chunk.into_vec()),
+                Err(e) => {
+                    // stop after the error rather than skipping the chunk
+                    self.next_id = count + 1;
+                    return Some(Err(e));
+                }
+            }
+        }
+        return self.buffer.pop_front().map(Ok);
+    }
+}
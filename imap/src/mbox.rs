@@ -0,0 +1,99 @@
+use anyhow::Result;
+use std::io::Write;
+
+pub(crate) const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+pub(crate) const WEEKDAYS: [&str; 7] = ["Sun", "Mon", "Tue", "Wed", "Thu", "Fri", "Sat"];
+
+/// Writes one message in mboxrd format, a `From ` separator line, the
+/// message with LF line endings and `From ` lines quoted with `>`, then a
+/// blank line.
+pub fn write_message(
+    writer: &mut impl Write,
+    raw: &[u8],
+    internal_date: Option<&str>,
+) -> Result<()> {
+    let date = internal_date
+        .and_then(asctime)
+        .unwrap_or_else(|| "Thu Jan  1 00:00:00 1970".to_owned());
+    writeln!(writer, "From MAILER-DAEMON {}", date)?;
+
+    let raw = raw.strip_suffix(b"\r\n").unwrap_or(raw);
+    for line in raw.split(|&x| x == b'\n') {
+        let line = line.strip_suffix(b"\r").unwrap_or(line);
+        let unquoted = &line[line.iter().take_while(|&&x| x == b'>').count()..];
+        if unquoted.starts_with(b"From ") {
+            writer.write_all(b">")?;
+        }
+        writer.write_all(line)?;
+        writer.write_all(b"\n")?;
+    }
+    writer.write_all(b"\n")?;
+    return Ok(());
+}
+
+/// Turns an `INTERNALDATE` like `17-Jul-1996 02:44:25 -0700` into the
+/// asctime format of the separator line, `Wed Jul 17 02:44:25 1996`.
+fn asctime(internal_date: &str) -> Option<String> {
+    let (date, rest) = internal_date.trim().split_once(' ')?;
+    let time = rest.split_whitespace().next()?;
+    let mut parts = date.split('-');
+    let day: u32 = parts.next()?.trim().parse().ok()?;
+    let month = parts.next()?;
+    let year: i64 = parts.next()?.parse().ok()?;
+    let month_index = MONTHS.iter().position(|x| x.eq_ignore_ascii_case(month))?;
+
+    let weekday = (days_from_civil(year, month_index as i64 + 1, day as i64) + 4).rem_euclid(7);
+    return Some(format!(
+        "{} {} {:>2} {} {}",
+        WEEKDAYS[weekday as usize], MONTHS[month_index], day, time, year
+    ));
+}
+
+/// Days since 1970-01-01 of a date in the proleptic Gregorian calendar.
+pub(crate) fn days_from_civil(year: i64, month: i64, day: i64) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let month = (month + 9) % 12;
+    let day_of_year = (153 * month + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    return era * 146097 + day_of_era - 719468;
+}
+
+/// The year, month and day of a count of days since 1970-01-01, the inverse
+/// of `days_from_civil`.
+pub(crate) fn civil_from_days(days: i64) -> (i64, i64, i64) {
+    let days = days + 719468;
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month + 2) / 5 + 1;
+    let month = if month < 10 { month + 3 } else { month - 9 };
+    let year = year_of_era + era * 400 + i64::from(month <= 2);
+    return (year, month, day);
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_write_message() {
+        let raw = b"Subject: hi\r\n\r\nFrom here on\r\n>From before\r\nok\r\n";
+        let mut result = vec![];
+        write_message(&mut result, raw, Some(" 7-Jul-1996 02:44:25 -0700")).unwrap();
+        assert_eq!(
+            String::from_utf8(result).unwrap(),
+            "From MAILER-DAEMON Sun Jul  7 02:44:25 1996\nSubject: hi\n\n>From here on\n>>From before\nok\n\n"
+        );
+        assert_eq!(
+            asctime("29-Feb-2024 23:59:59 +0000").as_deref(),
+            Some("Thu Feb 29 23:59:59 2024")
+        );
+    }
+}
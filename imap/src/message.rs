@@ -1,10 +1,15 @@
-use anyhow::{Context, Result};
+use crate::mbox::{days_from_civil, MONTHS, WEEKDAYS};
+use anyhow::{bail, Context, Result};
 use std::{fmt::Display, str::FromStr};
 
+// shown in place of a missing or empty header
+const NO_SUBJECT: &str = "(no subject)";
+const UNKNOWN_SENDER: &str = "(unknown sender)";
+
 #[derive(Debug, Clone)]
 pub struct Contact {
-    name: Option<Box<str>>,
-    email: Box<str>,
+    pub name: Option<Box<str>>,
+    pub email: Box<str>,
 }
 
 impl Display for Contact {
@@ -16,20 +21,659 @@ impl Display for Contact {
     }
 }
 
+/// A system flag that can be set with `STORE`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Flag {
+    Seen,
+    Answered,
+    Flagged,
+    Deleted,
+    Draft,
+}
+
+impl Display for Flag {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(
+            f,
+            "{}",
+            match self {
+                Flag::Seen => "\\Seen",
+                Flag::Answered => "\\Answered",
+                Flag::Flagged => "\\Flagged",
+                Flag::Deleted => "\\Deleted",
+                Flag::Draft => "\\Draft",
+            }
+        );
+    }
+}
+
+/// The system flags of a message.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Flags {
+    pub seen: bool,
+    pub answered: bool,
+    pub flagged: bool,
+    pub deleted: bool,
+    pub draft: bool,
+    /// Keywords such as `$Junk` or Gmail labels.
+    pub custom: Vec<Box<str>>,
+}
+
+impl Flags {
+    pub fn get(&self, flag: Flag) -> bool {
+        return match flag {
+            Flag::Seen => self.seen,
+            Flag::Answered => self.answered,
+            Flag::Flagged => self.flagged,
+            Flag::Deleted => self.deleted,
+            Flag::Draft => self.draft,
+        };
+    }
+
+    pub fn set(&mut self, flag: Flag, value: bool) {
+        let field = match flag {
+            Flag::Seen => &mut self.seen,
+            Flag::Answered => &mut self.answered,
+            Flag::Flagged => &mut self.flagged,
+            Flag::Deleted => &mut self.deleted,
+            Flag::Draft => &mut self.draft,
+        };
+        *field = value;
+    }
+}
+
+impl FromStr for Flags {
+    type Err = anyhow::Error;
+
+    /// Parses the `FLAGS (...)` item of a FETCH response, missing means no
+    /// flags are set.
+    fn from_str(s: &str) -> Result<Self> {
+        let mut flags = Self::default();
+        let list = match s.split_once("FLAGS (") {
+            Some((_, rest)) => rest.split(')').next().unwrap_or(""),
+            None => return Ok(flags),
+        };
+        for flag in list.split_whitespace() {
+            match flag.to_ascii_lowercase().as_str() {
+                "\\seen" => flags.seen = true,
+                "\\answered" => flags.answered = true,
+                "\\flagged" => flags.flagged = true,
+                "\\deleted" => flags.deleted = true,
+                "\\draft" => flags.draft = true,
+                // other system flags like \Recent can't be stored
+                _ if flag.starts_with('\\') => {}
+                _ => flags.custom.push(flag.into()),
+            }
+        }
+        return Ok(flags);
+    }
+}
+
+/// The flags the selected mailbox keeps, from the `PERMANENTFLAGS` code of
+/// the `SELECT` response. Others can be set but are lost at the end of the
+/// session.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct PermanentFlags {
+    pub flags: Box<[Box<str>]>,
+    /// Whether new keywords can be created, `\*` in the list.
+    pub keywords: bool,
+}
+
+impl PermanentFlags {
+    pub fn allows(&self, flag: &str) -> bool {
+        return self.flags.iter().any(|x| x.eq_ignore_ascii_case(flag));
+    }
+}
+
+impl FromStr for PermanentFlags {
+    type Err = anyhow::Error;
+
+    /// Parses the `[PERMANENTFLAGS (...)]` code out of a `SELECT` response.
+    fn from_str(s: &str) -> Result<Self> {
+        let (_, rest) = s
+            .split_once("[PERMANENTFLAGS (")
+            .context("No PERMANENTFLAGS found")?;
+        let list = rest.split(')').next().unwrap_or("");
+        let mut result = Self::default();
+        let mut flags = vec![];
+        for flag in list.split_whitespace() {
+            if flag == "\\*" {
+                result.keywords = true;
+            } else {
+                flags.push(flag.into());
+            }
+        }
+        result.flags = flags.into();
+        return Ok(result);
+    }
+}
+
+/// Gmail's own message data from the `X-GM-EXT-1` extension, see
+/// `IMap::fetch_gmail_info`.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GmailInfo {
+    /// Shared by every message of a conversation, `X-GM-THRID`.
+    pub thread_id: u64,
+    /// Stays the same across folders, `X-GM-MSGID`.
+    pub message_id: Option<u64>,
+    /// `X-GM-LABELS`, system ones start with a backslash, e.g. `\Inbox`.
+    pub labels: Box<[Box<str>]>,
+}
+
+impl FromStr for GmailInfo {
+    type Err = anyhow::Error;
+
+    /// Parses the Gmail items of a FETCH response line.
+    fn from_str(s: &str) -> Result<Self> {
+        let number = |name: &str| {
+            let (_, rest) = s.split_once(name)?;
+            return rest.trim_start().split([' ', ')']).next()?.parse().ok();
+        };
+        let thread_id = number("X-GM-THRID").context("No X-GM-THRID found")?;
+        let labels = match s.split_once("X-GM-LABELS (") {
+            Some((_, rest)) => parse_labels(rest),
+            None => vec![],
+        };
+        return Ok(Self {
+            thread_id,
+            message_id: number("X-GM-MSGID"),
+            labels: labels.into(),
+        });
+    }
+}
+
+/// Reads labels up to the closing paren, quoted ones can hold spaces.
+fn parse_labels(list: &str) -> Vec<Box<str>> {
+    let mut labels = vec![];
+    let mut chars = list.chars();
+    let mut current = String::new();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' => {
+                while let Some(c) = chars.next() {
+                    match c {
+                        '\\' => current.extend(chars.next()),
+                        '"' => break,
+                        c => current.push(c),
+                    }
+                }
+            }
+            ' ' | ')' => {
+                if !current.is_empty() {
+                    labels.push(std::mem::take(&mut current).into());
+                }
+                if c == ')' {
+                    break;
+                }
+            }
+            c => current.push(c),
+        }
+    }
+    return labels;
+}
+
+/// When the server received a message, from `INTERNALDATE`. Unlike the
+/// `Date:` header the sender can't get it wrong, so it's what to sort by.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct InternalDate {
+    pub year: i64,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8,
+    /// Offset from UTC in minutes, e.g. -240 for `-0400`.
+    pub offset: i16,
+}
+
+impl InternalDate {
+    /// Seconds since the Unix epoch.
+    pub fn timestamp(&self) -> i64 {
+        let days = days_from_civil(self.year, self.month.into(), self.day.into());
+        let seconds = i64::from(self.hour) * 3600 + i64::from(self.minute) * 60;
+        return days * 86400 + seconds + i64::from(self.second) - i64::from(self.offset) * 60;
+    }
+
+    /// Just the day and month, e.g. `12 Jun`, for lists.
+    pub fn short(&self) -> String {
+        return format!("{} {}", self.day, MONTHS[usize::from(self.month) - 1]);
+    }
+
+    /// The weekday, date and time, e.g. `Wed, 12 Jun 2024 at 09:31`, for
+    /// reply attributions.
+    pub fn long(&self) -> String {
+        let days = days_from_civil(self.year, self.month.into(), self.day.into());
+        return format!(
+            "{}, {} {} {} at {:02}:{:02}",
+            WEEKDAYS[(days + 4).rem_euclid(7) as usize],
+            self.day,
+            MONTHS[usize::from(self.month) - 1],
+            self.year,
+            self.hour,
+            self.minute
+        );
+    }
+}
+
+impl Ord for InternalDate {
+    /// Earlier instants first. The same instant in different zones still
+    /// differs, so the fields break ties to agree with `Eq`.
+    fn cmp(&self, other: &Self) -> std::cmp::Ordering {
+        let fields = |date: &Self| {
+            let time = (date.hour, date.minute, date.second, date.offset);
+            return (date.year, date.month, date.day, time);
+        };
+        return self
+            .timestamp()
+            .cmp(&other.timestamp())
+            .then_with(|| fields(self).cmp(&fields(other)));
+    }
+}
+
+impl PartialOrd for InternalDate {
+    fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
+        return Some(self.cmp(other));
+    }
+}
+
+impl FromStr for InternalDate {
+    type Err = anyhow::Error;
+
+    /// Parses the quoted value of `INTERNALDATE`, `12-Jun-2024 09:31:00 -0400`.
+    /// Days before the 10th may be padded with a space.
+    fn from_str(s: &str) -> Result<Self> {
+        let invalid = || format!("Invalid INTERNALDATE {}", s);
+        let mut parts = s.split_whitespace();
+        let (date, time, zone) = match (parts.next(), parts.next(), parts.next()) {
+            (Some(date), Some(time), Some(zone)) => (date, time, zone),
+            _ => bail!(invalid()),
+        };
+
+        let mut date = date.split('-');
+        let day = date.next().and_then(|x| x.parse().ok());
+        let month = date
+            .next()
+            .and_then(|x| MONTHS.iter().position(|m| m.eq_ignore_ascii_case(x)));
+        let year = date.next().and_then(|x| x.parse().ok());
+        let mut time = time.split(':').map(|x| x.parse::<u8>().ok());
+        let (hour, minute, second) = (time.next(), time.next(), time.next());
+
+        let sign = match zone.get(..1) {
+            Some("+") => 1,
+            Some("-") => -1,
+            _ => bail!(invalid()),
+        };
+        let zone_hours = zone.get(1..3).and_then(|x| x.parse::<i16>().ok());
+        let zone_minutes = zone.get(3..5).and_then(|x| x.parse::<i16>().ok());
+
+        return match (
+            day,
+            month,
+            year,
+            hour,
+            minute,
+            second,
+            zone_hours,
+            zone_minutes,
+        ) {
+            (
+                Some(day @ 1..=31),
+                Some(month),
+                Some(year),
+                Some(Some(hour @ 0..=23)),
+                Some(Some(minute @ 0..=59)),
+                Some(Some(second @ 0..=60)),
+                Some(zone_hours),
+                Some(zone_minutes),
+            ) => Ok(Self {
+                year,
+                month: month as u8 + 1,
+                day,
+                hour,
+                minute,
+                second,
+                offset: sign * (zone_hours * 60 + zone_minutes),
+            }),
+            _ => bail!(invalid()),
+        };
+    }
+}
+
+impl Display for InternalDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let sign = if self.offset < 0 { '-' } else { '+' };
+        let offset = self.offset.unsigned_abs();
+        return write!(
+            f,
+            "{:02}-{}-{} {:02}:{:02}:{:02} {}{:02}{:02}",
+            self.day,
+            MONTHS[usize::from(self.month) - 1],
+            self.year,
+            self.hour,
+            self.minute,
+            self.second,
+            sign,
+            offset / 60,
+            offset % 60
+        );
+    }
+}
+
+/// One method's verdict in `Authentication-Results:`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthStatus {
+    Pass,
+    /// `fail` and `softfail`.
+    Fail,
+    /// `none`, `neutral`, `temperror` and the like, nothing either way.
+    Other,
+}
+
+impl FromStr for AuthStatus {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        return Ok(match s.to_ascii_lowercase().as_str() {
+            "pass" => Self::Pass,
+            "fail" | "softfail" | "permerror" => Self::Fail,
+            "" => bail!("No result"),
+            _ => Self::Other,
+        });
+    }
+}
+
+/// What the receiving server found checking the sender, from the topmost
+/// `Authentication-Results:` (RFC 8601). Nothing is verified here, the
+/// server's word is taken, so check `added_by` first: anyone can write the
+/// header, only the topmost one from a known server counts.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct AuthResults {
+    /// The name of the server that did the checks, e.g. `mx.google.com`.
+    pub authserv_id: Box<str>,
+    pub dkim: Option<AuthStatus>,
+    pub spf: Option<AuthStatus>,
+    pub dmarc: Option<AuthStatus>,
+}
+
+impl AuthResults {
+    /// Whether the results come from `authserv_id`, the receiving server.
+    pub fn added_by(&self, authserv_id: &str) -> bool {
+        return self.authserv_id.eq_ignore_ascii_case(authserv_id);
+    }
+
+    /// The methods that failed, by name.
+    pub fn failures(&self) -> Vec<&'static str> {
+        return [
+            ("dkim", self.dkim),
+            ("spf", self.spf),
+            ("dmarc", self.dmarc),
+        ]
+        .into_iter()
+        .filter(|(_, x)| *x == Some(AuthStatus::Fail))
+        .map(|(name, _)| name)
+        .collect();
+    }
+
+    /// DMARC passed, or both DKIM and SPF did where there's no DMARC result.
+    pub fn authenticated(&self) -> bool {
+        return match self.dmarc {
+            Some(dmarc) => dmarc == AuthStatus::Pass,
+            None => self.dkim == Some(AuthStatus::Pass) && self.spf == Some(AuthStatus::Pass),
+        };
+    }
+}
+
+impl FromStr for AuthResults {
+    type Err = anyhow::Error;
+
+    /// Reads `authserv-id; dkim=pass header.d=x.com; spf=fail (...) ...`,
+    /// a message signed more than once counts as passing DKIM if any
+    /// signature passed.
+    fn from_str(s: &str) -> Result<Self> {
+        let (authserv_id, entries) = s.split_once(';').unwrap_or((s, ""));
+        // an optional version can follow the id
+        let mut result = Self {
+            authserv_id: authserv_id.split_whitespace().next().unwrap_or("").into(),
+            ..Self::default()
+        };
+        if result.authserv_id.is_empty() {
+            bail!("No authserv-id in {}", s);
+        }
+        for entry in entries.split(';') {
+            let (method, rest) = match entry.trim().split_once('=') {
+                Some(x) => x,
+                None => continue,
+            };
+            let status = match rest.split([' ', '(']).next().unwrap_or("").parse() {
+                Ok(x) => x,
+                Err(_) => continue,
+            };
+            let slot = match method.trim().to_ascii_lowercase().as_str() {
+                "dkim" => &mut result.dkim,
+                "spf" => &mut result.spf,
+                "dmarc" => &mut result.dmarc,
+                _ => continue,
+            };
+            if *slot != Some(AuthStatus::Pass) {
+                *slot = Some(status);
+            }
+        }
+        if (result.dkim, result.spf, result.dmarc) == (None, None, None) {
+            bail!("No DKIM, SPF or DMARC result in {}", s);
+        }
+        return Ok(result);
+    }
+}
+
+/// How urgent the sender marked a message, from `X-Priority:`,
+/// `Importance:` or `Priority:`.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Priority {
+    High,
+    #[default]
+    Normal,
+    Low,
+}
+
+impl FromStr for Priority {
+    type Err = anyhow::Error;
+
+    /// Takes the first word, so `X-Priority: 1 (Highest)` is high.
+    fn from_str(s: &str) -> Result<Self> {
+        let word = s.split([' ', '(']).next().unwrap_or("");
+        return Ok(match word.to_ascii_lowercase().as_str() {
+            "1" | "2" | "high" | "highest" | "urgent" => Self::High,
+            "3" | "normal" => Self::Normal,
+            "4" | "5" | "low" | "lowest" | "non-urgent" => Self::Low,
+            _ => bail!("Unknown priority {}", s),
+        });
+    }
+}
+
+/// How to leave a mailing list, from `List-Unsubscribe:` (RFC 2369) and
+/// `List-Unsubscribe-Post:` (RFC 8058).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Unsubscribe {
+    /// The whole `mailto:` URI, it can carry a subject and body.
+    pub mailto: Option<Box<str>>,
+    pub https: Option<Box<str>>,
+    /// POSTing to `https` unsubscribes without any further steps.
+    pub one_click: bool,
+}
+
+impl FromStr for Unsubscribe {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let mut result = Self::default();
+        for uri in parse_message_ids(s) {
+            let scheme = uri.split_once(':').map(|(scheme, _)| scheme);
+            match scheme {
+                Some(x) if x.eq_ignore_ascii_case("mailto") => result.mailto = Some(uri),
+                Some(x) if x.eq_ignore_ascii_case("https") => result.https = Some(uri),
+                _ => {}
+            }
+        }
+        if result.mailto.is_none() && result.https.is_none() {
+            bail!("No mailto or https unsubscribe in {}", s);
+        }
+        return Ok(result);
+    }
+}
+
+/// A header `Message` can be parsed from, see
+/// `IMap::get_n_email_headers_with`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HeaderField {
+    Subject,
+    From,
+    ReplyTo,
+    To,
+    Cc,
+    Bcc,
+    MessageId,
+    InReplyTo,
+    References,
+    /// `List-Unsubscribe:` along with `List-Unsubscribe-Post:`.
+    ListUnsubscribe,
+    /// `X-Priority:` and the other headers `Priority` is read from.
+    Priority,
+    AuthenticationResults,
+    /// The sender's `Date:`, not in `DEFAULT` since `internal_date` is
+    /// usually enough.
+    Date,
+}
+
+impl HeaderField {
+    /// What the list and message views use.
+    pub const DEFAULT: &'static [HeaderField] = &[
+        HeaderField::Subject,
+        HeaderField::From,
+        HeaderField::ReplyTo,
+        HeaderField::To,
+        HeaderField::Cc,
+        HeaderField::Bcc,
+        HeaderField::MessageId,
+        HeaderField::InReplyTo,
+        HeaderField::References,
+        HeaderField::ListUnsubscribe,
+        HeaderField::Priority,
+        HeaderField::AuthenticationResults,
+    ];
+
+    /// The header names to ask for in `HEADER.FIELDS`.
+    pub fn names(&self) -> &'static [&'static str] {
+        return match self {
+            HeaderField::Subject => &["SUBJECT"],
+            HeaderField::From => &["FROM"],
+            HeaderField::ReplyTo => &["REPLY-TO"],
+            HeaderField::To => &["TO"],
+            HeaderField::Cc => &["CC"],
+            HeaderField::Bcc => &["BCC"],
+            HeaderField::MessageId => &["MESSAGE-ID"],
+            HeaderField::InReplyTo => &["IN-REPLY-TO"],
+            HeaderField::References => &["REFERENCES"],
+            HeaderField::ListUnsubscribe => &["LIST-UNSUBSCRIBE", "LIST-UNSUBSCRIBE-POST"],
+            HeaderField::Priority => &["X-PRIORITY", "IMPORTANCE", "PRIORITY"],
+            HeaderField::AuthenticationResults => &["AUTHENTICATION-RESULTS"],
+            HeaderField::Date => &["DATE"],
+        };
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Message {
     pub id: usize,
     pub subject: Box<str>,
-    pub from: Contact,
+    /// Usually one, but `From:` can list several authors. Empty if there's
+    /// no usable `From:`, shown as `(unknown sender)`.
+    pub from: Box<[Contact]>,
+    /// Where replies should go instead of `from`, e.g. a mailing list.
+    pub reply_to: Option<Box<[Contact]>>,
     pub to: Option<Box<[Contact]>>,
     pub cc: Option<Box<[Contact]>>,
     pub bcc: Option<Box<[Contact]>>,
-    pub read: bool,
+    pub flags: Flags,
+    pub message_id: Option<Box<str>>,
+    pub in_reply_to: Option<Box<str>>,
+    pub references: Box<[Box<str>]>,
+    /// The start of the plain text body, see `IMap::fetch_previews`.
+    pub preview: Option<Box<str>>,
+    /// The CONDSTORE modification sequence, when it was fetched.
+    pub modseq: Option<u64>,
+    /// When the server received it, when it was fetched.
+    pub internal_date: Option<InternalDate>,
+    /// The whole message in bytes, from `RFC822.SIZE`.
+    pub size: Option<usize>,
+    /// The `Date:` header as the sender wrote it, only when fetched with
+    /// `HeaderField::Date`.
+    pub date: Option<Box<str>>,
+    pub unsubscribe: Option<Unsubscribe>,
+    pub priority: Priority,
+    /// The receiving server's DKIM, SPF and DMARC verdicts, when it added
+    /// them.
+    pub auth: Option<AuthResults>,
+    /// Only set when fetched from a Gmail server.
+    pub gmail: Option<GmailInfo>,
+}
+
+impl Message {
+    /// Who a reply goes to, `reply_to` when the sender set one.
+    pub fn reply_recipients(&self) -> &[Contact] {
+        return self.reply_to.as_deref().unwrap_or(&self.from);
+    }
+
+    /// Stands in for message `id` when its headers couldn't be parsed, so
+    /// the rest of a page still shows.
+    pub fn unreadable(id: usize, error: &anyhow::Error) -> Self {
+        return Self {
+            id,
+            subject: format!("(unreadable message: {})", error).into(),
+            from: Box::new([]),
+            reply_to: None,
+            to: None,
+            cc: None,
+            bcc: None,
+            flags: Flags::default(),
+            message_id: None,
+            in_reply_to: None,
+            references: Box::new([]),
+            preview: None,
+            modseq: None,
+            internal_date: None,
+            size: None,
+            date: None,
+            unsubscribe: None,
+            priority: Priority::Normal,
+            auth: None,
+            gmail: None,
+        };
+    }
+
+    /// Just the From and Subject lines of `Display`, for when space is short.
+    pub fn brief(&self) -> String {
+        return format!(
+            "From: {}\nSubject: {}\n",
+            contact_list(&self.from),
+            self.subject
+        );
+    }
+}
+
+fn contact_list(contacts: &[Contact]) -> String {
+    if contacts.is_empty() {
+        return UNKNOWN_SENDER.to_owned();
+    }
+    return contacts
+        .iter()
+        .map(|x| format!("{}", x))
+        .collect::<Vec<_>>()
+        .join(",");
 }
 
 impl Display for Message {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        writeln!(f, "From: {}", self.from)?;
+        writeln!(f, "From: {}", contact_list(&self.from))?;
 
         if let Some(to_list) = &self.to {
             let to_str = to_list
@@ -56,6 +700,12 @@ impl Display for Message {
             writeln!(f, "BCC: {}", bcc_str)?;
         }
 
+        if let Some(date) = &self.internal_date {
+            writeln!(f, "Received: {}", date)?;
+        }
+        if let Some(message_id) = &self.message_id {
+            writeln!(f, "Message-ID: <{}>", message_id)?;
+        }
         writeln!(f, "Subject: {}", self.subject)?;
 
         return Ok(());
@@ -69,7 +719,8 @@ impl FromStr for Contact {
         return Ok(match s.split_once('<') {
             Some((name, email)) => Self {
                 name: Some(name.into()),
-                email: email[0..email.len() - 1].into(),
+                // up to the `>`, which a broken header can leave out
+                email: email.split('>').next().unwrap_or(email).into(),
             },
             None => Self {
                 name: None,
@@ -88,47 +739,373 @@ impl FromStr for Message {
             .find_map(|word| word.parse().ok())
             .context("No ID found")?;
 
-        let read = s.contains("\\Seen");
+        let first_line = s.lines().next().unwrap_or("");
+        let flags = first_line.parse()?;
+        let modseq = first_line
+            .split_once("MODSEQ (")
+            .and_then(|(_, rest)| rest.split(')').next()?.trim().parse().ok());
+        let internal_date = first_line
+            .split_once("INTERNALDATE \"")
+            .and_then(|(_, rest)| rest.split('"').next()?.parse().ok());
+        let size = first_line
+            .split_once("RFC822.SIZE ")
+            .and_then(|(_, rest)| rest.split([' ', ')']).next()?.parse().ok());
 
-        let (subject, from, to, cc, bcc) = s.lines().skip(1).fold(
-            (None, None, None, None, None),
-            |(mut subject, mut from, mut to, mut cc, mut bcc), val| {
-                if val.starts_with("Subject:") {
-                    subject = Some(&val[9..]);
-                }
-                if val.starts_with("From:") {
-                    from = val[6..].parse::<Contact>().ok()
-                }
-                if val.starts_with("To:") {
-                    to = val[4..]
-                        .split(",")
-                        .map(|contact| contact.parse().ok())
-                        .collect::<Option<Box<[Contact]>>>();
-                }
-                if val.starts_with("Cc:") {
-                    cc = val[4..]
-                        .split(",")
-                        .map(|contact| contact.parse().ok())
-                        .collect::<Option<Box<[Contact]>>>();
-                }
-                if val.starts_with("Bcc:") {
-                    bcc = val[5..]
-                        .split(",")
-                        .map(|contact| contact.parse().ok())
-                        .collect::<Option<Box<[Contact]>>>();
+        let mut subject = None;
+        let mut from: Option<Box<[Contact]>> = None;
+        let mut reply_to = None;
+        let mut to = None;
+        let mut cc = None;
+        let mut bcc = None;
+        let mut message_id = None;
+        let mut in_reply_to = None;
+        let mut references: Box<[Box<str>]> = Box::new([]);
+        let mut unsubscribe: Option<Unsubscribe> = None;
+        let mut one_click = false;
+        let mut priority = Priority::Normal;
+        let mut date = None;
+        let mut auth = None;
+        let mut auth_seen = false;
+
+        for val in unfold_headers(s.lines().skip(1)) {
+            let val = val.as_str();
+            if let Some(value) = header_value(val, "Subject") {
+                subject = Some(value.to_owned()).filter(|x| !x.is_empty());
+            }
+            if let Some(value) = header_value(val, "From") {
+                from = parse_address_list(value);
+            }
+            if let Some(value) = header_value(val, "Reply-To") {
+                reply_to = parse_address_list(value).filter(|x| !x.is_empty());
+            }
+            if let Some(value) = header_value(val, "To") {
+                to = parse_address_list(value);
+            }
+            if let Some(value) = header_value(val, "Cc") {
+                cc = parse_address_list(value);
+            }
+            if let Some(value) = header_value(val, "Bcc") {
+                bcc = parse_address_list(value);
+            }
+            if let Some(value) = header_value(val, "Message-ID") {
+                message_id = parse_message_ids(value).into_iter().next();
+            }
+            if let Some(value) = header_value(val, "In-Reply-To") {
+                in_reply_to = parse_message_ids(value).into_iter().next();
+            }
+            if let Some(value) = header_value(val, "References") {
+                references = parse_message_ids(value).into();
+            }
+            if let Some(value) = header_value(val, "List-Unsubscribe") {
+                unsubscribe = value.parse().ok();
+            }
+            if let Some(value) = ["X-Priority", "Importance", "Priority"]
+                .into_iter()
+                .find_map(|name| header_value(val, name))
+            {
+                priority = value.parse().unwrap_or(priority);
+            }
+            // each hop adds one on top, the first is our server's and the
+            // ones below could have come from the sender
+            if let Some(value) = header_value(val, "Authentication-Results") {
+                if !auth_seen {
+                    auth = value.parse().ok();
                 }
-                return (subject, from, to, cc, bcc);
-            },
-        );
+                auth_seen = true;
+            }
+            if let Some(value) = header_value(val, "Date") {
+                date = Some(value).filter(|x| !x.is_empty()).map(Box::from);
+            }
+            if let Some(value) = header_value(val, "List-Unsubscribe-Post") {
+                one_click = value.eq_ignore_ascii_case("List-Unsubscribe=One-Click");
+            }
+        }
+
+        if let Some(unsubscribe) = &mut unsubscribe {
+            unsubscribe.one_click = one_click && unsubscribe.https.is_some();
+        }
 
         return Ok(Self {
             id,
-            subject: subject.context("No subject found")?.into(),
-            from: from.context("No From found")?,
+            subject: subject.as_deref().unwrap_or(NO_SUBJECT).into(),
+            from: from.unwrap_or_default(),
+            reply_to,
             bcc,
             cc,
             to,
-            read,
+            flags,
+            message_id,
+            in_reply_to,
+            references,
+            preview: None,
+            modseq,
+            internal_date,
+            size,
+            date,
+            unsubscribe,
+            priority,
+            auth,
+            gmail: first_line.parse().ok(),
         });
     }
 }
+
+/// Joins folded header lines (continuations start with whitespace) back into
+/// a single logical line.
+fn unfold_headers<'a>(lines: impl Iterator<Item = &'a str>) -> Vec<String> {
+    let mut result: Vec<String> = vec![];
+    for line in lines {
+        let line = line.trim_end_matches('\r');
+        match result.last_mut() {
+            Some(last) if line.starts_with([' ', '\t']) => {
+                last.push(' ');
+                last.push_str(line.trim_start());
+            }
+            _ => result.push(line.to_owned()),
+        }
+    }
+    return result;
+}
+
+/// Returns the value of `line` if it is the header `name`, ignoring case since
+/// servers return header names as the sender wrote them.
+fn header_value<'a>(line: &'a str, name: &str) -> Option<&'a str> {
+    let (key, value) = line.split_once(':')?;
+    if !key.eq_ignore_ascii_case(name) {
+        return None;
+    }
+    return Some(value.trim());
+}
+
+/// Splits an address list header on the commas between addresses, not the
+/// ones inside a quoted name or `<>`.
+fn parse_address_list(value: &str) -> Option<Box<[Contact]>> {
+    let mut parts = vec![];
+    let (mut start, mut quoted, mut bracketed) = (0, false, false);
+    for (i, c) in value.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            '<' if !quoted => bracketed = true,
+            '>' if !quoted => bracketed = false,
+            ',' if !quoted && !bracketed => {
+                parts.push(&value[start..i]);
+                start = i + 1;
+            }
+            _ => {}
+        }
+    }
+    parts.push(&value[start..]);
+    return parts
+        .into_iter()
+        .map(str::trim)
+        .filter(|x| !x.is_empty())
+        .map(|x| x.parse().ok())
+        .collect();
+}
+
+/// Pulls every `<id>` out of a Message-ID style header, without the brackets.
+fn parse_message_ids(value: &str) -> Vec<Box<str>> {
+    return value
+        .split('<')
+        .skip(1)
+        .filter_map(|part| part.split_once('>'))
+        .map(|(id, _)| id.trim())
+        .filter(|id| !id.is_empty())
+        .map(|id| id.into())
+        .collect();
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_permanent_flags() {
+        let raw = "* FLAGS (\\Answered \\Seen $Junk)\r\n* OK [PERMANENTFLAGS (\\Deleted \\Seen \\*)] Limited\r\n";
+        let flags: PermanentFlags = raw.parse().unwrap();
+        assert!(flags.keywords);
+        assert!(flags.allows("\\seen"));
+        assert!(!flags.allows("\\Flagged"));
+        assert!("* 3 EXISTS\r\n".parse::<PermanentFlags>().is_err());
+    }
+
+    #[test]
+    fn test_parse_internal_date() {
+        let date: InternalDate = "12-Jun-2024 09:31:00 -0400".parse().unwrap();
+        assert_eq!((date.year, date.month, date.day), (2024, 6, 12));
+        assert_eq!(date.offset, -240);
+        assert_eq!(date.timestamp(), 1718199060);
+        assert_eq!(date.to_string(), "12-Jun-2024 09:31:00 -0400");
+        assert_eq!(date.short(), "12 Jun");
+        assert_eq!(date.long(), "Wed, 12 Jun 2024 at 09:31");
+
+        let padded: InternalDate = " 2-Jan-2024 00:00:00 +0000".parse().unwrap();
+        assert_eq!(padded.day, 2);
+        assert!(padded < date);
+        // the same instant seen from another zone
+        let utc: InternalDate = "12-Jun-2024 13:31:00 +0000".parse().unwrap();
+        assert_eq!(utc.timestamp(), date.timestamp());
+        assert_ne!(utc, date);
+        assert_ne!(utc.cmp(&date), std::cmp::Ordering::Equal);
+        assert!("12-Foo-2024 09:31:00 -0400"
+            .parse::<InternalDate>()
+            .is_err());
+        assert!("12-Jun-2024 09:31:00".parse::<InternalDate>().is_err());
+    }
+
+    #[test]
+    fn test_parse_address_list() {
+        let raw = "* 5 FETCH (FLAGS () BODY[HEADER.FIELDS (SUBJECT FROM TO)] {0}\r\nSubject: hi\r\nFrom: \"Doe, Jane\" <jane@x.com>, bob@y.com\r\nTo: a@b.com,c@d.com\r\n";
+        let message: Message = raw.parse().unwrap();
+        assert_eq!(message.from.len(), 2);
+        assert_eq!(&*message.from[0].email, "jane@x.com");
+        assert_eq!(&*message.from[1].email, "bob@y.com");
+        let to = message.to.as_ref().unwrap();
+        assert_eq!(&*to[1].email, "c@d.com");
+        assert!(message.reply_to.is_none());
+        assert!(message.brief().ends_with(",bob@y.com\nSubject: hi\n"));
+        assert_eq!(message.reply_recipients().len(), 2);
+
+        let raw = "* 5 FETCH (FLAGS () BODY[HEADER.FIELDS (SUBJECT FROM REPLY-TO)] {0}\r\nSubject: hi\r\nFrom: noreply@x.com\r\nreply-to: List <list@x.com>\r\n";
+        let message: Message = raw.parse().unwrap();
+        assert_eq!(&*message.reply_recipients()[0].email, "list@x.com");
+
+        let raw =
+            "* 5 FETCH (FLAGS () BODY[HEADER.FIELDS (SUBJECT FROM)] {0}\r\nSubject:\r\nFrom: \r\n";
+        let message: Message = raw.parse().unwrap();
+        assert!(message.from.is_empty());
+        assert_eq!(
+            message.brief(),
+            "From: (unknown sender)\nSubject: (no subject)\n"
+        );
+    }
+
+    #[test]
+    fn test_parse_unsubscribe() {
+        let raw = "* 5 FETCH (FLAGS () BODY[HEADER.FIELDS (SUBJECT FROM LIST-UNSUBSCRIBE LIST-UNSUBSCRIBE-POST)] {0}\r\nSubject: News\r\nFrom: news@x.com\r\nList-Unsubscribe: <mailto:leave@x.com?subject=stop>,\r\n <https://x.com/u?id=1>\r\nList-Unsubscribe-Post: List-Unsubscribe=One-Click\r\n";
+        let message: Message = raw.parse().unwrap();
+        assert_eq!(
+            message.unsubscribe,
+            Some(Unsubscribe {
+                mailto: Some("mailto:leave@x.com?subject=stop".into()),
+                https: Some("https://x.com/u?id=1".into()),
+                one_click: true,
+            })
+        );
+        assert!("<http://x.com/u>".parse::<Unsubscribe>().is_err());
+    }
+
+    #[test]
+    fn test_parse_auth_results() {
+        let auth: AuthResults = "mx.google.com; dkim=pass header.i=@x.com; \
+            dkim=fail (bad signature) header.i=@y.com; spf=softfail (google.com: \
+            domain of a@x.com does not designate 1.2.3.4) smtp.mailfrom=a@x.com; \
+            dmarc=pass (p=NONE) header.from=x.com"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            auth,
+            AuthResults {
+                authserv_id: "mx.google.com".into(),
+                dkim: Some(AuthStatus::Pass),
+                spf: Some(AuthStatus::Fail),
+                dmarc: Some(AuthStatus::Pass),
+            }
+        );
+        assert!(auth.authenticated());
+        assert!(auth.added_by("MX.google.com"));
+        assert_eq!(auth.failures(), ["spf"]);
+
+        let auth: AuthResults = "mx.x.com; spf=pass smtp.mailfrom=x.com".parse().unwrap();
+        assert!(!auth.authenticated());
+        assert!("mx.x.com; none".parse::<AuthResults>().is_err());
+        let versioned: AuthResults = "mx.x.com 1; spf=pass".parse().unwrap();
+        assert_eq!(&*versioned.authserv_id, "mx.x.com");
+        assert!("; spf=pass".parse::<AuthResults>().is_err());
+
+        // only the topmost header counts, even without results
+        let raw = "* 5 FETCH (BODY[HEADER.FIELDS (FROM AUTHENTICATION-RESULTS)] {0}\r\nFrom: a@x.com\r\nAuthentication-Results: mx.google.com; arc=none\r\nAuthentication-Results: mx.google.com; dmarc=pass\r\n";
+        let message: Message = raw.parse().unwrap();
+        assert_eq!(message.auth, None);
+    }
+
+    #[test]
+    fn test_parse_priority() {
+        assert_eq!("1 (Highest)".parse::<Priority>().unwrap(), Priority::High);
+        assert_eq!("Urgent".parse::<Priority>().unwrap(), Priority::High);
+        assert_eq!("5".parse::<Priority>().unwrap(), Priority::Low);
+        assert_eq!("low".parse::<Priority>().unwrap(), Priority::Low);
+        assert!("soon".parse::<Priority>().is_err());
+
+        let raw = "* 5 FETCH (FLAGS () BODY[HEADER.FIELDS (SUBJECT FROM X-PRIORITY)] {0}\r\nSubject: hi\r\nFrom: a@b.com\r\nX-Priority: 2\r\n";
+        assert_eq!(raw.parse::<Message>().unwrap().priority, Priority::High);
+        let raw = "* 5 FETCH (FLAGS () BODY[HEADER.FIELDS (SUBJECT FROM IMPORTANCE)] {0}\r\nSubject: hi\r\nFrom: a@b.com\r\nImportance: whenever\r\n";
+        assert_eq!(raw.parse::<Message>().unwrap().priority, Priority::Normal);
+    }
+
+    #[test]
+    fn test_parse_gmail_info() {
+        let line = "* 4 FETCH (X-GM-THRID 1278455344230334865 X-GM-MSGID 1278455344230334866 X-GM-LABELS (\\Inbox \\Important \"Work (old)\" Home \"say \\\"hi\\\"\"))";
+        let info: GmailInfo = line.parse().unwrap();
+        assert_eq!(info.thread_id, 1278455344230334865);
+        assert_eq!(info.message_id, Some(1278455344230334866));
+        assert_eq!(
+            info.labels,
+            ["\\Inbox", "\\Important", "Work (old)", "Home", "say \"hi\""]
+                .map(Box::from)
+                .into()
+        );
+        assert!("* 4 FETCH (FLAGS ())".parse::<GmailInfo>().is_err());
+    }
+
+    #[test]
+    fn test_parse_flags() {
+        let line =
+            "* 12 FETCH (FLAGS (\\Seen \\Flagged $Label1) BODY[HEADER.FIELDS (SUBJECT)] {20}";
+        let flags: Flags = line.parse().unwrap();
+        assert_eq!(
+            flags,
+            Flags {
+                seen: true,
+                flagged: true,
+                custom: vec!["$Label1".into()],
+                ..Flags::default()
+            }
+        );
+        assert_eq!(
+            "* 1 FETCH (FLAGS ())".parse::<Flags>().unwrap(),
+            Flags::default()
+        );
+    }
+
+    // IMAP syntax characters show up often enough to reach the odd paths
+    const SYNTAX: &str = r#"[\\()<>"{}\[\] \r\n*:;,=@.A-Za-z0-9-]{0,200}"#;
+
+    proptest::proptest! {
+        #[test]
+        fn test_parse_contact_never_panics(s in SYNTAX) {
+            let _ = s.parse::<Contact>();
+            let _ = parse_address_list(&s);
+        }
+
+        #[test]
+        fn test_parse_empty_headers(name in "Subject|From|Reply-To|To|Cc|Bcc|Date") {
+            let _ = format!("* 1 FETCH (FLAGS ())\r\n{}:\r\n", name).parse::<Message>();
+        }
+
+        #[test]
+        fn test_parse_message_never_panics(id in 0..1000usize, rest in SYNTAX) {
+            let _ = format!("* {} FETCH ({}", id, rest).parse::<Message>();
+            let _ = rest.parse::<Message>();
+        }
+
+        #[test]
+        fn test_parse_headers_never_panic(s in SYNTAX) {
+            let _ = s.parse::<Flags>();
+            let _ = s.parse::<InternalDate>();
+            let _ = s.parse::<Unsubscribe>();
+            let _ = s.parse::<AuthResults>();
+            let _ = s.parse::<GmailInfo>();
+        }
+    }
+}
@@ -0,0 +1,53 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+/// An in-memory server for tests that plays back a recorded dialog. Each
+/// command the client writes must match the next expected one, its canned
+/// response is then made available to read.
+pub(crate) struct MockStream {
+    script: VecDeque<(String, String)>,
+    output: VecDeque<u8>,
+    written: Vec<u8>,
+}
+
+impl MockStream {
+    /// `script` pairs each expected command, without the CRLF, with the
+    /// server's response.
+    pub fn new(greeting: &str, script: &[(&str, &str)]) -> Self {
+        return Self {
+            script: script
+                .iter()
+                .map(|(cmd, response)| (cmd.to_string(), response.to_string()))
+                .collect(),
+            output: greeting.bytes().collect(),
+            written: vec![],
+        };
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        return self.output.read(buf);
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.written.extend_from_slice(buf);
+        while let Some(end) = self.written.windows(2).position(|x| x == b"\r\n") {
+            let line = String::from_utf8_lossy(&self.written[..end]).into_owned();
+            self.written.drain(..end + 2);
+            let (expected, response) = self
+                .script
+                .pop_front()
+                .unwrap_or_else(|| panic!("Unexpected command {:?}", line));
+            assert_eq!(line, expected);
+            self.output.extend(response.bytes());
+        }
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        return Ok(());
+    }
+}
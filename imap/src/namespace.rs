@@ -0,0 +1,151 @@
+use anyhow::{bail, Context, Result};
+use std::str::FromStr;
+
+/// One namespace, mailboxes in it start with `prefix` and use `delimiter`
+/// between levels of the hierarchy.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Namespace {
+    pub prefix: Box<str>,
+    pub delimiter: Option<char>,
+}
+
+/// The `NAMESPACE` response (RFC 2342).
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Namespaces {
+    pub personal: Vec<Namespace>,
+    pub other: Vec<Namespace>,
+    pub shared: Vec<Namespace>,
+}
+
+impl Namespaces {
+    /// The prefix for new personal mailboxes, e.g. `INBOX.` on Courier.
+    pub fn personal_prefix(&self) -> &str {
+        return self.personal.first().map_or("", |x| &x.prefix);
+    }
+}
+
+impl FromStr for Namespaces {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let line = s
+            .lines()
+            .find_map(|x| x.strip_prefix("* NAMESPACE "))
+            .context("No NAMESPACE response")?;
+        let mut tokens = tokenize(line)?.into_iter().peekable();
+        let mut lists = vec![];
+        for _ in 0..3 {
+            lists.push(parse_list(&mut tokens)?);
+        }
+        let shared = lists.pop().unwrap_or_default();
+        let other = lists.pop().unwrap_or_default();
+        let personal = lists.pop().unwrap_or_default();
+        return Ok(Self {
+            personal,
+            other,
+            shared,
+        });
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+enum Token {
+    Open,
+    Close,
+    Nil,
+    String(String),
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = vec![];
+    let mut chars = s.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '"' => {
+                let mut value = String::new();
+                loop {
+                    match chars.next() {
+                        Some('\\') => value.extend(chars.next()),
+                        Some('"') => break,
+                        Some(x) => value.push(x),
+                        None => bail!("Unterminated string in NAMESPACE response"),
+                    }
+                }
+                tokens.push(Token::String(value));
+            }
+            x if x.is_whitespace() => {}
+            _ => {
+                let mut atom = String::from(c);
+                while let Some(x) = chars.next_if(|x| !matches!(x, '(' | ')' | ' ')) {
+                    atom.push(x);
+                }
+                if !atom.eq_ignore_ascii_case("NIL") {
+                    bail!("Unexpected {} in NAMESPACE response", atom);
+                }
+                tokens.push(Token::Nil);
+            }
+        }
+    }
+    return Ok(tokens);
+}
+
+/// `NIL` or `(("prefix" "delimiter" extensions...)...)`
+fn parse_list(tokens: &mut impl Iterator<Item = Token>) -> Result<Vec<Namespace>> {
+    match tokens.next() {
+        Some(Token::Nil) => return Ok(vec![]),
+        Some(Token::Open) => {}
+        x => bail!("Expected namespace list, found {:?}", x),
+    }
+    let mut result = vec![];
+    loop {
+        match tokens.next() {
+            Some(Token::Close) => return Ok(result),
+            Some(Token::Open) => {}
+            x => bail!("Expected namespace, found {:?}", x),
+        }
+        let prefix = match tokens.next() {
+            Some(Token::String(x)) => x,
+            x => bail!("Expected namespace prefix, found {:?}", x),
+        };
+        let delimiter = match tokens.next() {
+            Some(Token::String(x)) => x.chars().next(),
+            Some(Token::Nil) => None,
+            x => bail!("Expected namespace delimiter, found {:?}", x),
+        };
+        // skip any extension data
+        let mut depth = 1;
+        while depth > 0 {
+            match tokens.next() {
+                Some(Token::Open) => depth += 1,
+                Some(Token::Close) => depth -= 1,
+                Some(_) => {}
+                None => bail!("Unterminated namespace"),
+            }
+        }
+        result.push(Namespace {
+            prefix: prefix.into(),
+            delimiter,
+        });
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_namespace() {
+        let raw = "* NAMESPACE ((\"INBOX.\" \".\")) NIL ((\"#shared.\" \".\" \"X-PARAM\" (\"a\")) (\"#public/\" \"/\"))\r\n? OK done\r\n";
+        let namespaces: Namespaces = raw.parse().unwrap();
+        assert_eq!(namespaces.personal_prefix(), "INBOX.");
+        assert_eq!(namespaces.personal[0].delimiter, Some('.'));
+        assert!(namespaces.other.is_empty());
+        assert_eq!(namespaces.shared.len(), 2);
+        assert_eq!(&*namespaces.shared[1].prefix, "#public/");
+
+        let namespaces: Namespaces = "* NAMESPACE ((\"\" \"/\")) NIL NIL".parse().unwrap();
+        assert_eq!(namespaces.personal_prefix(), "");
+    }
+}
@@ -0,0 +1,66 @@
+use anyhow::{bail, Context, Result};
+use std::str::FromStr;
+
+/// Usage of one resource under a quota root (RFC 9208), `STORAGE` is
+/// counted in units of 1024 octets.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QuotaResource {
+    pub name: Box<str>,
+    pub usage: u64,
+    pub limit: u64,
+}
+
+/// The resources of a `* QUOTA "root" (STORAGE 512 10240 ...)` line.
+pub(crate) struct QuotaLine(pub Vec<QuotaResource>);
+
+impl FromStr for QuotaLine {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        if !s.starts_with("* QUOTA ") {
+            bail!("Not a QUOTA response {}", s);
+        }
+        let list = s
+            .rsplit_once('(')
+            .and_then(|(_, rest)| rest.split_once(')'))
+            .context("QUOTA response has no resource list")?
+            .0;
+        let words = list.split_whitespace().collect::<Vec<_>>();
+        if words.len() % 3 != 0 {
+            bail!("Invalid QUOTA resource list {}", list);
+        }
+        let resources = words
+            .chunks(3)
+            .map(|x| {
+                return Ok(QuotaResource {
+                    name: x[0].into(),
+                    usage: x[1].parse().context("Invalid quota usage")?,
+                    limit: x[2].parse().context("Invalid quota limit")?,
+                });
+            })
+            .collect::<Result<_>>()?;
+        return Ok(Self(resources));
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_parse_quota() {
+        let QuotaLine(resources) = "* QUOTA \"\" (STORAGE 512 10240 MESSAGE 12 1000)"
+            .parse()
+            .unwrap();
+        assert_eq!(
+            resources[0],
+            QuotaResource {
+                name: "STORAGE".into(),
+                usage: 512,
+                limit: 10240,
+            }
+        );
+        assert_eq!(resources[1].limit, 1000);
+        assert!("* QUOTA \"\" (STORAGE 512)".parse::<QuotaLine>().is_err());
+    }
+}
@@ -0,0 +1,161 @@
+use crate::mbox::{civil_from_days, days_from_civil, MONTHS};
+use crate::quoted;
+use anyhow::{bail, Context, Result};
+use std::fmt::Display;
+use std::str::FromStr;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// Criteria for the IMAP `SEARCH` family of commands.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum SearchQuery {
+    All,
+    Seen,
+    Unseen,
+    Flagged,
+    Answered,
+    From(Box<str>),
+    To(Box<str>),
+    Subject(Box<str>),
+    Body(Box<str>),
+    Text(Box<str>),
+    /// A header field containing the value, e.g. `Message-ID`.
+    Header(Box<str>, Box<str>),
+    /// Received on or after the date.
+    Since(SearchDate),
+    /// Received before the date.
+    Before(SearchDate),
+    On(SearchDate),
+    Not(Box<SearchQuery>),
+    Or(Box<SearchQuery>, Box<SearchQuery>),
+    And(Box<[SearchQuery]>),
+}
+
+impl Display for SearchQuery {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        use SearchQuery::*;
+        return match self {
+            All => write!(f, "ALL"),
+            Seen => write!(f, "SEEN"),
+            Unseen => write!(f, "UNSEEN"),
+            Flagged => write!(f, "FLAGGED"),
+            Answered => write!(f, "ANSWERED"),
+            From(x) => write!(f, "FROM {}", quoted(x)),
+            To(x) => write!(f, "TO {}", quoted(x)),
+            Subject(x) => write!(f, "SUBJECT {}", quoted(x)),
+            Body(x) => write!(f, "BODY {}", quoted(x)),
+            Text(x) => write!(f, "TEXT {}", quoted(x)),
+            Header(name, value) => write!(f, "HEADER {} {}", quoted(name), quoted(value)),
+            Since(x) => write!(f, "SINCE {}", x),
+            Before(x) => write!(f, "BEFORE {}", x),
+            On(x) => write!(f, "ON {}", x),
+            Not(x) => write!(f, "NOT {}", x),
+            Or(lhs, rhs) => write!(f, "OR {} {}", lhs, rhs),
+            And(list) if list.is_empty() => write!(f, "ALL"),
+            And(list) => {
+                let list = list
+                    .iter()
+                    .map(|x| format!("{}", x))
+                    .collect::<Vec<_>>()
+                    .join(" ");
+                write!(f, "({})", list)
+            }
+        };
+    }
+}
+
+/// A calendar date for `SEARCH`, parsed from `YYYY-MM-DD` and shown the way
+/// IMAP expects, e.g. `1-Jan-2024`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct SearchDate {
+    pub year: i64,
+    pub month: u8,
+    pub day: u8,
+}
+
+impl SearchDate {
+    /// Today in UTC.
+    pub fn today() -> Self {
+        let seconds = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |x| x.as_secs());
+        return Self::from_days(seconds as i64 / 86400);
+    }
+
+    pub fn days_before(&self, days: i64) -> Self {
+        let since_epoch = days_from_civil(self.year, self.month.into(), self.day.into());
+        return Self::from_days(since_epoch - days);
+    }
+
+    fn from_days(days: i64) -> Self {
+        let (year, month, day) = civil_from_days(days);
+        return Self {
+            year,
+            month: month as u8,
+            day: day as u8,
+        };
+    }
+}
+
+impl FromStr for SearchDate {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parts = s.trim().splitn(3, '-');
+        let mut next = |name| {
+            return parts
+                .next()
+                .and_then(|x| x.parse::<i64>().ok())
+                .context(format!("Invalid {} in date {}", name, s));
+        };
+        let (year, month, day) = (next("year")?, next("month")?, next("day")?);
+        if !(1..=12).contains(&month) || !(1..=31).contains(&day) {
+            bail!("Invalid date {}, expected YYYY-MM-DD", s);
+        }
+        return Ok(Self {
+            year,
+            month: month as u8,
+            day: day as u8,
+        });
+    }
+}
+
+impl Display for SearchDate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let month = MONTHS[usize::from(self.month.clamp(1, 12)) - 1];
+        return write!(f, "{}-{}-{}", self.day, month, self.year);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_search_query_display() {
+        let query = SearchQuery::And(Box::new([
+            SearchQuery::Unseen,
+            SearchQuery::Or(
+                Box::new(SearchQuery::From("bob".into())),
+                Box::new(SearchQuery::Subject("say \"hi\"".into())),
+            ),
+        ]));
+        assert_eq!(
+            format!("{}", query),
+            r#"(UNSEEN OR FROM "bob" SUBJECT "say \"hi\"")"#
+        );
+        assert_eq!(
+            SearchQuery::Header("Message-ID".into(), "a@b".into()).to_string(),
+            r#"HEADER "Message-ID" "a@b""#
+        );
+    }
+
+    #[test]
+    fn test_search_date() {
+        let date: SearchDate = "2024-03-01".parse().unwrap();
+        assert_eq!(format!("{}", SearchQuery::Since(date)), "SINCE 1-Mar-2024");
+        assert_eq!(date.days_before(1).to_string(), "29-Feb-2024");
+        assert_eq!(date.days_before(61).to_string(), "31-Dec-2023");
+        assert!("2024-13-01".parse::<SearchDate>().is_err());
+        assert!("yesterday".parse::<SearchDate>().is_err());
+    }
+}
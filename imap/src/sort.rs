@@ -0,0 +1,65 @@
+use std::fmt::Display;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SortField {
+    Date,
+    From,
+    Subject,
+    Size,
+}
+
+/// A sort criterion for the `SORT` extension (RFC 5256).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct SortKey {
+    pub field: SortField,
+    pub descending: bool,
+}
+
+impl SortKey {
+    pub fn ascending(field: SortField) -> Self {
+        return Self {
+            field,
+            descending: false,
+        };
+    }
+
+    pub fn descending(field: SortField) -> Self {
+        return Self {
+            field,
+            descending: true,
+        };
+    }
+}
+
+impl Display for SortKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.descending {
+            write!(f, "REVERSE ")?;
+        }
+        return match self.field {
+            SortField::Date => write!(f, "DATE"),
+            SortField::From => write!(f, "FROM"),
+            SortField::Subject => write!(f, "SUBJECT"),
+            SortField::Size => write!(f, "SIZE"),
+        };
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_sort_key_display() {
+        let keys = [
+            SortKey::descending(SortField::Date),
+            SortKey::ascending(SortField::Subject),
+        ];
+        let keys = keys
+            .iter()
+            .map(|x| format!("{}", x))
+            .collect::<Vec<_>>()
+            .join(" ");
+        assert_eq!(keys, "REVERSE DATE SUBJECT");
+    }
+}
@@ -0,0 +1,430 @@
+use crate::message::Message;
+use anyhow::{bail, Context, Result};
+use std::{collections::HashMap, fmt::Display, str::FromStr};
+
+/// Threading algorithms for the server side `THREAD` extension (RFC 5256).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ThreadAlg {
+    References,
+    OrderedSubject,
+}
+
+impl Display for ThreadAlg {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return match self {
+            ThreadAlg::References => write!(f, "REFERENCES"),
+            ThreadAlg::OrderedSubject => write!(f, "ORDEREDSUBJECT"),
+        };
+    }
+}
+
+/// A node of a thread tree made of message ids. `id` is `None` for a missing
+/// parent that only groups its children.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ThreadNode {
+    pub id: Option<usize>,
+    pub children: Vec<ThreadNode>,
+}
+
+impl From<&Thread> for ThreadNode {
+    fn from(value: &Thread) -> Self {
+        return Self {
+            id: value.message.as_ref().map(|message| message.id),
+            children: value.children.iter().map(ThreadNode::from).collect(),
+        };
+    }
+}
+
+/// The threads of a `* THREAD (2)(3 6 (4 23)(44 7 96))` response.
+pub struct ThreadList(pub Vec<ThreadNode>);
+
+impl FromStr for ThreadList {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<Self> {
+        let line = s
+            .lines()
+            .find(|line| line.starts_with("* THREAD"))
+            .context("No THREAD response found")?;
+        let tokens = tokenize_thread(&line["* THREAD".len()..]);
+        let mut tokens = tokens.iter().peekable();
+        let mut threads = vec![];
+        while let Some(token) = tokens.next() {
+            if *token != ThreadToken::Open {
+                bail!("Expected '(' in THREAD response");
+            }
+            threads.push(parse_thread(&mut tokens)?);
+        }
+        return Ok(Self(threads));
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum ThreadToken {
+    Open,
+    Close,
+    Id(usize),
+}
+
+fn tokenize_thread(s: &str) -> Vec<ThreadToken> {
+    let mut tokens = vec![];
+    let mut number = String::new();
+    for c in s.chars() {
+        if c.is_ascii_digit() {
+            number.push(c);
+            continue;
+        }
+        if let Ok(id) = number.parse() {
+            tokens.push(ThreadToken::Id(id));
+        }
+        number.clear();
+        match c {
+            '(' => tokens.push(ThreadToken::Open),
+            ')' => tokens.push(ThreadToken::Close),
+            _ => {}
+        }
+    }
+    if let Ok(id) = number.parse() {
+        tokens.push(ThreadToken::Id(id));
+    }
+    return tokens;
+}
+
+/// Parses the inside of a `(...)` thread, the opening paren has already been
+/// read. A run of ids is a chain of replies, nested lists are branches off the
+/// last id in the chain.
+fn parse_thread<'a>(
+    tokens: &mut std::iter::Peekable<impl Iterator<Item = &'a ThreadToken>>,
+) -> Result<ThreadNode> {
+    let mut chain = vec![];
+    let mut branches = vec![];
+    loop {
+        match tokens.next().context("Unterminated THREAD list")? {
+            ThreadToken::Id(id) if branches.is_empty() => chain.push(*id),
+            ThreadToken::Id(_) => bail!("Unexpected id after nested thread"),
+            ThreadToken::Open => branches.push(parse_thread(tokens)?),
+            ThreadToken::Close => break,
+        }
+    }
+
+    let mut node = ThreadNode {
+        id: chain.pop(),
+        children: branches,
+    };
+    // a single branch without an id of its own is just a continuation
+    if node.id.is_none() && node.children.len() == 1 {
+        node = node.children.pop().unwrap();
+    }
+    while let Some(id) = chain.pop() {
+        node = ThreadNode {
+            id: Some(id),
+            children: vec![node],
+        };
+    }
+    return Ok(node);
+}
+
+/// A node in a conversation tree. `message` is `None` for messages that are
+/// referenced by a reply but weren't part of the fetched set.
+#[derive(Debug, Clone)]
+pub struct Thread {
+    pub message: Option<Message>,
+    pub children: Vec<Thread>,
+}
+
+impl Thread {
+    /// The first real message in the thread, depth first.
+    pub fn root_message(&self) -> Option<&Message> {
+        if let Some(message) = &self.message {
+            return Some(message);
+        }
+        return self.children.iter().find_map(Thread::root_message);
+    }
+
+    pub fn len(&self) -> usize {
+        let own = if self.message.is_some() { 1 } else { 0 };
+        return own + self.children.iter().map(Thread::len).sum::<usize>();
+    }
+
+    pub fn is_empty(&self) -> bool {
+        return self.len() == 0;
+    }
+}
+
+#[derive(Default)]
+struct Container {
+    message: Option<usize>,
+    parent: Option<usize>,
+    children: Vec<usize>,
+}
+
+/// Groups messages into conversations using the jwz threading algorithm
+/// (https://www.jwz.org/doc/threading.html) over the Message-ID, In-Reply-To
+/// and References headers, then merges roots that share a base subject.
+pub fn thread(messages: &[Message]) -> Vec<Thread> {
+    let mut containers: Vec<Container> = vec![];
+    let mut id_table: HashMap<&str, usize> = HashMap::new();
+
+    for (i, message) in messages.iter().enumerate() {
+        let current = match message.message_id.as_deref() {
+            Some(id) => {
+                let index = get_container(&mut id_table, &mut containers, id);
+                if containers[index].message.is_some() {
+                    // duplicate Message-ID, thread it on its own
+                    containers.push(Container::default());
+                    containers.len() - 1
+                } else {
+                    index
+                }
+            }
+            None => {
+                containers.push(Container::default());
+                containers.len() - 1
+            }
+        };
+        containers[current].message = Some(i);
+
+        let mut references: Vec<&str> = message.references.iter().map(|x| &**x).collect();
+        if let Some(in_reply_to) = message.in_reply_to.as_deref() {
+            if references.last() != Some(&in_reply_to) {
+                references.push(in_reply_to);
+            }
+        }
+
+        let mut prev: Option<usize> = None;
+        for reference in references {
+            let index = get_container(&mut id_table, &mut containers, reference);
+            if let Some(prev) = prev {
+                if containers[index].parent.is_none() && !is_reachable(&containers, index, prev) {
+                    link(&mut containers, prev, index);
+                }
+            }
+            prev = Some(index);
+        }
+
+        // the message's own parent is always its last reference
+        if let Some(parent) = containers[current].parent {
+            containers[parent].children.retain(|&x| x != current);
+            containers[current].parent = None;
+        }
+        if let Some(prev) = prev {
+            if prev != current && !is_reachable(&containers, current, prev) {
+                link(&mut containers, prev, current);
+            }
+        }
+    }
+
+    let roots = (0..containers.len()).filter(|&i| containers[i].parent.is_none());
+    let mut threads: Vec<Thread> = roots
+        .flat_map(|root| build(&containers, messages, root))
+        .collect();
+
+    threads = group_by_subject(threads);
+    sort_threads(&mut threads);
+    return threads;
+}
+
+fn get_container<'a>(
+    id_table: &mut HashMap<&'a str, usize>,
+    containers: &mut Vec<Container>,
+    id: &'a str,
+) -> usize {
+    return *id_table.entry(id).or_insert_with(|| {
+        containers.push(Container::default());
+        containers.len() - 1
+    });
+}
+
+fn link(containers: &mut [Container], parent: usize, child: usize) {
+    containers[child].parent = Some(parent);
+    containers[parent].children.push(child);
+}
+
+/// True if `target` is `from` or one of its descendants.
+fn is_reachable(containers: &[Container], from: usize, target: usize) -> bool {
+    if from == target {
+        return true;
+    }
+    return containers[from]
+        .children
+        .iter()
+        .any(|&child| is_reachable(containers, child, target));
+}
+
+/// Converts a container into threads, pruning empty containers. An empty
+/// container is replaced by its children, except at the root where it's kept
+/// if it groups several replies together.
+fn build(containers: &[Container], messages: &[Message], index: usize) -> Vec<Thread> {
+    let container = &containers[index];
+    let children: Vec<Thread> = container
+        .children
+        .iter()
+        .flat_map(|&child| build(containers, messages, child))
+        .collect();
+
+    return match container.message {
+        Some(message) => vec![Thread {
+            message: Some(messages[message].clone()),
+            children,
+        }],
+        None if container.parent.is_none() && children.len() > 1 => vec![Thread {
+            message: None,
+            children,
+        }],
+        None => children,
+    };
+}
+
+/// Strips any `Re:`/`Fwd:` prefixes, returning the lowercased subject and
+/// whether it had a prefix.
+fn base_subject(subject: &str) -> (String, bool) {
+    let mut subject = subject.trim();
+    let mut is_reply = false;
+    loop {
+        let lower = subject.to_ascii_lowercase();
+        let prefix = ["re:", "fwd:", "fw:"]
+            .iter()
+            .find(|prefix| lower.starts_with(**prefix));
+        match prefix {
+            Some(prefix) => {
+                subject = subject[prefix.len()..].trim_start();
+                is_reply = true;
+            }
+            None => return (subject.to_ascii_lowercase(), is_reply),
+        }
+    }
+}
+
+fn group_by_subject(threads: Vec<Thread>) -> Vec<Thread> {
+    let mut result: Vec<Thread> = vec![];
+    let mut subjects: HashMap<String, usize> = HashMap::new();
+    for thread in threads {
+        let (subject, is_reply) = thread
+            .root_message()
+            .map(|message| base_subject(&message.subject))
+            .unwrap_or_default();
+        if subject.is_empty() {
+            result.push(thread);
+            continue;
+        }
+        match subjects.get(&subject) {
+            Some(&index) => {
+                let existing = &mut result[index];
+                let existing_is_reply = existing
+                    .message
+                    .as_ref()
+                    .map(|message| base_subject(&message.subject).1);
+                if existing.message.is_none() || (is_reply && existing_is_reply == Some(false)) {
+                    existing.children.push(thread);
+                } else {
+                    let existing = std::mem::replace(
+                        existing,
+                        Thread {
+                            message: None,
+                            children: vec![],
+                        },
+                    );
+                    result[index].children = vec![existing, thread];
+                }
+            }
+            None => {
+                subjects.insert(subject, result.len());
+                result.push(thread);
+            }
+        }
+    }
+    return result;
+}
+
+fn sort_threads(threads: &mut [Thread]) {
+    for thread in threads.iter_mut() {
+        sort_threads(&mut thread.children);
+    }
+    threads.sort_by_key(|thread| thread.root_message().map(|message| message.id));
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    fn message(id: usize, subject: &str, message_id: &str, references: &[&str]) -> Message {
+        let references = references
+            .iter()
+            .map(|x| format!("<{}>", x))
+            .collect::<Vec<_>>()
+            .join(" ");
+        let raw = format!(
+            "* {} FETCH (FLAGS () BODY[HEADER.FIELDS (SUBJECT FROM)] {{0}}\r\nSubject: {}\r\nFrom: a@b.com\r\nMessage-ID: <{}>\r\nReferences: {}\r\n",
+            id, subject, message_id, references
+        );
+        return raw.parse().unwrap();
+    }
+
+    #[test]
+    fn test_thread() {
+        let messages = [
+            message(1, "Lunch", "a@x", &[]),
+            message(2, "Other", "b@x", &[]),
+            message(3, "Re: Lunch", "c@x", &["a@x"]),
+            message(4, "Re: Lunch", "d@x", &["a@x", "c@x"]),
+            // parent was never fetched
+            message(5, "Re: Missing", "e@x", &["gone@x"]),
+        ];
+        let threads = thread(&messages);
+        assert_eq!(threads.len(), 3);
+        assert_eq!(threads[0].root_message().unwrap().id, 1);
+        assert_eq!(threads[0].len(), 3);
+        assert_eq!(
+            threads[0].children[0].children[0]
+                .message
+                .as_ref()
+                .unwrap()
+                .id,
+            4
+        );
+        assert_eq!(threads[1].len(), 1);
+        assert_eq!(threads[2].message.as_ref().unwrap().id, 5);
+    }
+
+    #[test]
+    fn test_parse_thread_response() {
+        let leaf = |id| ThreadNode {
+            id: Some(id),
+            children: vec![],
+        };
+        let ThreadList(threads) = "* THREAD (2)(3 6 (4 23)(44 7 96))((5)(8))\r\n"
+            .parse()
+            .unwrap();
+        assert_eq!(threads.len(), 3);
+        assert_eq!(threads[0], leaf(2));
+        assert_eq!(
+            threads[1],
+            ThreadNode {
+                id: Some(3),
+                children: vec![ThreadNode {
+                    id: Some(6),
+                    children: vec![
+                        ThreadNode {
+                            id: Some(4),
+                            children: vec![leaf(23)],
+                        },
+                        ThreadNode {
+                            id: Some(44),
+                            children: vec![ThreadNode {
+                                id: Some(7),
+                                children: vec![leaf(96)],
+                            }],
+                        },
+                    ],
+                }],
+            }
+        );
+        assert_eq!(
+            threads[2],
+            ThreadNode {
+                id: None,
+                children: vec![leaf(5), leaf(8)],
+            }
+        );
+    }
+}
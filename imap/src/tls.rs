@@ -0,0 +1,153 @@
+use anyhow::{Context, Result};
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+    path::PathBuf,
+};
+
+#[cfg(not(any(feature = "openssl", feature = "rustls")))]
+compile_error!("either the `openssl` or the `rustls` feature must be enabled");
+
+/// Any byte stream the client can talk IMAP over, lets the rest of the crate
+/// stay agnostic of the TLS backend.
+pub trait Stream: Read + Write + Send {}
+impl<T: Read + Write + Send> Stream for T {}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TlsVersion {
+    Tls1_2,
+    Tls1_3,
+}
+
+#[derive(Debug, Clone, Default)]
+pub(crate) struct TlsOptions {
+    pub ca_file: Option<PathBuf>,
+    pub accept_invalid_certs: bool,
+    pub min_tls_version: Option<TlsVersion>,
+}
+
+#[cfg(not(feature = "rustls"))]
+pub(crate) fn wrap(
+    options: &TlsOptions,
+    domain: &str,
+    stream: TcpStream,
+) -> Result<Box<dyn Stream>> {
+    use openssl::ssl::{SslConnector, SslMethod, SslVerifyMode, SslVersion};
+
+    let mut connector = SslConnector::builder(SslMethod::tls())?;
+    if let Some(ca_file) = &options.ca_file {
+        connector
+            .set_ca_file(ca_file)
+            .context(format!("Failed to load CA file {}", ca_file.display()))?;
+    }
+    if options.accept_invalid_certs {
+        connector.set_verify(SslVerifyMode::NONE);
+    }
+    if let Some(version) = options.min_tls_version {
+        let version = match version {
+            TlsVersion::Tls1_2 => SslVersion::TLS1_2,
+            TlsVersion::Tls1_3 => SslVersion::TLS1_3,
+        };
+        connector.set_min_proto_version(Some(version))?;
+    }
+    let mut config = connector.build().configure()?;
+    if options.accept_invalid_certs {
+        config = config.verify_hostname(false);
+    }
+    return Ok(Box::new(config.connect(domain, stream)?));
+}
+
+#[cfg(feature = "rustls")]
+pub(crate) fn wrap(
+    options: &TlsOptions,
+    domain: &str,
+    stream: TcpStream,
+) -> Result<Box<dyn Stream>> {
+    use rustls::{
+        pki_types::{pem::PemObject, CertificateDer, ServerName},
+        ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+    };
+    use std::sync::Arc;
+
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    if let Some(ca_file) = &options.ca_file {
+        for cert in CertificateDer::pem_file_iter(ca_file)
+            .context(format!("Failed to load CA file {}", ca_file.display()))?
+        {
+            roots.add(cert?)?;
+        }
+    }
+
+    let versions: &[&rustls::SupportedProtocolVersion] = match options.min_tls_version {
+        Some(TlsVersion::Tls1_3) => &[&rustls::version::TLS13],
+        _ => rustls::DEFAULT_VERSIONS,
+    };
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config =
+        ClientConfig::builder_with_provider(provider.clone()).with_protocol_versions(versions)?;
+    let config = if options.accept_invalid_certs {
+        config
+            .dangerous()
+            .with_custom_certificate_verifier(Arc::new(danger::NoVerification(provider)))
+            .with_no_client_auth()
+    } else {
+        config.with_root_certificates(roots).with_no_client_auth()
+    };
+
+    let server_name = ServerName::try_from(domain.to_owned())?;
+    let connection = ClientConnection::new(Arc::new(config), server_name)?;
+    return Ok(Box::new(StreamOwned::new(connection, stream)));
+}
+
+#[cfg(feature = "rustls")]
+mod danger {
+    use rustls::{
+        client::danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        crypto::{verify_tls12_signature, verify_tls13_signature, CryptoProvider},
+        pki_types::{CertificateDer, ServerName, UnixTime},
+        DigitallySignedStruct, SignatureScheme,
+    };
+    use std::sync::Arc;
+
+    /// Accepts any certificate, still checks the handshake signatures.
+    #[derive(Debug)]
+    pub struct NoVerification(pub Arc<CryptoProvider>);
+
+    impl ServerCertVerifier for NoVerification {
+        fn verify_server_cert(
+            &self,
+            _end_entity: &CertificateDer<'_>,
+            _intermediates: &[CertificateDer<'_>],
+            _server_name: &ServerName<'_>,
+            _ocsp_response: &[u8],
+            _now: UnixTime,
+        ) -> Result<ServerCertVerified, rustls::Error> {
+            return Ok(ServerCertVerified::assertion());
+        }
+
+        fn verify_tls12_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            let algorithms = &self.0.signature_verification_algorithms;
+            return verify_tls12_signature(message, cert, dss, algorithms);
+        }
+
+        fn verify_tls13_signature(
+            &self,
+            message: &[u8],
+            cert: &CertificateDer<'_>,
+            dss: &DigitallySignedStruct,
+        ) -> Result<HandshakeSignatureValid, rustls::Error> {
+            let algorithms = &self.0.signature_verification_algorithms;
+            return verify_tls13_signature(message, cert, dss, algorithms);
+        }
+
+        fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+            return self.0.signature_verification_algorithms.supported_schemes();
+        }
+    }
+}
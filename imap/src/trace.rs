@@ -0,0 +1,52 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Gets every line sent to the server prefixed with `C: ` and every line
+/// received prefixed with `S: `. Passwords are redacted by the caller. Set
+/// with `ImapBuilder::trace`, useful for bug reports about parse failures.
+#[derive(Clone)]
+pub(crate) struct Trace(Arc<dyn Fn(&str) + Send + Sync>);
+
+impl Trace {
+    pub fn new(callback: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        return Self(Arc::new(callback));
+    }
+
+    pub fn sent(&self, line: &str) {
+        (self.0)(&format!("C: {}", line));
+    }
+
+    pub fn received(&self, bytes: &[u8]) {
+        let text = String::from_utf8_lossy(bytes);
+        (self.0)(&format!("S: {}", text.trim_end_matches(['\r', '\n'])));
+    }
+}
+
+impl Debug for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "Trace");
+    }
+}
+
+/// `text` with every occurrence of `secret` masked, for errors that might
+/// echo a command back.
+pub(crate) fn redact_secret(text: &str, secret: &str) -> String {
+    if secret.is_empty() {
+        return text.to_owned();
+    }
+    return text.replace(secret, "<redacted>");
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_redact_secret() {
+        assert_eq!(
+            redact_secret("BAD LOGIN \"a\" \"pw\"", "pw"),
+            "BAD LOGIN \"a\" \"<redacted>\""
+        );
+        assert_eq!(redact_secret("no secret", ""), "no secret");
+    }
+}
@@ -0,0 +1,201 @@
+use anyhow::{bail, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use hmac::{Hmac, Mac};
+use md5::Md5;
+use rand::{distributions::Alphanumeric, Rng};
+use sha1::{Digest, Sha1};
+
+/// SASL mechanisms we can authenticate with, strongest first.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mechanism {
+    ScramSha1,
+    CramMd5,
+    Login,
+    Plain,
+}
+
+impl Mechanism {
+    const PREFERRED: [Mechanism; 4] = [
+        Mechanism::ScramSha1,
+        Mechanism::CramMd5,
+        Mechanism::Login,
+        Mechanism::Plain,
+    ];
+
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            Mechanism::ScramSha1 => "SCRAM-SHA-1",
+            Mechanism::CramMd5 => "CRAM-MD5",
+            Mechanism::Login => "LOGIN",
+            Mechanism::Plain => "PLAIN",
+        };
+    }
+
+    /// The strongest mechanism in the `AUTH` line of an EHLO response, LOGIN
+    /// if the server doesn't list any.
+    pub fn choose(extensions: &[Box<str>]) -> Self {
+        let offered = extensions
+            .iter()
+            .filter_map(|x| x.strip_prefix("AUTH "))
+            .flat_map(|x| x.split_whitespace())
+            .collect::<Vec<_>>();
+        return Self::PREFERRED
+            .into_iter()
+            .find(|x| offered.iter().any(|y| y.eq_ignore_ascii_case(x.as_str())))
+            .unwrap_or(Mechanism::Login);
+    }
+}
+
+/// The PLAIN (RFC 4616) credentials, base64 encoded.
+pub fn plain(username: &str, password: &str) -> String {
+    return STANDARD.encode(format!("\0{}\0{}", username, password));
+}
+
+/// The CRAM-MD5 (RFC 2195) answer to a base64 `challenge`, base64 encoded.
+pub fn cram_md5(username: &str, password: &str, challenge: &str) -> Result<String> {
+    let challenge = STANDARD
+        .decode(challenge.trim())
+        .context("Invalid CRAM-MD5 challenge")?;
+    let mut mac = Hmac::<Md5>::new_from_slice(password.as_bytes())?;
+    mac.update(&challenge);
+    let digest = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|x| format!("{:02x}", x))
+        .collect::<String>();
+    return Ok(STANDARD.encode(format!("{} {}", username, digest)));
+}
+
+/// A SCRAM-SHA-1 (RFC 5802) exchange without channel binding.
+pub struct Scram {
+    client_first_bare: String,
+    nonce: String,
+    server_signature: Option<Vec<u8>>,
+}
+
+impl Scram {
+    pub fn new(username: &str) -> Self {
+        let nonce = rand::thread_rng()
+            .sample_iter(&Alphanumeric)
+            .take(24)
+            .map(char::from)
+            .collect();
+        return Self::with_nonce(username, nonce);
+    }
+
+    fn with_nonce(username: &str, nonce: String) -> Self {
+        let username = username.replace('=', "=3D").replace(',', "=2C");
+        return Self {
+            client_first_bare: format!("n={},r={}", username, nonce),
+            nonce,
+            server_signature: None,
+        };
+    }
+
+    pub fn client_first(&self) -> String {
+        return format!("n,,{}", self.client_first_bare);
+    }
+
+    /// Answers the server-first message with the client proof.
+    pub fn client_final(&mut self, password: &str, server_first: &str) -> Result<String> {
+        let attr = |name: &str| {
+            return server_first
+                .split(',')
+                .find_map(|x| x.strip_prefix(name)?.strip_prefix('='))
+                .context(format!("SCRAM server message has no {}", name));
+        };
+        let nonce = attr("r")?;
+        if !nonce.starts_with(&self.nonce) {
+            bail!("SCRAM server nonce doesn't extend ours");
+        }
+        let salt = STANDARD.decode(attr("s")?).context("Invalid SCRAM salt")?;
+        let iterations: u32 = attr("i")?.parse().context("Invalid SCRAM iterations")?;
+
+        let mut salted_password = [0; 20];
+        pbkdf2::pbkdf2_hmac::<Sha1>(password.as_bytes(), &salt, iterations, &mut salted_password);
+        let client_key = hmac_sha1(&salted_password, b"Client Key")?;
+        let stored_key = Sha1::digest(&client_key);
+        let server_key = hmac_sha1(&salted_password, b"Server Key")?;
+
+        let without_proof = format!("c=biws,r={}", nonce);
+        let auth_message = format!(
+            "{},{},{}",
+            self.client_first_bare, server_first, without_proof
+        );
+        let client_signature = hmac_sha1(&stored_key, auth_message.as_bytes())?;
+        let proof = client_key
+            .iter()
+            .zip(client_signature)
+            .map(|(a, b)| a ^ b)
+            .collect::<Vec<_>>();
+        self.server_signature = Some(hmac_sha1(&server_key, auth_message.as_bytes())?);
+
+        return Ok(format!("{},p={}", without_proof, STANDARD.encode(proof)));
+    }
+
+    /// Checks the server proved it knows the password too.
+    pub fn verify(&self, server_final: &str) -> Result<()> {
+        let signature = server_final
+            .strip_prefix("v=")
+            .context(format!("SCRAM authentication failed: {}", server_final))?;
+        let signature = STANDARD
+            .decode(signature)
+            .context("Invalid SCRAM signature")?;
+        if Some(signature) != self.server_signature {
+            bail!("SCRAM server signature doesn't match");
+        }
+        return Ok(());
+    }
+}
+
+fn hmac_sha1(key: &[u8], data: &[u8]) -> Result<Vec<u8>> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(key)?;
+    mac.update(data);
+    return Ok(mac.finalize().into_bytes().to_vec());
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_choose_mechanism() {
+        let extensions: Vec<Box<str>> =
+            vec!["SIZE 1000".into(), "AUTH PLAIN LOGIN CRAM-MD5".into()];
+        assert_eq!(Mechanism::choose(&extensions), Mechanism::CramMd5);
+        assert_eq!(Mechanism::choose(&[]), Mechanism::Login);
+        assert_eq!(Mechanism::choose(&["AUTH PLAIN".into()]), Mechanism::Plain);
+        assert_eq!(plain("tim", "pw"), "AHRpbQBwdw==");
+    }
+
+    #[test]
+    fn test_cram_md5() {
+        // RFC 2195 example
+        let challenge = STANDARD.encode("<1896.697170952@postoffice.reston.mci.net>");
+        let response = cram_md5("tim", "tanstaaftanstaaf", &challenge).unwrap();
+        assert_eq!(
+            STANDARD.decode(response).unwrap(),
+            b"tim b913a602c7eda7a495b4e6e7334d3890"
+        );
+    }
+
+    #[test]
+    fn test_scram_sha1() {
+        // RFC 5802 example
+        let mut scram = Scram::with_nonce("user", "fyko+d2lbbFgONRv9qkxdawL".to_owned());
+        assert_eq!(scram.client_first(), "n,,n=user,r=fyko+d2lbbFgONRv9qkxdawL");
+        let client_final = scram
+            .client_final(
+                "pencil",
+                "r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,s=QSXCR+Q6sek8bf92,i=4096",
+            )
+            .unwrap();
+        assert_eq!(
+            client_final,
+            "c=biws,r=fyko+d2lbbFgONRv9qkxdawL3rfcNHYJY1ZVvWVs7j,p=v0X8v3Bz2T0CJGbJQyF0X+HI4Ts="
+        );
+        scram.verify("v=rmF9pqV8S7suAoZWja4dJRkFsKQ=").unwrap();
+        assert!(scram.verify("v=AAAA").is_err());
+    }
+}
@@ -0,0 +1,266 @@
+use base64::{engine::general_purpose::STANDARD, Engine};
+
+// RFC 5321 limits a line to 1000 octets including the CRLF, leave some
+// headroom before we decide a body needs encoding
+const BODY_LINE_LIMIT: usize = 990;
+// RFC 5322 recommends folding headers at 78 chars
+const HEADER_FOLD_LEN: usize = 78;
+// RFC 2045 limits encoded lines to 76 chars
+const ENCODED_LINE_LEN: usize = 76;
+// bytes per RFC 2047 encoded-word, 45 is 60 chars of base64 plus the 12 of
+// `=?UTF-8?B??=`, under the 75 char limit
+const ENCODED_WORD_BYTES: usize = 45;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TransferEncoding {
+    SevenBit,
+    EightBit,
+    QuotedPrintable,
+    Base64,
+}
+
+impl TransferEncoding {
+    pub fn as_str(&self) -> &'static str {
+        return match self {
+            TransferEncoding::SevenBit => "7bit",
+            TransferEncoding::EightBit => "8bit",
+            TransferEncoding::QuotedPrintable => "quoted-printable",
+            TransferEncoding::Base64 => "base64",
+        };
+    }
+}
+
+/// Formats `name: value` folding at whitespace so no line goes over 78 chars
+/// where possible. Words longer than a line are left intact since headers can
+/// only be folded at whitespace, and no whitespace is dropped so unfolding
+/// gives back `value` exactly.
+pub fn fold_header(name: &str, value: &str) -> String {
+    let is_space = |c: char| c == ' ' || c == '\t';
+    let mut result = format!("{}:", name);
+    let mut line_len = result.len();
+    let value = format!(" {}", value);
+    let mut rest = value.as_str();
+    while !rest.is_empty() {
+        // a run of whitespace and the word after it, folded before the run
+        let word = rest.find(|c| !is_space(c)).unwrap_or(rest.len());
+        let end = rest[word..].find(is_space).map_or(rest.len(), |x| word + x);
+        let (piece, tail) = rest.split_at(end);
+        if line_len > name.len() + 1 && line_len + piece.len() > HEADER_FOLD_LEN {
+            result.push_str("\r\n");
+            line_len = 0;
+        }
+        result.push_str(piece);
+        line_len += piece.len();
+        rest = tail;
+    }
+    return result;
+}
+
+/// An address with an optional display name for a `From:` style header, e.g.
+/// `Jane Doe <jane@x.com>`. Non ASCII names become RFC 2047 encoded-words and
+/// names with special characters are quoted.
+pub fn mailbox(name: Option<&str>, address: &str) -> String {
+    let name = match name.map(str::trim) {
+        Some(name) if !name.is_empty() => name,
+        _ => return address.to_owned(),
+    };
+    if !name.is_ascii() {
+        return format!("{} <{}>", encode_words(name), address);
+    }
+    let is_atom =
+        |c: char| c.is_ascii_alphanumeric() || c == ' ' || "!#$%&'*+-/=?^_`{|}~".contains(c);
+    if name.chars().all(is_atom) {
+        return format!("{} <{}>", name, address);
+    }
+    let escaped = name.replace('\\', "\\\\").replace('"', "\\\"");
+    return format!("\"{}\" <{}>", escaped, address);
+}
+
+/// `text` as base64 encoded-words separated by spaces, each short enough to
+/// stay under the 75 char limit and split only between chars.
+pub fn encode_words(text: &str) -> String {
+    let mut words = vec![];
+    let mut start = 0;
+    for (i, c) in text.char_indices() {
+        if i + c.len_utf8() - start > ENCODED_WORD_BYTES {
+            words.push(&text[start..i]);
+            start = i;
+        }
+    }
+    words.push(&text[start..]);
+    return words
+        .iter()
+        .map(|word| format!("=?UTF-8?B?{}?=", STANDARD.encode(word)))
+        .collect::<Vec<_>>()
+        .join(" ");
+}
+
+/// Picks a transfer encoding for `body` and returns it encoded with CRLF line
+/// endings. Bodies with lines over the SMTP limit, or non ASCII ones when the
+/// server can't take `8bit` (`eight_bit_mime`), are quoted-printable encoded,
+/// or base64 encoded if they are mostly non ASCII.
+pub fn encode_body(body: &str, eight_bit_mime: bool) -> (TransferEncoding, String) {
+    let lines = body
+        .split('\n')
+        .map(|line| line.strip_suffix('\r').unwrap_or(line));
+    let too_long = lines.clone().any(|line| line.len() > BODY_LINE_LIMIT);
+
+    if !too_long && (eight_bit_mime || body.is_ascii()) {
+        let encoding = if body.is_ascii() {
+            TransferEncoding::SevenBit
+        } else {
+            TransferEncoding::EightBit
+        };
+        return (encoding, lines.collect::<Vec<_>>().join("\r\n"));
+    }
+
+    let non_ascii = body.bytes().filter(|b| !b.is_ascii()).count();
+    if non_ascii * 4 > body.len() {
+        let crlf = lines.collect::<Vec<_>>().join("\r\n");
+        return (TransferEncoding::Base64, encode_base64(crlf.as_bytes()));
+    }
+
+    let encoded = lines
+        .map(encode_quoted_printable_line)
+        .collect::<Vec<_>>()
+        .join("\r\n");
+    return (TransferEncoding::QuotedPrintable, encoded);
+}
+
+/// Doubles the leading `.` of every line so the server doesn't read it as the
+/// end of the DATA section.
+pub fn dot_stuff(payload: &str) -> String {
+    return payload
+        .split("\r\n")
+        .map(|line| {
+            if line.starts_with('.') {
+                format!(".{}", line)
+            } else {
+                line.to_owned()
+            }
+        })
+        .collect::<Vec<_>>()
+        .join("\r\n");
+}
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let encoded = STANDARD.encode(bytes);
+    return encoded
+        .as_bytes()
+        .chunks(ENCODED_LINE_LEN)
+        // base64 output is always ascii
+        .map(|chunk| std::str::from_utf8(chunk).unwrap())
+        .collect::<Vec<_>>()
+        .join("\r\n");
+}
+
+fn encode_quoted_printable_line(line: &str) -> String {
+    let bytes = line.as_bytes();
+    let mut result = String::new();
+    let mut line_len = 0;
+    for (i, &byte) in bytes.iter().enumerate() {
+        let is_last = i == bytes.len() - 1;
+        let literal = match byte {
+            b'=' => false,
+            b' ' | b'\t' => !is_last,
+            33..=126 => true,
+            _ => false,
+        };
+        let token = if literal {
+            (byte as char).to_string()
+        } else {
+            format!("={:02X}", byte)
+        };
+        // keep room for the trailing `=` of a soft line break
+        if line_len + token.len() > ENCODED_LINE_LEN - 1 {
+            result.push_str("=\r\n");
+            line_len = 0;
+        }
+        result.push_str(&token);
+        line_len += token.len();
+    }
+    return result;
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_fold_header() {
+        let value = "word ".repeat(40);
+        let folded = fold_header("Subject", value.trim_end());
+        assert!(folded.starts_with("Subject: word"));
+        assert!(folded.split("\r\n").all(|line| line.len() <= 78));
+        assert!(folded
+            .split("\r\n")
+            .skip(1)
+            .all(|line| line.starts_with(' ')));
+        assert_eq!(
+            folded.replace("\r\n", ""),
+            format!("Subject: {}", value.trim_end())
+        );
+        assert_eq!(fold_header("Subject", "a  b\tc"), "Subject: a  b\tc");
+        let spaced = format!("{}    {}", "a".repeat(60), "b".repeat(20));
+        assert_eq!(
+            fold_header("Subject", &spaced),
+            format!("Subject: {}\r\n    {}", "a".repeat(60), "b".repeat(20))
+        );
+    }
+
+    #[test]
+    fn test_mailbox() {
+        assert_eq!(mailbox(None, "jane@x.com"), "jane@x.com");
+        assert_eq!(mailbox(Some(" "), "jane@x.com"), "jane@x.com");
+        assert_eq!(
+            mailbox(Some("Jane Doe"), "jane@x.com"),
+            "Jane Doe <jane@x.com>"
+        );
+        assert_eq!(
+            mailbox(Some("Doe, Jane \"JD\""), "jane@x.com"),
+            r#""Doe, Jane \"JD\"" <jane@x.com>"#
+        );
+        assert_eq!(
+            mailbox(Some("José"), "jose@x.com"),
+            "=?UTF-8?B?Sm9zw6k=?= <jose@x.com>"
+        );
+
+        let long = "é".repeat(40);
+        let encoded = encode_words(&long);
+        assert_eq!(encoded.split(' ').count(), 2);
+        assert!(encoded.split(' ').all(|word| word.len() <= 75));
+    }
+
+    #[test]
+    fn test_encode_body() {
+        let (encoding, body) = encode_body("hello\nworld", false);
+        assert_eq!(encoding, TransferEncoding::SevenBit);
+        assert_eq!(body, "hello\r\nworld");
+
+        let (encoding, _) = encode_body("héllo", true);
+        assert_eq!(encoding, TransferEncoding::EightBit);
+        let (encoding, body) = encode_body("café au lait", false);
+        assert_eq!(encoding, TransferEncoding::QuotedPrintable);
+        assert_eq!(body, "caf=C3=A9 au lait");
+
+        let long_line = format!("{}=end", "a".repeat(1200));
+        let (encoding, body) = encode_body(&long_line, true);
+        assert_eq!(encoding, TransferEncoding::QuotedPrintable);
+        assert!(body.split("\r\n").all(|line| line.len() <= 76));
+        assert!(body.ends_with("=3Dend"));
+
+        let (encoding, body) = encode_body("ééé\nééé", false);
+        assert_eq!(encoding, TransferEncoding::Base64);
+        assert_eq!(
+            STANDARD.decode(body.replace("\r\n", "")).unwrap(),
+            "ééé\r\nééé".as_bytes()
+        );
+    }
+
+    #[test]
+    fn test_dot_stuff() {
+        let (_, body) = encode_body("first\n.\n.hidden\nlast.", false);
+        assert_eq!(dot_stuff(&body), "first\r\n..\r\n..hidden\r\nlast.");
+        assert_eq!(dot_stuff("."), "..");
+    }
+}
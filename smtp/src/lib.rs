@@ -1,70 +1,281 @@
+mod auth;
+mod encoding;
+#[cfg(test)]
+mod mock;
+mod tls;
+mod trace;
+
 use anyhow::{bail, Context, Result};
 use std::{
+    fmt::Display,
     io::{BufRead, BufReader, Write},
     net::TcpStream,
 };
 
-use openssl::{
-    base64,
-    ssl::{SslConnector, SslMethod, SslStream},
-};
+use auth::{Mechanism, Scram};
+use base64::{engine::general_purpose::STANDARD, Engine};
+pub use encoding::mailbox;
+use encoding::{dot_stuff, encode_body, encode_words, fold_header};
+pub use tls::Stream;
+use trace::{redact_secrets, Trace};
+
+/// A recipient the server refused in `RCPT TO`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Rejected {
+    pub address: Box<str>,
+    pub code: u32,
+    pub reason: Box<str>,
+}
+
+impl Display for Rejected {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{} ({} {})", self.address, self.code, self.reason);
+    }
+}
+
+/// The server refused a command. `permanent` for a 5xx reply or a message
+/// it can never take, sending it again won't help. Anything else going
+/// wrong, e.g. the connection, is a plain error.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Refused {
+    pub reason: Box<str>,
+    pub permanent: bool,
+}
+
+impl Display for Refused {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "{}", self.reason);
+    }
+}
+
+impl std::error::Error for Refused {}
+
+const REDACTED: &str = "<redacted>";
 
 pub struct SMTP {
-    stream: SslStream<TcpStream>,
+    stream: BufReader<Box<dyn Stream>>,
     username: Option<Box<str>>,
+    /// Shown with the address in the `From:` header.
+    display_name: Option<Box<str>>,
+    /// The extensions from the EHLO response, e.g. `AUTH LOGIN PLAIN`.
+    extensions: Vec<Box<str>>,
+    trace: Option<Trace>,
 }
 
 impl SMTP {
     pub fn connect(server: &str, port: u32) -> Result<Self> {
-        let connector = SslConnector::builder(SslMethod::tls())?.build();
         let stream = TcpStream::connect(format!("{}:{}", server, port))?;
-        let stream = connector.connect(server, stream)?;
+        let stream = tls::wrap(server, stream)?;
+        return Self::from_stream(stream);
+    }
+
+    /// Talks SMTP over an already connected `stream`, starting with the
+    /// server greeting. Tests can pass an in-memory stream.
+    pub fn from_stream(stream: Box<dyn Stream>) -> Result<Self> {
         let mut obj = Self {
-            stream,
+            stream: BufReader::new(stream),
             username: None,
+            display_name: None,
+            extensions: vec![],
+            trace: None,
         };
         obj.check_response(220)?;
         return Ok(obj);
     }
 
-    fn check_response(&mut self, expected_num: u32) -> Result<()> {
-        let mut reader = BufReader::new(&mut self.stream);
-        let num = loop {
+    /// The name recipients see next to the address in `From:`, the envelope
+    /// keeps the bare address.
+    pub fn set_display_name(&mut self, name: &str) {
+        self.display_name = Some(name.into());
+    }
+
+    /// Calls `callback` with every line sent and received from now on,
+    /// prefixed with `C: ` or `S: `. Credentials are redacted.
+    pub fn set_trace(&mut self, callback: impl Fn(&str) + Send + Sync + 'static) {
+        self.trace = Some(Trace::new(callback));
+    }
+
+    fn send(&mut self, line: &str) -> Result<()> {
+        return self.send_redacted(line, line);
+    }
+
+    /// Sends `line` but traces `shown` instead, for lines with credentials.
+    fn send_redacted(&mut self, line: &str, shown: &str) -> Result<()> {
+        if let Some(trace) = &self.trace {
+            trace.sent(shown);
+        }
+        let stream = self.stream.get_mut();
+        write!(stream, "{}\r\n", line)?;
+        stream.flush()?;
+        return Ok(());
+    }
+
+    /// Reads a reply, multiline replies continue with `-` after the code.
+    /// Returns the code and the text of each line.
+    fn read_response(&mut self) -> Result<(u32, Vec<String>)> {
+        let mut lines = vec![];
+        loop {
             let mut buf = String::new();
-            reader.read_line(&mut buf)?;
-            let num = buf.split(' ').find_map(|x| x.parse::<u32>().ok());
-            if let Some(num) = num {
-                break num;
+            if self.stream.read_line(&mut buf)? == 0 {
+                bail!("connection ended");
+            }
+            if let Some(trace) = &self.trace {
+                trace.received(&buf);
+            }
+            let buf = buf.trim_end();
+            let code = buf
+                .get(..3)
+                .and_then(|x| x.parse::<u32>().ok())
+                .context(format!("Invalid SMTP reply {}", buf))?;
+            lines.push(buf.get(4..).unwrap_or("").to_owned());
+            if buf.as_bytes().get(3) != Some(&b'-') {
+                return Ok((code, lines));
             }
-        };
-        if num == expected_num {
-            Ok(())
-        } else {
-            bail!("{}", num);
         }
     }
 
+    fn check_response(&mut self, expected_num: u32) -> Result<Vec<String>> {
+        let (num, lines) = self.read_response()?;
+        if num != expected_num {
+            return Err(Refused {
+                reason: format!("{} {}", num, lines.join(" ")).into(),
+                permanent: (500..600).contains(&num),
+            }
+            .into());
+        }
+        return Ok(lines);
+    }
+
+    /// Logs in with the strongest mechanism the server offers.
     pub fn login(&mut self, username: Box<str>, password: &str) -> Result<()> {
         let (_, domain) = username
             .split_once("@")
             .context("Invalid domain not found")?;
 
-        write!(self.stream, "EHLO {domain}\r\n")?;
-        self.stream.flush()?;
-        self.check_response(250)?;
-        write!(self.stream, "AUTH LOGIN\r\n")?;
+        self.send(&format!("EHLO {domain}"))?;
+        // the first line is the greeting, the rest are extensions
+        let lines = self.check_response(250)?;
+        self.extensions = lines.into_iter().skip(1).map(|x| x.into()).collect();
+
+        let result = match Mechanism::choose(&self.extensions) {
+            Mechanism::ScramSha1 => self.auth_scram_sha1(&username, password),
+            Mechanism::CramMd5 => self.auth_cram_md5(&username, password),
+            Mechanism::Login => self.auth_login(&username, password),
+            Mechanism::Plain => self.auth_plain(&username, password),
+        };
+        if let Err(e) = result {
+            // a server may echo what it got, keep the credentials out of errors
+            let secrets = [
+                password,
+                &STANDARD.encode(password),
+                &auth::plain(&username, password),
+            ];
+            bail!(
+                "Authentication failed: {}",
+                redact_secrets(&format!("{:#}", e), &secrets)
+            );
+        }
+        self.username = Some(username);
+        return Ok(());
+    }
+
+    fn auth_login(&mut self, username: &str, password: &str) -> Result<()> {
+        self.send("AUTH LOGIN")?;
         self.check_response(334)?;
-        let username_b64 = base64::encode_block(username.as_bytes());
-        let password = base64::encode_block(password.as_bytes());
-        write!(self.stream, "{}\r\n", username_b64)?;
+        self.send(&STANDARD.encode(username.as_bytes()))?;
         self.check_response(334)?;
-        write!(self.stream, "{}\r\n", password)?;
+        self.send_redacted(&STANDARD.encode(password.as_bytes()), REDACTED)?;
         self.check_response(235)?;
-        self.stream.flush()?;
-        self.username = Some(username);
         return Ok(());
     }
 
+    fn auth_plain(&mut self, username: &str, password: &str) -> Result<()> {
+        let credentials = auth::plain(username, password);
+        if self
+            .extensions
+            .iter()
+            .any(|x| x.eq_ignore_ascii_case("SASL-IR"))
+        {
+            let line = format!("AUTH PLAIN {}", credentials);
+            self.send_redacted(&line, &format!("AUTH PLAIN {}", REDACTED))?;
+        } else {
+            self.send("AUTH PLAIN")?;
+            self.check_response(334)?;
+            self.send_redacted(&credentials, REDACTED)?;
+        }
+        self.check_response(235)?;
+        return Ok(());
+    }
+
+    fn auth_cram_md5(&mut self, username: &str, password: &str) -> Result<()> {
+        self.send("AUTH CRAM-MD5")?;
+        let challenge = self.check_response(334)?.concat();
+        self.send_redacted(&auth::cram_md5(username, password, &challenge)?, REDACTED)?;
+        self.check_response(235)?;
+        return Ok(());
+    }
+
+    fn auth_scram_sha1(&mut self, username: &str, password: &str) -> Result<()> {
+        let mut scram = Scram::new(username);
+        self.send(&format!(
+            "AUTH SCRAM-SHA-1 {}",
+            STANDARD.encode(scram.client_first())
+        ))?;
+        let server_first = decode_challenge(&self.check_response(334)?.concat())?;
+        let client_final = STANDARD.encode(scram.client_final(password, &server_first)?);
+        self.send_redacted(&client_final, REDACTED)?;
+
+        // the server's proof comes as a last challenge or with the success
+        let (num, lines) = self.read_response()?;
+        match num {
+            334 => {
+                scram.verify(&decode_challenge(&lines.concat())?)?;
+                self.send("")?;
+                self.check_response(235)?;
+            }
+            235 => {}
+            _ => bail!("{} {}", num, lines.join(" ")),
+        }
+        return Ok(());
+    }
+
+    /// Aborts any transaction in progress so the connection can send again.
+    pub fn reset(&mut self) -> Result<()> {
+        self.send("RSET")?;
+        self.check_response(250)?;
+        return Ok(());
+    }
+
+    /// Checks the connection is still usable, servers drop idle ones.
+    pub fn noop(&mut self) -> Result<()> {
+        self.send("NOOP")?;
+        self.check_response(250)?;
+        return Ok(());
+    }
+
+    /// Ends the session, the server closes the connection.
+    pub fn quit(mut self) -> Result<()> {
+        self.send("QUIT")?;
+        self.check_response(221)?;
+        return Ok(());
+    }
+
+    /// The largest message the server accepts from the `SIZE` extension, none
+    /// if it isn't advertised or is 0 (no fixed limit).
+    pub fn size_limit(&self) -> Option<usize> {
+        return parse_size(&self.extensions);
+    }
+
+    /// Whether the server takes `8bit` bodies, the `8BITMIME` extension.
+    pub fn eight_bit_mime(&self) -> bool {
+        return self
+            .extensions
+            .iter()
+            .any(|x| x.eq_ignore_ascii_case("8BITMIME"));
+    }
+
+    /// Sends to every recipient the server accepts, returning the ones it
+    /// rejected. Fails if none are accepted.
     pub fn send_email(
         &mut self,
         to: &[&str],
@@ -72,76 +283,374 @@ impl SMTP {
         bcc: Option<&[&str]>,
         subject: &str,
         body: &str,
-    ) -> Result<()> {
-        let username = self.username.as_deref().context("blah")?;
-        write!(self.stream, "MAIL FROM:<{}>\r\n", username)?;
-        self.stream.flush()?;
-        self.check_response(250)?;
-
-        for &recv in to
+    ) -> Result<Vec<Rejected>> {
+        let recipients = to
             .iter()
             .chain(cc.unwrap_or(&[]).iter())
             .chain(bcc.unwrap_or(&[]).iter())
-        {
-            write!(self.stream, "RCPT TO:<{}>\r\n", recv)?;
-            self.stream.flush()?;
-            self.check_response(250)?;
+            .copied()
+            .collect::<Vec<_>>();
+        // Bcc recipients only go in the envelope, a header would show them to
+        // everyone
+        let username = self.username.clone().context("Not logged in")?;
+        let from = mailbox(self.display_name.as_deref(), &username);
+        let message = format_message(&from, to, cc, subject, body, self.eight_bit_mime());
+        return self.send_raw(&recipients, &message);
+    }
+
+    /// Sends an already formatted message, e.g. from `format_message`, to
+    /// `recipients`. One with non ASCII bytes is sent as `8BITMIME` and
+    /// fails if the server doesn't have it.
+    pub fn send_raw(&mut self, recipients: &[&str], message: &str) -> Result<Vec<Rejected>> {
+        let username = self.username.clone().context("Not logged in")?;
+        let payload = dot_stuff(message);
+        let eight_bit = !payload.is_ascii();
+        if eight_bit && !self.eight_bit_mime() {
+            return Err(Refused {
+                reason: "The message has 8bit content but the server doesn't support 8BITMIME"
+                    .into(),
+                permanent: true,
+            }
+            .into());
         }
-        write!(self.stream, "DATA\r\n")?;
-        self.stream.flush()?;
-        self.check_response(354)?;
-        write!(
-            self.stream,
-            "Subject: {}\r\nTo: {}\r\n",
-            subject,
-            to.join(", "),
-        )?;
-        if let Some(cc) = cc {
-            write!(self.stream, "Cc: {}\r\n", cc.join(", "))?
+        self.reset()?;
+        if let Some(limit) = self.size_limit() {
+            if payload.len() > limit {
+                return Err(Refused {
+                    reason: format!(
+                        "Message is {} bytes, the server accepts at most {} bytes",
+                        payload.len(),
+                        limit
+                    )
+                    .into(),
+                    permanent: true,
+                }
+                .into());
+            }
         }
-        if let Some(bcc) = bcc {
-            write!(self.stream, "Bcc: {}\r\n", bcc.join(", "))?
+
+        match eight_bit {
+            true => self.send(&format!("MAIL FROM:<{}> BODY=8BITMIME", username))?,
+            false => self.send(&format!("MAIL FROM:<{}>", username))?,
         }
-        write!(self.stream, "{}\r\n.\r\n", body)?;
-        self.stream.flush()?;
         self.check_response(250)?;
-        return Ok(());
+
+        let mut rejected = vec![];
+        for &recv in recipients {
+            self.send(&format!("RCPT TO:<{}>", recv))?;
+            let (code, lines) = self.read_response()?;
+            // 251 means the server will forward it
+            if code != 250 && code != 251 {
+                rejected.push(Rejected {
+                    address: recv.into(),
+                    code,
+                    reason: lines.join(" ").into(),
+                });
+            }
+        }
+        if rejected.len() == recipients.len() {
+            self.reset()?;
+            let reasons = rejected.iter().map(|x| x.to_string()).collect::<Vec<_>>();
+            return Err(Refused {
+                reason: format!("Every recipient was rejected: {}", reasons.join(", ")).into(),
+                permanent: rejected.iter().all(|x| (500..600).contains(&x.code)),
+            }
+            .into());
+        }
+        self.send("DATA")?;
+        self.check_response(354)?;
+        self.send(&format!("{}\r\n.", payload))?;
+        self.check_response(250)?;
+        return Ok(rejected);
+    }
+}
+
+fn parse_size(extensions: &[Box<str>]) -> Option<usize> {
+    return extensions
+        .iter()
+        .find_map(|x| x.strip_prefix("SIZE "))
+        .and_then(|x| x.trim().parse().ok())
+        .filter(|&x| x > 0);
+}
+
+fn decode_challenge(challenge: &str) -> Result<String> {
+    let challenge = STANDARD
+        .decode(challenge.trim())
+        .context("Invalid base64 challenge")?;
+    return Ok(String::from_utf8(challenge)?);
+}
+
+/// Builds the headers and encoded body of a message with CRLF line endings,
+/// as sent in DATA before dot stuffing.
+/// `from` is the whole header value, see `mailbox`. Unless `eight_bit_mime`
+/// says the server takes `8bit` bodies, see `SMTP::eight_bit_mime`, the
+/// message is kept to ASCII.
+pub fn format_message(
+    from: &str,
+    to: &[&str],
+    cc: Option<&[&str]>,
+    subject: &str,
+    body: &str,
+    eight_bit_mime: bool,
+) -> String {
+    let subject = match subject.is_ascii() {
+        true => subject.to_owned(),
+        false => encode_words(subject),
+    };
+    let mut headers = vec![
+        fold_header("From", from),
+        fold_header("Subject", &subject),
+        fold_header("To", &to.join(", ")),
+    ];
+    if let Some(cc) = cc {
+        headers.push(fold_header("Cc", &cc.join(", ")));
     }
+    let (transfer_encoding, body) = encode_body(body, eight_bit_mime);
+    headers.push("MIME-Version: 1.0".to_owned());
+    // without a charset recipients may guess one and garble non ASCII text
+    headers.push("Content-Type: text/plain; charset=utf-8".to_owned());
+    headers.push(fold_header(
+        "Content-Transfer-Encoding",
+        transfer_encoding.as_str(),
+    ));
+
+    return format!("{}\r\n\r\n{}", headers.join("\r\n"), body);
 }
 
 #[cfg(test)]
 mod test {
-    use dotenv::dotenv;
-
     use super::*;
-    fn connect() -> SMTP {
-        dotenv().ok();
-        let mut smtp = SMTP::connect("smtp.gmail.com", 465).unwrap();
-        smtp.login(
-            std::env::var("EMAIL_USERNAME").unwrap().into(),
-            &std::env::var("EMAIL_PASSWORD").unwrap(),
-        )
-        .unwrap();
-        return smtp;
-    }
-
-    //     #[test]
-    //     fn test_send_email() {
-    //         let mut smtp = connect();
-    //         smtp.send_email(
-    //             &["gip.garbage@gmail.com"],
-    //             None,
-    //             None,
-    //             "Test 2 email from rust",
-    //             r#"-------------START-------------
-    // THIS IS an email
-    //
-    // I like to send
-    // emails
-    //
-    // --------------END_____________
-    // "#,
-    //         )
-    //         .unwrap();
-    //     }
+
+    #[test]
+    fn test_parse_size() {
+        let extensions: Vec<Box<str>> = vec!["8BITMIME".into(), "SIZE 35882577".into()];
+        assert_eq!(parse_size(&extensions), Some(35882577));
+        assert_eq!(parse_size(&["SIZE 0".into()]), None);
+        assert_eq!(parse_size(&["SIZE".into()]), None);
+    }
+
+    #[test]
+    fn test_format_message_content_headers() {
+        let headers = |body| {
+            let message = format_message("a@x.com", &["b@x.com"], None, "Hi", body, true);
+            let (headers, _) = message.split_once("\r\n\r\n").unwrap();
+            return headers.to_owned();
+        };
+        let plain = headers("hello");
+        assert!(plain.contains("\r\nContent-Type: text/plain; charset=utf-8\r\n"));
+        assert!(plain.ends_with("\r\nContent-Transfer-Encoding: 7bit"));
+        let accented = headers("héllo");
+        assert!(accented.contains("\r\nContent-Type: text/plain; charset=utf-8\r\n"));
+        assert!(accented.ends_with("\r\nContent-Transfer-Encoding: 8bit"));
+        let long = headers(&format!("{}é", "a".repeat(1000)));
+        assert!(long.ends_with("\r\nContent-Transfer-Encoding: quoted-printable"));
+
+        let ascii_only =
+            format_message("a@x.com", &["b@x.com"], None, "Café", "café au lait", false);
+        assert!(ascii_only.is_ascii());
+        assert!(ascii_only.contains("\r\nSubject: =?UTF-8?B?Q2Fmw6k=?=\r\n"));
+        assert!(ascii_only.contains("\r\nContent-Transfer-Encoding: quoted-printable\r\n"));
+    }
+
+    #[test]
+    fn test_mock_send_8bitmime() {
+        let mut smtp = mock(&[
+            (
+                "EHLO x.com\r\n",
+                "250-smtp.x.com\r\n250-8BITMIME\r\n250 AUTH LOGIN\r\n",
+            ),
+            ("AUTH LOGIN\r\n", "334 VXNlcm5hbWU6\r\n"),
+            ("YUB4LmNvbQ==\r\n", "334 UGFzc3dvcmQ6\r\n"),
+            ("cHc=\r\n", "235 2.7.0 Accepted\r\n"),
+            ("RSET\r\n", "250 OK\r\n"),
+            ("MAIL FROM:<a@x.com> BODY=8BITMIME\r\n", "250 OK\r\n"),
+            ("RCPT TO:<b@x.com>\r\n", "250 OK\r\n"),
+            ("DATA\r\n", "354 Go ahead\r\n"),
+            (
+                "From: a@x.com\r\nSubject: Hi\r\nTo: b@x.com\r\nMIME-Version: 1.0\r\n\
+                 Content-Type: text/plain; charset=utf-8\r\n\
+                 Content-Transfer-Encoding: 8bit\r\n\r\nhéllo\r\n.\r\n",
+                "250 2.0.0 OK queued\r\n",
+            ),
+        ]);
+        smtp.login("a@x.com".into(), "pw").unwrap();
+        let rejected = smtp.send_email(&["b@x.com"], None, None, "Hi", "héllo");
+        assert!(rejected.unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_mock_send_8bit_unsupported() {
+        let mut smtp = mock(&[
+            ("EHLO x.com\r\n", "250-smtp.x.com\r\n250 AUTH LOGIN\r\n"),
+            ("AUTH LOGIN\r\n", "334 VXNlcm5hbWU6\r\n"),
+            ("YUB4LmNvbQ==\r\n", "334 UGFzc3dvcmQ6\r\n"),
+            ("cHc=\r\n", "235 2.7.0 Accepted\r\n"),
+        ]);
+        smtp.login("a@x.com".into(), "pw").unwrap();
+        let error = smtp
+            .send_raw(&["b@x.com"], "Subject: é\r\n\r\nhi")
+            .unwrap_err();
+        assert!(error.downcast_ref::<Refused>().unwrap().permanent);
+    }
+
+    #[test]
+    fn test_mock_send_all_rejected() {
+        let mut smtp = mock(&[
+            ("EHLO x.com\r\n", "250-smtp.x.com\r\n250 AUTH LOGIN\r\n"),
+            ("AUTH LOGIN\r\n", "334 VXNlcm5hbWU6\r\n"),
+            ("YUB4LmNvbQ==\r\n", "334 UGFzc3dvcmQ6\r\n"),
+            ("cHc=\r\n", "235 2.7.0 Accepted\r\n"),
+            ("RSET\r\n", "250 OK\r\n"),
+            ("MAIL FROM:<a@x.com>\r\n", "250 OK\r\n"),
+            ("RCPT TO:<b@x.com>\r\n", "450 4.2.1 Try later\r\n"),
+            ("RCPT TO:<c@x.com>\r\n", "550 5.1.1 No such user\r\n"),
+            ("RSET\r\n", "250 OK\r\n"),
+            ("RSET\r\n", "250 OK\r\n"),
+            ("MAIL FROM:<a@x.com>\r\n", "250 OK\r\n"),
+            ("RCPT TO:<c@x.com>\r\n", "550 5.1.1 No such user\r\n"),
+            ("RSET\r\n", "250 OK\r\n"),
+        ]);
+        smtp.login("a@x.com".into(), "pw").unwrap();
+        let message = "Subject: Hi\r\n\r\nhi";
+        // b@x.com may take it later
+        let error = smtp.send_raw(&["b@x.com", "c@x.com"], message).unwrap_err();
+        assert!(!error.downcast_ref::<Refused>().unwrap().permanent);
+        let error = smtp.send_raw(&["c@x.com"], message).unwrap_err();
+        assert!(error.downcast_ref::<Refused>().unwrap().permanent);
+    }
+
+    fn mock(script: &[(&str, &str)]) -> SMTP {
+        let stream = mock::MockStream::new("220 smtp.x.com ready\r\n", script);
+        return SMTP::from_stream(Box::new(stream)).unwrap();
+    }
+
+    #[test]
+    fn test_trace_redacts_password() {
+        use std::sync::{Arc, Mutex};
+        let mut smtp = mock(&[
+            ("EHLO x.com\r\n", "250-smtp.x.com\r\n250 AUTH LOGIN\r\n"),
+            ("AUTH LOGIN\r\n", "334 VXNlcm5hbWU6\r\n"),
+            ("YUB4LmNvbQ==\r\n", "334 UGFzc3dvcmQ6\r\n"),
+            ("cHc=\r\n", "235 2.7.0 Accepted\r\n"),
+        ]);
+        let lines = Arc::new(Mutex::new(vec![]));
+        let sink = lines.clone();
+        smtp.set_trace(move |line| sink.lock().unwrap().push(line.to_owned()));
+        smtp.login("a@x.com".into(), "pw").unwrap();
+        let lines = lines.lock().unwrap();
+        assert_eq!(lines[0], "C: EHLO x.com");
+        assert_eq!(lines[1], "S: 250-smtp.x.com");
+        assert!(lines.contains(&"C: <redacted>".to_owned()));
+        assert!(!lines.contains(&"C: cHc=".to_owned()));
+    }
+
+    #[test]
+    fn test_auth_error_redacted() {
+        let mut smtp = mock(&[
+            ("EHLO x.com\r\n", "250-smtp.x.com\r\n250 AUTH LOGIN\r\n"),
+            ("AUTH LOGIN\r\n", "334 VXNlcm5hbWU6\r\n"),
+            ("YUB4LmNvbQ==\r\n", "334 UGFzc3dvcmQ6\r\n"),
+            ("cHc=\r\n", "535 5.7.8 Bad credentials cHc=\r\n"),
+        ]);
+        let error = smtp.login("a@x.com".into(), "pw").unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "Authentication failed: 535 5.7.8 Bad credentials <redacted>"
+        );
+    }
+
+    #[test]
+    fn test_mock_bcc_not_in_headers() {
+        let mut smtp = mock(&[
+            ("EHLO x.com\r\n", "250-smtp.x.com\r\n250 AUTH LOGIN\r\n"),
+            ("AUTH LOGIN\r\n", "334 VXNlcm5hbWU6\r\n"),
+            ("YUB4LmNvbQ==\r\n", "334 UGFzc3dvcmQ6\r\n"),
+            ("cHc=\r\n", "235 2.7.0 Accepted\r\n"),
+            ("RSET\r\n", "250 OK\r\n"),
+            ("MAIL FROM:<a@x.com>\r\n", "250 OK\r\n"),
+            ("RCPT TO:<b@x.com>\r\n", "250 OK\r\n"),
+            ("RCPT TO:<secret@x.com>\r\n", "250 OK\r\n"),
+            ("DATA\r\n", "354 Go ahead\r\n"),
+            (
+                "From: Ann <a@x.com>\r\nSubject: Hi\r\nTo: b@x.com\r\nMIME-Version: 1.0\r\n\
+                 Content-Type: text/plain; charset=utf-8\r\n\
+                 Content-Transfer-Encoding: 7bit\r\n\r\nhello\r\n.\r\n",
+                "250 2.0.0 OK queued\r\n",
+            ),
+        ]);
+        smtp.login("a@x.com".into(), "pw").unwrap();
+        smtp.set_display_name("Ann");
+        let rejected = smtp
+            .send_email(&["b@x.com"], None, Some(&["secret@x.com"]), "Hi", "hello")
+            .unwrap();
+        assert!(rejected.is_empty());
+    }
+
+    #[test]
+    fn test_mock_multiline_greeting() {
+        // the EHLO reply would be read as the greeting's second line if
+        // connecting stopped after the first
+        let stream = mock::MockStream::new(
+            "220-smtp.x.com ESMTP\r\n220 no spam please\r\n",
+            &[
+                (
+                    "EHLO x.com\r\n",
+                    "250-smtp.x.com\r\n250-8BITMIME\r\n250-AUTH PLAIN\r\n250 SASL-IR\r\n",
+                ),
+                ("AUTH PLAIN AGFAeC5jb20AcHc=\r\n", "235 OK\r\n"),
+            ],
+        );
+        let mut smtp = SMTP::from_stream(Box::new(stream)).unwrap();
+        smtp.login("a@x.com".into(), "pw").unwrap();
+        assert_eq!(
+            smtp.extensions,
+            ["8BITMIME".into(), "AUTH PLAIN".into(), "SASL-IR".into()] as [Box<str>; 3]
+        );
+    }
+
+    #[test]
+    fn test_mock_login_and_send() {
+        let mut smtp = mock(&[
+            (
+                "EHLO x.com\r\n",
+                "250-smtp.x.com at your service\r\n250-SIZE 35882577\r\n250 AUTH LOGIN\r\n",
+            ),
+            ("AUTH LOGIN\r\n", "334 VXNlcm5hbWU6\r\n"),
+            ("YUB4LmNvbQ==\r\n", "334 UGFzc3dvcmQ6\r\n"),
+            ("cHc=\r\n", "235 2.7.0 Accepted\r\n"),
+            ("RSET\r\n", "250 OK\r\n"),
+            ("MAIL FROM:<a@x.com>\r\n", "250 OK\r\n"),
+            ("RCPT TO:<b@x.com>\r\n", "250 OK\r\n"),
+            ("RCPT TO:<gone@x.com>\r\n", "550 5.1.1 No such user\r\n"),
+            ("DATA\r\n", "354 Go ahead\r\n"),
+            (
+                "From: a@x.com\r\nSubject: Hi\r\nTo: b@x.com, gone@x.com\r\nMIME-Version: 1.0\r\n\
+                 Content-Type: text/plain; charset=utf-8\r\n\
+                 Content-Transfer-Encoding: 7bit\r\n\r\nfirst\r\n..\r\n..last\r\n.\r\n",
+                "250 2.0.0 OK queued\r\n",
+            ),
+            ("NOOP\r\n", "250 2.0.0 OK\r\n"),
+        ]);
+        smtp.login("a@x.com".into(), "pw").unwrap();
+        assert_eq!(smtp.size_limit(), Some(35882577));
+
+        let rejected = smtp
+            .send_email(
+                &["b@x.com", "gone@x.com"],
+                None,
+                None,
+                "Hi",
+                "first\n.\n.last",
+            )
+            .unwrap();
+        assert_eq!(
+            rejected,
+            [Rejected {
+                address: "gone@x.com".into(),
+                code: 550,
+                reason: "5.1.1 No such user".into(),
+            }]
+        );
+        smtp.noop().unwrap();
+    }
 }
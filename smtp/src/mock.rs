@@ -0,0 +1,54 @@
+use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+/// An in-memory server for tests that plays back a recorded dialog. The
+/// bytes written between flushes must match the next expected chunk exactly,
+/// its canned reply is then made available to read.
+pub(crate) struct MockStream {
+    script: VecDeque<(String, String)>,
+    output: VecDeque<u8>,
+    written: Vec<u8>,
+}
+
+impl MockStream {
+    /// `script` pairs the exact bytes of each command, CRLF included, with
+    /// the server's reply.
+    pub fn new(greeting: &str, script: &[(&str, &str)]) -> Self {
+        return Self {
+            script: script
+                .iter()
+                .map(|(cmd, reply)| (cmd.to_string(), reply.to_string()))
+                .collect(),
+            output: greeting.bytes().collect(),
+            written: vec![],
+        };
+    }
+}
+
+impl Read for MockStream {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        return self.output.read(buf);
+    }
+}
+
+impl Write for MockStream {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.written.extend_from_slice(buf);
+        return Ok(buf.len());
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        if self.written.is_empty() {
+            return Ok(());
+        }
+        let written = String::from_utf8_lossy(&self.written).into_owned();
+        self.written.clear();
+        let (expected, reply) = self
+            .script
+            .pop_front()
+            .unwrap_or_else(|| panic!("Unexpected command {:?}", written));
+        assert_eq!(written, expected);
+        self.output.extend(reply.bytes());
+        return Ok(());
+    }
+}
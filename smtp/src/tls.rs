@@ -0,0 +1,41 @@
+use anyhow::Result;
+use std::{
+    io::{Read, Write},
+    net::TcpStream,
+};
+
+#[cfg(not(any(feature = "openssl", feature = "rustls")))]
+compile_error!("either the `openssl` or the `rustls` feature must be enabled");
+
+/// Any byte stream the client can talk SMTP over, lets the rest of the crate
+/// stay agnostic of the TLS backend.
+pub trait Stream: Read + Write + Send {}
+impl<T: Read + Write + Send> Stream for T {}
+
+#[cfg(not(feature = "rustls"))]
+pub(crate) fn wrap(domain: &str, stream: TcpStream) -> Result<Box<dyn Stream>> {
+    use openssl::ssl::{SslConnector, SslMethod};
+
+    let connector = SslConnector::builder(SslMethod::tls())?.build();
+    return Ok(Box::new(connector.connect(domain, stream)?));
+}
+
+#[cfg(feature = "rustls")]
+pub(crate) fn wrap(domain: &str, stream: TcpStream) -> Result<Box<dyn Stream>> {
+    use rustls::{
+        pki_types::ServerName, ClientConfig, ClientConnection, RootCertStore, StreamOwned,
+    };
+    use std::sync::Arc;
+
+    let mut roots = RootCertStore::empty();
+    roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned());
+    let provider = Arc::new(rustls::crypto::ring::default_provider());
+    let config = ClientConfig::builder_with_provider(provider)
+        .with_safe_default_protocol_versions()?
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+
+    let server_name = ServerName::try_from(domain.to_owned())?;
+    let connection = ClientConnection::new(Arc::new(config), server_name)?;
+    return Ok(Box::new(StreamOwned::new(connection, stream)));
+}
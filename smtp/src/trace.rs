@@ -0,0 +1,40 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+
+/// Gets every line sent to the server prefixed with `C: ` and every line
+/// received prefixed with `S: `. Credentials sent while authenticating are
+/// redacted by the caller. Set with `SMTP::set_trace`.
+#[derive(Clone)]
+pub(crate) struct Trace(Arc<dyn Fn(&str) + Send + Sync>);
+
+impl Trace {
+    pub fn new(callback: impl Fn(&str) + Send + Sync + 'static) -> Self {
+        return Self(Arc::new(callback));
+    }
+
+    pub fn sent(&self, text: &str) {
+        for line in text.split("\r\n") {
+            (self.0)(&format!("C: {}", line));
+        }
+    }
+
+    pub fn received(&self, line: &str) {
+        (self.0)(&format!("S: {}", line.trim_end_matches(['\r', '\n'])));
+    }
+}
+
+impl Debug for Trace {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        return write!(f, "Trace");
+    }
+}
+
+/// `text` with every non-empty secret masked, for errors that might echo a
+/// command back.
+pub(crate) fn redact_secrets(text: &str, secrets: &[&str]) -> String {
+    let mut text = text.to_owned();
+    for secret in secrets.iter().filter(|x| !x.is_empty()) {
+        text = text.replace(secret, "<redacted>");
+    }
+    return text;
+}